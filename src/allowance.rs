@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol_types::{SolCall, SolValue};
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::encoding::{IERC20, InteractionBatch, MultitradeCall, decode_multitrade_calldata};
+
+/// ERC20 allowances granted by the executor contract, keyed by `(token, spender)`. Entries
+/// are read once and dropped after each of our own trades that spends or sets them.
+#[derive(Debug, Default)]
+pub struct AllowanceCache {
+    entries: Mutex<HashMap<(Address, Address), U256>>,
+}
+
+impl AllowanceCache {
+    pub async fn allowance(
+        &self,
+        provider: &impl Provider,
+        token: Address,
+        owner: Address,
+        spender: Address,
+    ) -> Result<U256> {
+        if let Some(allowance) = self.cached(token, spender) {
+            return Ok(allowance);
+        }
+
+        let tx = TransactionRequest::default()
+            .to(token)
+            .input(IERC20::allowanceCall { owner, spender }.abi_encode().into());
+        let output = provider.call(tx).await.context("allowance call failed")?;
+        let allowance = U256::abi_decode(&output).context("allowance returned no uint256")?;
+        self.entries
+            .lock()
+            .expect("allowance cache lock poisoned")
+            .insert((token, spender), allowance);
+
+        Ok(allowance)
+    }
+
+    /// Drops every allowance `batch` spends or sets: the sold token's, whoever pulls it,
+    /// and each `(token, spender)` it approves.
+    pub fn invalidate_touched(&self, batch: &MultitradeCall) {
+        let approved: Vec<(Address, Address)> = batch
+            .interactions
+            .iter()
+            .filter_map(|interaction| {
+                IERC20::approveCall::abi_decode(&interaction.callData)
+                    .ok()
+                    .map(|approve| (interaction.target, approve.spender))
+            })
+            .collect();
+        self.entries
+            .lock()
+            .expect("allowance cache lock poisoned")
+            .retain(|(token, spender), _| {
+                *token != batch.token_address && !approved.contains(&(*token, *spender))
+            });
+    }
+
+    fn cached(&self, token: Address, spender: Address) -> Option<U256> {
+        self.entries
+            .lock()
+            .expect("allowance cache lock poisoned")
+            .get(&(token, spender))
+            .copied()
+    }
+
+    /// Removes the approvals from an `executeInteractions` transaction whose spender is
+    /// already allowed `amount`, saving their gas. The transaction is returned unchanged
+    /// when it has no approval or the allowance can't be read.
+    pub async fn skip_covered_approvals(
+        &self,
+        provider: &impl Provider,
+        tx: TransactionRequest,
+        amount: U256,
+    ) -> TransactionRequest {
+        let (Some(calldata), Some(owner)) =
+            (tx.input.input().cloned(), tx.to.and_then(|to| to.to().copied()))
+        else {
+            return tx;
+        };
+        let Ok(call) = decode_multitrade_calldata(&calldata) else {
+            return tx;
+        };
+        let token = call.token_address;
+        let Some(spender) = call.interactions.iter().find_map(|interaction| {
+            (interaction.target == token)
+                .then(|| IERC20::approveCall::abi_decode(&interaction.callData).ok())
+                .flatten()
+                .map(|approve| approve.spender)
+        }) else {
+            return tx;
+        };
+
+        match self.allowance(provider, token, owner, spender).await {
+            Ok(allowance) if allowance >= amount => {
+                debug!(%token, %spender, %allowance, "Allowance covers the trade, skipping approve");
+                let mode = call.mode;
+                let calldata = InteractionBatch::from(call)
+                    .without_approvals(token, spender)
+                    .build(token, mode);
+                tx.input(Bytes::from(calldata).into())
+            }
+            Ok(_) => tx,
+            Err(e) => {
+                debug!(%token, error = %e, "Can't read allowance, keeping approve");
+                tx
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::providers::ProviderBuilder;
+    use alloy::transports::mock::Asserter;
+
+    use super::*;
+    use crate::encoding::{ApproveStrategy, ExecutionMode, create_multitrade_calldata};
+
+    const TOKEN: Address = Address::repeat_byte(0x11);
+    const OTHER_TOKEN: Address = Address::repeat_byte(0x12);
+    const ROUTER: Address = Address::repeat_byte(0x22);
+    const EXECUTOR: Address = Address::repeat_byte(0x33);
+
+    fn allowance_response(amount: u64) -> Bytes {
+        Bytes::from(U256::from(amount).abi_encode())
+    }
+
+    fn trade(strategy: ApproveStrategy, amount: u64) -> TransactionRequest {
+        let calldata = create_multitrade_calldata(
+            TOKEN,
+            ROUTER,
+            Some((ROUTER, U256::from(amount))),
+            strategy,
+            vec![0xab; 4],
+            U256::ZERO,
+            ExecutionMode::Test,
+        );
+        TransactionRequest::default()
+            .to(EXECUTOR)
+            .input(Bytes::from(calldata).into())
+    }
+
+    /// Amounts approved by `tx`, and how many interactions it makes in total.
+    fn approvals(tx: &TransactionRequest) -> (Vec<U256>, usize) {
+        let call = decode_multitrade_calldata(tx.input.input().unwrap()).unwrap();
+        let approved = call
+            .interactions
+            .iter()
+            .filter_map(|interaction| IERC20::approveCall::abi_decode(&interaction.callData).ok())
+            .map(|approve| approve.amount)
+            .collect();
+        (approved, call.interactions.len())
+    }
+
+    #[tokio::test]
+    async fn each_strategy_approves_as_configured_and_is_skipped_once_covered() {
+        let amount = 1_000u64;
+        let cases = [
+            (ApproveStrategy::Exact, vec![U256::from(amount)]),
+            (ApproveStrategy::Unlimited, vec![U256::MAX]),
+            (ApproveStrategy::Reset, vec![U256::ZERO, U256::from(amount)]),
+        ];
+
+        for (strategy, expected) in cases {
+            let tx = trade(strategy, amount);
+            assert_eq!(approvals(&tx), (expected.clone(), expected.len() + 1), "{strategy:?}");
+
+            // Short of the amount: every approval stays
+            let asserter = Asserter::new();
+            asserter.push_success(&allowance_response(amount - 1));
+            let provider = ProviderBuilder::new().connect_mocked_client(asserter);
+            let kept = AllowanceCache::default()
+                .skip_covered_approvals(&provider, tx.clone(), U256::from(amount))
+                .await;
+            assert_eq!(approvals(&kept), (expected.clone(), expected.len() + 1), "{strategy:?}");
+
+            // Covered: only the router call is left
+            let asserter = Asserter::new();
+            asserter.push_success(&allowance_response(amount));
+            let provider = ProviderBuilder::new().connect_mocked_client(asserter);
+            let trimmed = AllowanceCache::default()
+                .skip_covered_approvals(&provider, tx, U256::from(amount))
+                .await;
+            assert_eq!(approvals(&trimmed), (vec![], 1), "{strategy:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn our_own_trades_invalidate_the_allowances_they_touch() {
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let cache = AllowanceCache::default();
+        asserter.push_success(&allowance_response(5_000));
+        asserter.push_success(&allowance_response(7_000));
+        asserter.push_success(&allowance_response(9_000));
+
+        assert_eq!(cache.allowance(&provider, TOKEN, EXECUTOR, ROUTER).await.unwrap(), U256::from(5_000));
+        assert_eq!(cache.allowance(&provider, OTHER_TOKEN, EXECUTOR, ROUTER).await.unwrap(), U256::from(7_000));
+        // Served from the cache, no request made
+        assert_eq!(cache.allowance(&provider, TOKEN, EXECUTOR, ROUTER).await.unwrap(), U256::from(5_000));
+
+        let sent = decode_multitrade_calldata(
+            trade(ApproveStrategy::Exact, 1_000).input.input().unwrap(),
+        )
+        .unwrap();
+        cache.invalidate_touched(&sent);
+
+        // TOKEN is read again, OTHER_TOKEN's allowance wasn't touched
+        assert_eq!(cache.allowance(&provider, TOKEN, EXECUTOR, ROUTER).await.unwrap(), U256::from(9_000));
+        assert_eq!(cache.allowance(&provider, OTHER_TOKEN, EXECUTOR, ROUTER).await.unwrap(), U256::from(7_000));
+    }
+}
@@ -0,0 +1,418 @@
+use std::cell::Cell;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use num_bigint::{BigInt, BigUint};
+use num_traits::ToPrimitive;
+use tracing::{debug, info};
+use tycho_simulation::protocol::models::ProtocolComponent;
+use tycho_simulation::tycho_common::models::token::Token;
+use tycho_simulation::tycho_core::simulation::protocol_sim::ProtocolSim;
+
+/// Sizes tried on the geometric ladder before refining around the best one.
+const LADDER_POINTS: u32 = 10;
+/// Upper bound on `get_amount_out` calls per search, every size costs a forward and a reverse quote.
+const MAX_SEARCH_QUOTES: usize = 40;
+/// Smallest size the search starts from when `optimal` has no explicit minimum.
+const DEFAULT_SEARCH_MIN: u64 = 1_000;
+/// 1 / golden ratio, scaled to thousandths.
+const GOLDEN_RATIO_PERMILLE: u32 = 618;
+/// Share of the pool's sell limit a trade may use, quotes right at the limit are unreliable.
+const LIMIT_USAGE_BPS: u32 = 9_000;
+
+/// How much of the sell token to offer on each quote.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmountStrategy {
+    /// Always trade the given amount, in base units.
+    Fixed(BigUint),
+    /// Trade a fraction of the most the pool can take for this direction, as reported by
+    /// `get_limits`. Components carry no TVL, so this is the closest liquidity measure.
+    FractionOfLimit(f64),
+    /// Search between a minimum size in base units and the pool limit for the amount
+    /// maximizing the round-trip profit.
+    OptimalForProfit { min_amount: BigUint },
+}
+
+/// Best size found by `search_trade_size` with its quote and round-trip profit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeSize {
+    pub amount_in: BigUint,
+    pub amount_out: BigUint,
+    /// Gas of the `amount_in -> amount_out` quote.
+    pub gas: BigUint,
+    pub expected_profit: BigInt,
+}
+
+impl FromStr for AmountStrategy {
+    type Err = anyhow::Error;
+
+    /// Parses `fixed:<base units>`, `fraction_of_limit:<0..1>` or `optimal[:<min base units>]`.
+    fn from_str(raw: &str) -> Result<Self> {
+        let raw = raw.trim();
+        let (kind, value) = raw.split_once(':').unwrap_or((raw, ""));
+
+        match kind.to_lowercase().as_str() {
+            "fixed" => Ok(Self::Fixed(
+                BigUint::from_str(value.trim()).context("Fixed amount must be an integer")?,
+            )),
+            "fraction_of_limit" => {
+                let fraction: f64 = value.trim().parse().context("Fraction must be a number")?;
+                if !(f64::EPSILON..=1.0).contains(&fraction) {
+                    bail!("Fraction of the pool limit must be in (0, 1], got {}", fraction);
+                }
+                Ok(Self::FractionOfLimit(fraction))
+            }
+            "optimal" => {
+                let min_amount = match value.trim() {
+                    "" => BigUint::from(DEFAULT_SEARCH_MIN),
+                    min => BigUint::from_str(min).context("Optimal minimum must be an integer")?,
+                };
+                if min_amount == BigUint::ZERO {
+                    bail!("Optimal minimum must be above zero");
+                }
+                Ok(Self::OptimalForProfit { min_amount })
+            }
+            other => bail!(
+                "Unknown amount strategy {other:?}, expected fixed:<amount>, fraction_of_limit:<fraction> or optimal[:<min>]"
+            ),
+        }
+    }
+}
+
+pub fn compute_amount_in(
+    component: &ProtocolComponent,
+    state: &dyn ProtocolSim,
+    sell_token: &Token,
+    buy_token: &Token,
+    strategy: &AmountStrategy,
+) -> BigUint {
+    match strategy {
+        AmountStrategy::Fixed(amount) => amount.clone(),
+        AmountStrategy::FractionOfLimit(fraction) => {
+            let Some(limit) = sell_limit(component, state, sell_token, buy_token) else {
+                return BigUint::ZERO;
+            };
+            let scale = 1_000_000u64;
+            limit * BigUint::from((fraction * scale as f64) as u64) / BigUint::from(scale)
+        }
+        AmountStrategy::OptimalForProfit { min_amount } => {
+            optimal_trade(component, state, sell_token, buy_token, min_amount)
+                .map(|trade| trade.amount_in)
+                .unwrap_or_default()
+        }
+    }
+}
+
+/// The size `OptimalForProfit` settles on, searched up to the share of the pool limit a
+/// trade may use, with the quote for it.
+pub fn optimal_trade(
+    component: &ProtocolComponent,
+    state: &dyn ProtocolSim,
+    sell_token: &Token,
+    buy_token: &Token,
+    min_amount: &BigUint,
+) -> Option<TradeSize> {
+    let limit = sell_limit(component, state, sell_token, buy_token)?;
+    let usable = limit * BigUint::from(LIMIT_USAGE_BPS) / BigUint::from(10_000u32);
+    let trade = search_trade_size(state, state, sell_token, buy_token, min_amount, &usable)?;
+    debug!(
+        component = %component.id,
+        amount_in = %trade.amount_in,
+        amount_out = %trade.amount_out,
+        expected_profit = %trade.expected_profit,
+        "Optimal trade size"
+    );
+    Some(trade)
+}
+
+/// Caps `amount_in` at 90% of what the pool can take for this direction. Returns `None` when
+/// the limit is at or below `dust`, and the amount unchanged when the protocol can't report
+/// limits.
+pub fn clamp_to_limits(
+    component: &ProtocolComponent,
+    state: &dyn ProtocolSim,
+    sell_token: &Token,
+    buy_token: &Token,
+    amount_in: BigUint,
+    dust: &BigUint,
+) -> Option<BigUint> {
+    let Some(limit) = sell_limit(component, state, sell_token, buy_token) else {
+        return Some(amount_in);
+    };
+    if &limit <= dust {
+        debug!(component = %component.id, %limit, "Pool limit is below POOL_LIMIT_DUST, skipping");
+        return None;
+    }
+
+    let max_amount = limit * BigUint::from(LIMIT_USAGE_BPS) / BigUint::from(10_000u32);
+    if amount_in > max_amount {
+        info!(
+            component = %component.id,
+            requested = %amount_in,
+            clamped = %max_amount,
+            "✂️ Clamping amount_in to the pool limit"
+        );
+        return Some(max_amount);
+    }
+
+    Some(amount_in)
+}
+
+fn sell_limit(
+    component: &ProtocolComponent,
+    state: &dyn ProtocolSim,
+    sell_token: &Token,
+    buy_token: &Token,
+) -> Option<BigUint> {
+    match state.get_limits(sell_token.address.clone(), buy_token.address.clone()) {
+        Ok((max_sell, _)) => Some(max_sell),
+        Err(e) => {
+            debug!(component = %component.id, error = %e, "Can't read pool limits");
+            None
+        }
+    }
+}
+
+/// Quotes a geometric ladder of sizes between `min_amount` and `limit`, then narrows in
+/// on the best rung with a golden-section search. The round trip buys on `state` and sells
+/// back on `exit`, keeping the profit in one token, and at most `MAX_SEARCH_QUOTES`
+/// quotes are spent.
+pub fn search_trade_size(
+    state: &dyn ProtocolSim,
+    exit: &dyn ProtocolSim,
+    sell_token: &Token,
+    buy_token: &Token,
+    min_amount: &BigUint,
+    limit: &BigUint,
+) -> Option<TradeSize> {
+    if min_amount > limit {
+        return None;
+    }
+
+    let quotes = Cell::new(0usize);
+    let evaluate = |amount: &BigUint| -> Option<TradeSize> {
+        if quotes.get() + 2 > MAX_SEARCH_QUOTES {
+            return None;
+        }
+        quotes.set(quotes.get() + 2);
+        let quote = state
+            .get_amount_out(amount.clone(), sell_token, buy_token)
+            .ok()?;
+        let round_trip_out = exit
+            .get_amount_out(quote.amount.clone(), buy_token, sell_token)
+            .ok()?
+            .amount;
+        Some(TradeSize {
+            amount_in: amount.clone(),
+            amount_out: quote.amount,
+            gas: quote.gas,
+            expected_profit: BigInt::from(round_trip_out) - BigInt::from(amount.clone()),
+        })
+    };
+
+    let ladder = geometric_ladder(min_amount, limit);
+    let mut best: Option<(usize, TradeSize)> = None;
+    for (index, amount) in ladder.iter().enumerate() {
+        if let Some(trade) = evaluate(amount)
+            && best
+                .as_ref()
+                .is_none_or(|(_, current)| trade.expected_profit > current.expected_profit)
+        {
+            best = Some((index, trade));
+        }
+    }
+    let (best_index, mut best) = best?;
+
+    // The optimum sits between the rungs around the best one
+    let mut low = ladder[best_index.saturating_sub(1)].clone();
+    let mut high = ladder[(best_index + 1).min(ladder.len() - 1)].clone();
+    while high > &low + BigUint::from(2u32) {
+        let step = (&high - &low) * BigUint::from(GOLDEN_RATIO_PERMILLE) / BigUint::from(1_000u32);
+        let left = &high - &step;
+        let right = &low + &step;
+        let (Some(left_trade), Some(right_trade)) = (evaluate(&left), evaluate(&right)) else {
+            break;
+        };
+
+        if left_trade.expected_profit < right_trade.expected_profit {
+            low = left;
+            if right_trade.expected_profit > best.expected_profit {
+                best = right_trade;
+            }
+        } else {
+            high = right;
+            if left_trade.expected_profit > best.expected_profit {
+                best = left_trade;
+            }
+        }
+    }
+
+    Some(best)
+}
+
+/// Smallest sell amount `state` turns into at least `amount_out` of `buy_token`, by
+/// bisecting up to the pool's sell limit. `None` when even the limit falls short.
+pub fn amount_in_for(
+    state: &dyn ProtocolSim,
+    sell_token: &Token,
+    buy_token: &Token,
+    amount_out: &BigUint,
+) -> Option<BigUint> {
+    let reaches = |amount: &BigUint| {
+        state
+            .get_amount_out(amount.clone(), sell_token, buy_token)
+            .is_ok_and(|quote| &quote.amount >= amount_out)
+    };
+    let (mut high, _) = state
+        .get_limits(sell_token.address.clone(), buy_token.address.clone())
+        .ok()?;
+    if !reaches(&high) {
+        return None;
+    }
+
+    let mut low = BigUint::ZERO;
+    while &high - &low > BigUint::from(1u32) {
+        let middle = (&low + &high) / 2u32;
+        if reaches(&middle) {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+    Some(high)
+}
+
+/// `LADDER_POINTS` sizes from `min` to `max`, each a constant factor above the previous.
+fn geometric_ladder(min: &BigUint, max: &BigUint) -> Vec<BigUint> {
+    let ratio = (max.to_f64().unwrap_or(f64::MAX) / min.to_f64().unwrap_or(1.0))
+        .powf(1.0 / f64::from(LADDER_POINTS - 1));
+
+    let mut ladder: Vec<BigUint> = (0..LADDER_POINTS)
+        .map(|step| {
+            let factor = ratio.powi(step as i32);
+            let amount = min.to_f64().unwrap_or(1.0) * factor;
+            BigUint::from(amount as u128).clamp(min.clone(), max.clone())
+        })
+        .collect();
+    ladder.dedup();
+    ladder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{component, pool, token};
+
+    #[test]
+    fn parses_every_strategy() {
+        assert_eq!(
+            "fixed:1000".parse::<AmountStrategy>().unwrap(),
+            AmountStrategy::Fixed(BigUint::from(1_000u32))
+        );
+        assert_eq!(
+            "fraction_of_limit:0.25".parse::<AmountStrategy>().unwrap(),
+            AmountStrategy::FractionOfLimit(0.25)
+        );
+        assert_eq!(
+            "optimal".parse::<AmountStrategy>().unwrap(),
+            AmountStrategy::OptimalForProfit {
+                min_amount: BigUint::from(DEFAULT_SEARCH_MIN)
+            }
+        );
+        assert!("fraction_of_limit:1.5".parse::<AmountStrategy>().is_err());
+        assert!("fraction_of_tvl:0.5".parse::<AmountStrategy>().is_err());
+    }
+
+    #[test]
+    fn fraction_of_limit_scales_the_pool_sell_limit() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let weth = token(0xc0, "WETH", 18);
+        let state = pool(1_000 * 10u128.pow(8), 30_000 * 10u128.pow(18));
+        let (limit, _) = state
+            .get_limits(wbtc.address.clone(), weth.address.clone())
+            .unwrap();
+
+        let amount = compute_amount_in(
+            &component("pool", &[wbtc.clone(), weth.clone()]),
+            &state,
+            &wbtc,
+            &weth,
+            &AmountStrategy::FractionOfLimit(0.5),
+        );
+
+        assert_eq!(amount, limit / 2u32);
+    }
+
+    #[test]
+    fn search_converges_on_the_peak_of_a_concave_payoff() {
+        const ONE: u128 = 1_000_000_000_000_000_000;
+        let aaa = token(0x11, "AAA", 18);
+        let bbb = token(0x22, "BBB", 18);
+        // BBB is cheaper to buy on the entry pool than it sells for on the exit one, and
+        // price impact on both turns the profit back down past some size
+        let entry = pool(1_000 * ONE, 2_000 * ONE);
+        let exit = pool(1_000 * ONE, 1_800 * ONE);
+        let profit = |amount: &BigUint| {
+            let out = entry.get_amount_out(amount.clone(), &aaa, &bbb).unwrap().amount;
+            let back = exit.get_amount_out(out, &bbb, &aaa).unwrap().amount;
+            BigInt::from(back) - BigInt::from(amount.clone())
+        };
+        let (min, limit) = (BigUint::from(ONE / 1_000), BigUint::from(500 * ONE));
+
+        let trade = search_trade_size(&entry, &exit, &aaa, &bbb, &min, &limit).unwrap();
+
+        let best_on_grid = (1..=1_000u32)
+            .map(|step| profit(&(&limit * step / 1_000u32)))
+            .max()
+            .unwrap();
+        assert!(best_on_grid > BigInt::ZERO);
+        assert!(
+            trade.expected_profit.clone() * 100 >= best_on_grid.clone() * 99,
+            "search found {} against {best_on_grid}",
+            trade.expected_profit
+        );
+        assert!(trade.amount_in > min && trade.amount_in < limit);
+        // The triple is the quote for the size found
+        let quote = entry.get_amount_out(trade.amount_in.clone(), &aaa, &bbb).unwrap();
+        assert_eq!(trade.amount_out, quote.amount);
+        assert_eq!(trade.gas, quote.gas);
+    }
+
+    #[test]
+    fn optimal_trade_stays_within_the_usable_share_of_the_limit() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let weth = token(0xc0, "WETH", 18);
+        let state = pool(1_000 * 10u128.pow(8), 30_000 * 10u128.pow(18));
+        let (limit, _) = state
+            .get_limits(wbtc.address.clone(), weth.address.clone())
+            .unwrap();
+
+        let trade = optimal_trade(
+            &component("pool", &[wbtc.clone(), weth.clone()]),
+            &state,
+            &wbtc,
+            &weth,
+            &BigUint::from(1_000u32),
+        )
+        .unwrap();
+
+        assert!(trade.amount_in <= limit * LIMIT_USAGE_BPS / 10_000u32);
+    }
+
+    #[test]
+    fn amount_in_for_finds_the_smallest_input_reaching_the_output() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let weth = token(0xc0, "WETH", 18);
+        let state = pool(1_000 * 10u128.pow(8), 30_000 * 10u128.pow(18));
+        let wanted = BigUint::from(3u128 * 10u128.pow(18));
+
+        let amount_in = amount_in_for(&state, &wbtc, &weth, &wanted).unwrap();
+
+        let out = |amount: &BigUint| state.get_amount_out(amount.clone(), &wbtc, &weth).unwrap().amount;
+        assert!(out(&amount_in) >= wanted);
+        assert!(out(&(&amount_in - 1u32)) < wanted);
+        // More than the whole pool holds can't be bought
+        let everything = BigUint::from(30_000u128 * 10u128.pow(18));
+        assert_eq!(amount_in_for(&state, &wbtc, &weth, &everything), None);
+    }
+}
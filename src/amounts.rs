@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use num_bigint::BigUint;
+use tycho_simulation::tycho_common::models::token::Token;
+
+use crate::config::ConfigSource;
+
+/// Probe size used when no `AMOUNT_IN` is configured, in base units of the sell token.
+const FALLBACK_AMOUNT_IN: u128 = 1000;
+
+/// A human readable token amount such as `0.01`, kept as digits until the token decimals are known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecimalAmount {
+    integer: BigUint,
+    fraction: String,
+}
+
+impl DecimalAmount {
+    /// Converts to base units. Fraction digits the token can't represent and amounts that
+    /// don't fit a uint256 are errors rather than silently truncated.
+    pub fn to_base_units(&self, decimals: u32) -> Result<BigUint> {
+        if self.fraction.len() > decimals as usize {
+            bail!(
+                "Amount {self} has {} decimal places, more than the token's {decimals}",
+                self.fraction.len()
+            );
+        }
+        let fraction = format!("{:0<width$}", self.fraction, width = decimals as usize);
+
+        let scale = BigUint::from(10u32).pow(decimals);
+        let fraction = if fraction.is_empty() {
+            BigUint::ZERO
+        } else {
+            BigUint::from_str(&fraction).expect("fraction contains only digits")
+        };
+
+        let amount = &self.integer * scale + fraction;
+        if amount.bits() > 256 {
+            bail!("Amount {self} overflows a uint256 with {decimals} decimals");
+        }
+        Ok(amount)
+    }
+}
+
+impl fmt::Display for DecimalAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.fraction.is_empty() {
+            write!(f, "{}", self.integer)
+        } else {
+            write!(f, "{}.{}", self.integer, self.fraction)
+        }
+    }
+}
+
+impl FromStr for DecimalAmount {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        let raw = raw.trim();
+        let (integer, fraction) = raw.split_once('.').unwrap_or((raw, ""));
+
+        if integer.is_empty() && fraction.is_empty() {
+            bail!("Amount is empty");
+        }
+        if !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            bail!("Amount {raw:?} must be a non-negative decimal number");
+        }
+
+        let integer = if integer.is_empty() {
+            BigUint::ZERO
+        } else {
+            BigUint::from_str(integer)?
+        };
+
+        Ok(Self {
+            integer,
+            fraction: fraction.trim_end_matches('0').to_string(),
+        })
+    }
+}
+
+/// Formats base units as a decimal amount with the token symbol, e.g. `0.0245 WBTC`.
+pub fn format_token_amount(amount: &BigUint, token: &Token) -> String {
+    format_amount(amount, token.decimals, &token.symbol)
+}
+
+/// `format_token_amount` for amounts without a `Token`, such as ETH balances.
+pub fn format_amount(amount: &BigUint, decimals: u32, symbol: &str) -> String {
+    let scale = BigUint::from(10u32).pow(decimals);
+    let integer = amount / &scale;
+    let fraction = (amount % &scale).to_string();
+    let fraction = format!("{fraction:0>width$}", width = decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+
+    if fraction.is_empty() {
+        format!("{integer} {symbol}")
+    } else {
+        format!("{integer}.{fraction} {symbol}")
+    }
+}
+
+/// Trade sizes from `AMOUNT_IN` plus per-symbol overrides like `AMOUNT_IN_WBTC`.
+#[derive(Debug, Clone, Default)]
+pub struct AmountInConfig {
+    pub default: Option<DecimalAmount>,
+    pub overrides: HashMap<String, DecimalAmount>,
+}
+
+impl AmountInConfig {
+    pub fn from_source(source: &ConfigSource) -> Result<Self> {
+        let default = source
+            .get("AMOUNT_IN")
+            .map(|raw| raw.parse().context("Can't parse AMOUNT_IN"))
+            .transpose()?;
+
+        let mut overrides = HashMap::new();
+        for key in source.keys_with_prefix("AMOUNT_IN_") {
+            let Some(raw) = source.get(&key) else {
+                continue;
+            };
+            let amount = raw
+                .parse()
+                .with_context(|| format!("Can't parse {key}"))?;
+            overrides.insert(key["AMOUNT_IN_".len()..].to_uppercase(), amount);
+        }
+
+        Ok(Self { default, overrides })
+    }
+
+    /// The trade size for `token` in its base units, an error when the configured amount
+    /// is more precise than the token or overflows.
+    pub fn for_token(&self, token: &Token) -> Result<BigUint> {
+        let symbol = token.symbol.to_uppercase();
+        let (key, amount) = match self.overrides.get(&symbol) {
+            Some(amount) => (format!("AMOUNT_IN_{symbol}"), amount),
+            None => match &self.default {
+                Some(amount) => ("AMOUNT_IN".to_string(), amount),
+                None => return Ok(BigUint::from(FALLBACK_AMOUNT_IN)),
+            },
+        };
+        amount
+            .to_base_units(token.decimals)
+            .with_context(|| format!("Can't use {key} for {}", token.symbol))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::token;
+
+    fn amount(raw: &str) -> DecimalAmount {
+        raw.parse().unwrap()
+    }
+
+    fn parse_error(raw: &str) -> String {
+        format!("{:#}", raw.parse::<DecimalAmount>().unwrap_err())
+    }
+
+    #[test]
+    fn amounts_scale_to_the_token_decimals() {
+        assert_eq!(amount("1.5").to_base_units(6).unwrap(), BigUint::from(1_500_000u32));
+        assert_eq!(amount("0.0245").to_base_units(8).unwrap(), BigUint::from(2_450_000u32));
+        assert_eq!(
+            amount("0.01").to_base_units(18).unwrap(),
+            BigUint::from(10_000_000_000_000_000u64)
+        );
+        assert_eq!(amount("42").to_base_units(0).unwrap(), BigUint::from(42u32));
+        // Trailing zeros are not extra precision
+        assert_eq!(amount("1.500000000").to_base_units(6).unwrap(), BigUint::from(1_500_000u32));
+    }
+
+    #[test]
+    fn fractions_finer_than_the_token_are_rejected() {
+        let error = amount("0.1234567").to_base_units(6).unwrap_err().to_string();
+        assert!(error.contains("0.1234567"), "{error}");
+        assert!(error.contains("7 decimal places"), "{error}");
+        assert!(amount("0.000000001").to_base_units(8).is_err());
+        assert!(amount("0.1234567").to_base_units(7).is_ok());
+    }
+
+    #[test]
+    fn leading_and_trailing_dots_are_accepted() {
+        assert_eq!(amount(".5"), amount("0.5"));
+        assert_eq!(amount("5."), amount("5"));
+        assert_eq!(amount(" 2.5 ").to_base_units(1).unwrap(), BigUint::from(25u32));
+    }
+
+    #[test]
+    fn malformed_amounts_are_rejected() {
+        assert_eq!(parse_error(""), "Amount is empty");
+        assert_eq!(parse_error("."), "Amount is empty");
+        for raw in ["-1", "-0.5", "1e18", "1,5", "1.2.3", "0x10", "abc"] {
+            assert!(parse_error(raw).contains("non-negative decimal number"), "{raw}");
+        }
+    }
+
+    #[test]
+    fn amounts_beyond_uint256_overflow() {
+        // 2^256 - 1 still fits
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(amount(max).to_base_units(0).unwrap().bits(), 256);
+        let error = amount(max).to_base_units(1).unwrap_err().to_string();
+        assert!(error.contains("overflows a uint256"), "{error}");
+        assert!(amount("1000000000000000000000000000000000000000000000000000000000000")
+            .to_base_units(18)
+            .is_err());
+    }
+
+    #[test]
+    fn formatting_round_trips_through_parsing() {
+        for (raw, decimals, symbol) in [
+            ("1.5", 6, "USDC"),
+            ("0.0245", 8, "WBTC"),
+            ("0.000000000000000001", 18, "WETH"),
+            ("1234", 18, "WETH"),
+        ] {
+            let token = token(0x11, symbol, decimals);
+            let base_units = amount(raw).to_base_units(decimals).unwrap();
+            let formatted = format_token_amount(&base_units, &token);
+            assert_eq!(formatted, format!("{raw} {symbol}"));
+
+            let (digits, _) = formatted.split_once(' ').unwrap();
+            assert_eq!(amount(digits).to_base_units(decimals).unwrap(), base_units);
+        }
+        assert_eq!(format_token_amount(&BigUint::ZERO, &token(0x11, "USDC", 6)), "0 USDC");
+    }
+
+    #[test]
+    fn amount_in_overrides_are_per_symbol_and_checked_per_token() {
+        let config = AmountInConfig::from_source(&ConfigSource::from_pairs(&[
+            ("AMOUNT_IN", "0.5"),
+            ("AMOUNT_IN_wbtc", "0.001"),
+            ("AMOUNT_IN_USDC", "100.1234567"),
+        ]))
+        .unwrap();
+        let weth = token(0xc0, "WETH", 18);
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+
+        assert_eq!(config.for_token(&weth).unwrap(), BigUint::from(500_000_000_000_000_000u64));
+        assert_eq!(config.for_token(&wbtc).unwrap(), BigUint::from(100_000u32));
+        let error = format!("{:#}", config.for_token(&usdc).unwrap_err());
+        assert!(error.starts_with("Can't use AMOUNT_IN_USDC for USDC"), "{error}");
+
+        // Nothing configured falls back to a fixed probe size
+        let config = AmountInConfig::from_source(&ConfigSource::from_pairs(&[])).unwrap();
+        assert_eq!(config.for_token(&usdc).unwrap(), BigUint::from(FALLBACK_AMOUNT_IN));
+
+        let error = AmountInConfig::from_source(&ConfigSource::from_pairs(&[("AMOUNT_IN_WBTC", "-1")]))
+            .unwrap_err();
+        assert!(format!("{error:#}").starts_with("Can't parse AMOUNT_IN_WBTC"));
+    }
+}
@@ -0,0 +1,1477 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use alloy::network::EthereumWallet;
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use futures::StreamExt;
+use num_bigint::{BigInt, BigUint};
+use tracing::{debug, error, info, trace, warn};
+
+use tycho_execution::encoding::evm::encoder_builders::TychoRouterEncoderBuilder;
+use tycho_execution::encoding::tycho_encoder::TychoEncoder;
+use tycho_simulation::evm::protocol::u256_num::biguint_to_u256;
+use tycho_simulation::protocol::models::{ProtocolComponent, Update};
+use tycho_simulation::tycho_common::models::token::Token;
+use tycho_simulation::tycho_core::simulation::protocol_sim::ProtocolSim;
+
+use crate::amount_in_strategy::{AmountStrategy, clamp_to_limits, compute_amount_in, optimal_trade};
+use crate::amounts::{AmountInConfig, format_token_amount};
+use crate::config::AppConfig;
+use crate::encoding::ExecutionMode;
+use crate::error::{EncodingError, ExecutorError, RunError};
+use crate::executor::Executor;
+use crate::opportunity_log::Opportunity;
+use crate::pool_registry::{PoolRegistry, pair_key};
+use crate::price_oracle::{Oracle, PriceOracle, value_usd};
+use crate::profit::{check_profit, check_thresholds, exit_quote, gas_cost, net_profit};
+use crate::provider::build_provider;
+use crate::receipts::ReceiptWatcher;
+use crate::simulation::{SwapSimulation, simulate_call, simulate_with_overrides};
+use crate::stream::{load_tokens, run_stream_with_reconnect};
+use crate::spread::find_spreads;
+use crate::stream_handler::{
+    SplitQuote, SwapSettings, TradeMode, best_direction, has_suspicious_metadata, process_route,
+    process_split_swap, process_swap, quote_route, quote_split, token_pairs,
+};
+use crate::{health, metrics, opportunity_log, preflight};
+
+/// Runs the bot with `config` until the stream gives up or a shutdown signal arrives.
+pub async fn run(config: AppConfig) -> Result<(), RunError> {
+    let signer_address = config.signer.address();
+    if let Some(wallet) = config.wallet_address
+        && wallet != signer_address
+    {
+        warn!(
+            %wallet,
+            %signer_address,
+            "⚠️ WALLET_ADDRESS differs from the PRIVATE_KEY signer, transactions will be sent from an account this key can't sign for"
+        );
+    }
+    if let Some(receiver) = config.receiver {
+        info!(%receiver, "💼 Swap proceeds are sent to RECEIVER_ADDRESS");
+    }
+    if config.dry_run {
+        info!("🧪 [DRY RUN] enabled, swaps are encoded and logged but never estimated or submitted");
+    }
+    match config.execution_mode {
+        ExecutionMode::Test => info!("🧪 Execution mode: TEST, executeInteractions is called with isTest=1"),
+        ExecutionMode::Live => warn!("🔴 Execution mode: LIVE, executeInteractions is called with isTest=0"),
+    }
+
+    let tokens = load_tokens(&config)
+        .await
+        .map_err(|e| RunError::Tokens(format!("{e:#}")))?;
+
+    for exchange in &config.exchanges {
+        info!(
+            exchange = %exchange.name,
+            remove_threshold = exchange.tvl.remove,
+            add_threshold = exchange.tvl.add,
+            "📊 Effective TVL filter"
+        );
+        if exchange.is_vm() {
+            warn!(
+                exchange = %exchange.name,
+                "🐢 VM exchange enabled, its pools are simulated in a local EVM so quotes and state updates are slower than native protocols"
+            );
+        }
+    }
+
+    let encoder = TychoRouterEncoderBuilder::new()
+        .user_transfer_type(config.user_transfer_type.clone())
+        .chain(config.chain)
+        .build()?;
+
+    if config.rpc_urls.len() > 1 {
+        info!(endpoints = config.rpc_urls.len(), "🔁 RPC failover enabled");
+    }
+    let wallet = EthereumWallet::from(config.signer.clone());
+    let provider = build_provider(&config.rpc_urls, wallet.clone())
+        .await
+        .map_err(|e| RunError::Provider(format!("{e:#}")))?;
+    let submitter = config
+        .submission
+        .build(provider.clone(), wallet, config.chain.id())
+        .map_err(|e| RunError::Submission(format!("{e:#}")))?;
+    let receipts = ReceiptWatcher::new(provider.clone(), config.watch);
+    let executor = Executor::new(
+        provider,
+        submitter,
+        receipts,
+        config.replacement,
+        config.wallet_address.unwrap_or(signer_address),
+        config.gas.clone(),
+        config.pending_tx_ttl,
+        config.nonce_resync_after,
+    );
+    if !config.dry_run {
+        executor.sync_nonce().await?;
+    }
+
+    let gas_payer = config.wallet_address.unwrap_or(signer_address);
+    let accounts = preflight::Accounts {
+        gas_payer,
+        holder: config.executor_contract,
+        spender: None,
+        tokens: config
+            .target_tokens
+            .iter()
+            .filter_map(|address| tokens.get(address))
+            .collect(),
+    };
+    if config.dry_run {
+        info!("🧪 [DRY RUN] Skipping the preflight balance check, a dry run makes no RPC calls");
+    } else {
+        preflight::run(executor.provider(), &config.preflight, &accounts)
+            .await
+            .map_err(|e| RunError::Preflight(format!("{e:#}")))?;
+    }
+    let mut last_funds_check = None;
+
+    let swap_settings = SwapSettings::from(&config);
+
+    let oracle = config
+        .price_oracle
+        .as_ref()
+        .map(|oracle_config| Oracle::from_config(oracle_config, config.chain))
+        .transpose()
+        .map_err(|e| service_error("price oracle", e))?;
+
+    let mut registry = PoolRegistry::new(config.v4_hook_addresses.clone());
+    let mut quote_failures = HashSet::new();
+
+    if let Some(port) = config.metrics_port {
+        metrics::install(port).map_err(|e| service_error("metrics exporter", e))?;
+    }
+    if let Some(port) = config.health_port {
+        health::install(port, config.health_stale_after)
+            .await
+            .map_err(|e| service_error("health endpoint", e))?;
+    }
+    if let Some(path) = &config.opportunity_log {
+        opportunity_log::install(path).map_err(|e| service_error("opportunity log", e))?;
+    }
+
+    run_stream_with_reconnect(&config, tokens, async |update| {
+        metrics::message_received();
+        for removed in registry.apply(&update) {
+            executor.cancel_pool(&removed);
+        }
+        metrics::tracked_pools(registry.len());
+        debug!(tracked_pools = registry.len(), "Pool registry updated");
+        executor.prune_pending().await;
+        let block = update.block_number_or_timestamp;
+        let checked = *last_funds_check.get_or_insert(block);
+        if !config.dry_run && block >= checked + config.preflight.interval_blocks {
+            last_funds_check = Some(block);
+            preflight::check_gas_funds(executor.provider(), &config.preflight, gas_payer).await;
+        }
+        // The registry still takes the update, only trading on it is skipped
+        if is_stale(&update, &config, executor.provider()).await {
+            return;
+        }
+        handle_spreads(
+            &update,
+            &registry,
+            &config,
+            &swap_settings,
+            encoder.as_ref(),
+            &executor,
+            oracle.as_ref(),
+        )
+        .await;
+        handle_update(
+            update,
+            &registry,
+            &config,
+            &swap_settings,
+            encoder.as_ref(),
+            &executor,
+            oracle.as_ref(),
+            &mut quote_failures,
+        )
+        .await;
+    })
+    .await
+    .map_err(|e| RunError::Stream(format!("{e:#}")))?;
+
+    executor.shutdown(config.shutdown_timeout).await;
+    opportunity_log::flush();
+    info!("👋 Shutdown complete");
+
+    Ok(())
+}
+
+fn service_error(service: &'static str, e: anyhow::Error) -> RunError {
+    RunError::Service {
+        service,
+        reason: format!("{e:#}"),
+    }
+}
+
+/// Gas price candidates are weighed against, `None` when it can't be fetched or is above
+/// `GAS_PRICE_CAP_GWEI`. A dry run prices gas at `DRY_RUN_GAS_PRICE_GWEI` instead of asking
+/// the RPC.
+async fn gas_price(config: &AppConfig, provider: &impl Provider) -> Option<u128> {
+    if config.dry_run {
+        return Some(config.gas.dry_run_gas_price);
+    }
+    let gas_price = match provider.get_gas_price().await {
+        Ok(gas_price) => gas_price,
+        Err(e) => {
+            error!("❌ Failed to fetch gas price: {}", e);
+            return None;
+        }
+    };
+    if config.gas.exceeds_cap(gas_price) {
+        info!(gas_price, "⛽ Gas price above GAS_PRICE_CAP_GWEI, pausing");
+        return None;
+    }
+    Some(gas_price)
+}
+
+/// Whether `update` is more than `MAX_STATE_AGE_BLOCKS` behind the chain head. If the
+/// head can't be fetched the update is treated as fresh, and so is every update of a dry
+/// run, which makes no RPC calls.
+async fn is_stale(update: &Update, config: &AppConfig, provider: &impl Provider) -> bool {
+    let Some(max_age) = config.max_state_age_blocks.filter(|_| !config.dry_run) else {
+        return false;
+    };
+    let head = match provider.get_block_number().await {
+        Ok(head) => head,
+        Err(e) => {
+            warn!(error = %e, "Can't fetch the latest block, skipping the staleness check");
+            return false;
+        }
+    };
+
+    let age = head.saturating_sub(update.block_number_or_timestamp);
+    if age > max_age {
+        warn!(
+            update_block = update.block_number_or_timestamp,
+            head,
+            age,
+            max_age,
+            "🕰️ Update is older than MAX_STATE_AGE_BLOCKS, not trading on it"
+        );
+        return true;
+    }
+    false
+}
+
+/// One direction of a pair worth quoting, owned so it can move to a blocking task.
+struct QuoteJob {
+    id: String,
+    component: ProtocolComponent,
+    state: Box<dyn ProtocolSim>,
+    sell_token: Token,
+    buy_token: Token,
+}
+
+/// A job that passed the forward and round-trip quotes.
+struct QuotedSwap {
+    id: String,
+    sell_token: Token,
+    buy_token: Token,
+    amount_in: BigUint,
+    amount_out: BigUint,
+    gas: BigUint,
+}
+
+enum QuoteOutcome {
+    Quoted(QuotedSwap),
+    Failed {
+        id: String,
+        sell_token: Token,
+        buy_token: Token,
+        error: String,
+    },
+    Skipped,
+}
+
+/// What the quoting tasks need from `AppConfig`, shared between them.
+struct QuoteSettings {
+    amount_strategy: Option<AmountStrategy>,
+    amount_in: AmountInConfig,
+    pool_limit_dust: BigUint,
+    max_round_trip_loss_bps: Option<u32>,
+}
+
+async fn handle_update(
+    update: Update,
+    registry: &PoolRegistry,
+    config: &AppConfig,
+    swap_settings: &SwapSettings,
+    encoder: &dyn TychoEncoder,
+    executor: &Executor<impl Provider>,
+    oracle: Option<&impl PriceOracle>,
+    quote_failures: &mut HashSet<String>,
+) {
+    // Every candidate is weighed against gas, with or without a profit gate
+    let Some(gas_price) = gas_price(config, executor.provider()).await else {
+        return;
+    };
+
+    for quoted in quote_update(&update, registry, config, quote_failures).await {
+        let QuotedSwap {
+            id,
+            sell_token,
+            buy_token,
+            amount_in,
+            amount_out,
+            gas,
+        } = quoted;
+        let Some(component) = registry.get(&id) else {
+            continue;
+        };
+        if executor.is_pending(&id) {
+            debug!(component = %id, "Transaction for this component still pending, skipping");
+            continue;
+        }
+        let (sell_token, buy_token, id) = (&sell_token, &buy_token, id.as_str());
+
+        // Priced against where the position would be exited, not the pool it came from
+        let net = match exit_quote(registry, oracle, id, sell_token, buy_token, &amount_out).await {
+            Ok(amount_back) => {
+                net_profit(
+                    config.chain,
+                    oracle,
+                    sell_token,
+                    &amount_in,
+                    &amount_back,
+                    &gas,
+                    gas_price,
+                )
+                .await
+            }
+            Err(miss) => Err(miss),
+        };
+        let not_submitted = |profit: Option<&BigInt>| Opportunity {
+            block: update.block_number_or_timestamp,
+            component: id,
+            sell_token,
+            buy_token,
+            amount_in: &amount_in,
+            amount_out: &amount_out,
+            estimated_profit: profit,
+            estimated_gas: &gas,
+            submitted: false,
+        };
+        // Whatever the thresholds, a trade that can't be priced or whose simulated gas eats
+        // the spread isn't encoded
+        let net = match net {
+            Ok(net) if net <= BigInt::ZERO => {
+                debug!(
+                    component = %id,
+                    net_profit = %net,
+                    simulated_gas = %gas,
+                    gas_price,
+                    "Simulated gas exceeds the spread, skipping"
+                );
+                opportunity_log::record(&not_submitted(Some(&net)));
+                continue;
+            }
+            Ok(net) => net,
+            Err(miss) => {
+                debug!(
+                    component = %id,
+                    threshold = miss.threshold,
+                    shortfall = %miss.shortfall,
+                    simulated_gas = %gas,
+                    "Can't price the round trip, skipping"
+                );
+                opportunity_log::record(&not_submitted(None));
+                continue;
+            }
+        };
+
+        let profit = if config.has_profit_gate() {
+            match check_thresholds(config, oracle, sell_token, &amount_in, net).await {
+                Ok(profit) => {
+                    metrics::record_profit(&profit, sell_token);
+                    info!(
+                        component = %id,
+                        %profit,
+                        simulated_gas = %gas,
+                        "💰 Profitable round trip"
+                    );
+                    Some(profit)
+                }
+                Err(miss) => {
+                    debug!(
+                        component = %id,
+                        threshold = miss.threshold,
+                        shortfall = %miss.shortfall,
+                        simulated_gas = %gas,
+                        "Below profit threshold, skipping"
+                    );
+                    opportunity_log::record(&not_submitted(None));
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+
+        info!(
+            "Selling/buying token symbol: {}/{}",
+            sell_token.symbol, buy_token.symbol
+        );
+        info!("Processing swap for {}", sell_token.symbol);
+        info!("Amount: {}", format_token_amount(&amount_out, buy_token));
+        if let Some(oracle) = oracle {
+            match value_usd(oracle, &amount_in, sell_token).await {
+                Ok(notional_usd) => info!(component = %id, notional_usd, "💵 Trade notional"),
+                Err(e) => debug!(component = %id, "No USD notional: {:#}", e),
+            }
+        }
+
+        let split = if config.max_split_pools > 1 {
+            split_if_better(
+                registry,
+                config,
+                oracle,
+                |pool_id| pool_id != id && executor.is_pending(pool_id),
+                sell_token,
+                buy_token,
+                &amount_in,
+                &amount_out,
+                &gas,
+                gas_price,
+            )
+            .await
+        } else {
+            None
+        };
+
+        // Tracked under every pool it trades on, so none of them is traded again meanwhile
+        let key = match &split {
+            Some((pool_ids, _, _)) => pool_ids.join("+"),
+            None => id.to_string(),
+        };
+        let (encoded, amount_out, simulated_gas) = match split {
+            Some((_, components, split)) => {
+                info!(
+                    component = %id,
+                    pools = components.len(),
+                    amount_out = %format_token_amount(&split.amount_out, buy_token),
+                    "🔀 Splitting trade across pools"
+                );
+                let amount_out = split.amount_out.clone();
+                let gas = split.gas.clone();
+                let encoded = process_split_swap(
+                    components,
+                    sell_token,
+                    buy_token,
+                    split,
+                    amount_in.clone(),
+                    &config.signer,
+                    swap_settings,
+                    encoder,
+                );
+                (encoded, amount_out, gas)
+            }
+            None => {
+                let encoded = process_swap(
+                    component,
+                    sell_token,
+                    buy_token,
+                    amount_in.clone(),
+                    amount_out.clone(),
+                    TradeMode::ExactIn,
+                    &config.signer,
+                    swap_settings,
+                    encoder,
+                );
+                (encoded, amount_out, gas)
+            }
+        };
+        let submitted = execute_swap(
+            &key,
+            update.block_number_or_timestamp,
+            encoded,
+            &amount_in,
+            sell_token,
+            &amount_out,
+            buy_token,
+            &simulated_gas,
+            config,
+            swap_settings,
+            executor,
+        )
+        .await;
+        opportunity_log::record(&Opportunity {
+            block: update.block_number_or_timestamp,
+            component: id,
+            sell_token,
+            buy_token,
+            amount_in: &amount_in,
+            amount_out: &amount_out,
+            estimated_profit: profit.as_ref(),
+            estimated_gas: &simulated_gas,
+            submitted,
+        });
+    }
+}
+
+/// Splits `amount_in` over the `MAX_SPLIT_POOLS` pools of the pair quoting it best, when
+/// that still beats the single pool's `amount_out` once the extra gas is paid for in
+/// `buy_token`. Pools for which `busy` holds are left out. Returns the ids of the pools
+/// traded on along with their components.
+#[allow(clippy::too_many_arguments)]
+async fn split_if_better(
+    registry: &PoolRegistry,
+    config: &AppConfig,
+    oracle: Option<&impl PriceOracle>,
+    busy: impl Fn(&str) -> bool,
+    sell_token: &Token,
+    buy_token: &Token,
+    amount_in: &BigUint,
+    amount_out: &BigUint,
+    gas: &BigUint,
+    gas_price: u128,
+) -> Option<(Vec<String>, Vec<ProtocolComponent>, SplitQuote)> {
+    let mut ranked: Vec<(&String, &ProtocolComponent, &dyn ProtocolSim, BigUint)> = registry
+        .pools_for_pair(&pair_key(&sell_token.address, &buy_token.address))
+        .filter(|pool_id| !busy(pool_id))
+        .filter_map(|pool_id| {
+            let state = registry.state(pool_id)?;
+            let quote = state.get_amount_out(amount_in.clone(), sell_token, buy_token).ok()?;
+            Some((pool_id, registry.get(pool_id)?, state, quote.amount))
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.3.cmp(&a.3));
+    ranked.truncate(config.max_split_pools);
+    let pools: Vec<(&ProtocolComponent, &dyn ProtocolSim)> = ranked
+        .iter()
+        .map(|(_, component, state, _)| (*component, *state))
+        .collect();
+    let (components, split) = quote_split(&pools, sell_token, buy_token, amount_in)?;
+    // quote_split drops the pools it can't quote, keep the ids of the others
+    let pool_ids = components
+        .iter()
+        .filter_map(|kept| {
+            ranked
+                .iter()
+                .find(|(_, component, _, _)| component.id == kept.id)
+                .map(|(pool_id, _, _, _)| pool_id.to_string())
+        })
+        .collect();
+
+    let net_of_gas = |amount_out: &BigUint, gas_cost: BigUint| {
+        BigInt::from(amount_out.clone()) - BigInt::from(gas_cost)
+    };
+    let (single_gas, split_gas) = match (
+        gas_cost(config.chain, oracle, buy_token, gas, gas_price).await,
+        gas_cost(config.chain, oracle, buy_token, &split.gas, gas_price).await,
+    ) {
+        (Ok(single_gas), Ok(split_gas)) => (single_gas, split_gas),
+        (Err(miss), _) | (_, Err(miss)) => {
+            debug!(shortfall = %miss.shortfall, "Can't price split gas, keeping the single pool");
+            return None;
+        }
+    };
+    (net_of_gas(&split.amount_out, split_gas) > net_of_gas(amount_out, single_gas))
+        .then_some((pool_ids, components, split))
+}
+
+/// Whether `best_direction` against another pool of the pair picks selling `sell_token` on
+/// `state`. A pair with no other pool is left to the oracle, so both sides stay candidates.
+fn sells_at_a_spread(
+    registry: &PoolRegistry,
+    config: &AppConfig,
+    id: &str,
+    state: &dyn ProtocolSim,
+    sell_token: &Token,
+    buy_token: &Token,
+) -> bool {
+    let mut exits = registry
+        .pools_for_pair(&pair_key(&sell_token.address, &buy_token.address))
+        .filter(|other| other.as_str() != id)
+        .filter_map(|other| registry.state(other))
+        .peekable();
+    if exits.peek().is_none() {
+        return true;
+    }
+    // quote_job reports an unusable AMOUNT_IN for the pool
+    let Ok(amount) = config.amount_in.for_token(sell_token) else {
+        return true;
+    };
+    exits.any(|exit| {
+        best_direction(state, exit, sell_token, buy_token, amount.clone())
+            .is_some_and(|(sell, _, _)| sell.address == sell_token.address)
+    })
+}
+
+/// Quotes every candidate pair of the update on blocking threads, at most
+/// `QUOTE_CONCURRENCY` at a time, and returns the ones worth checking for profit.
+async fn quote_update(
+    update: &Update,
+    registry: &PoolRegistry,
+    config: &AppConfig,
+    quote_failures: &mut HashSet<String>,
+) -> Vec<QuotedSwap> {
+    // A pool is logged again if it comes back, and the set stays as small as the registry
+    quote_failures.retain(|id| registry.get(id).is_some());
+    let mut jobs = Vec::new();
+    for (id, state) in update.states.iter() {
+        if config.is_blocked_component(id) {
+            trace!(component = %id, "Component is in COMPONENT_BLOCKLIST, skipping");
+            continue;
+        }
+
+        // States for pools that were just removed can still arrive in the same update
+        let Some(component) = registry.get(id) else {
+            continue;
+        };
+        for (sell_token, buy_token) in token_pairs(&component.tokens) {
+            // Only sell tokens the bot holds
+            if !config.is_target_token(sell_token) {
+                continue;
+            }
+            if has_suspicious_metadata(sell_token) || has_suspicious_metadata(buy_token) {
+                debug!(
+                    component = %id,
+                    sell_token = %sell_token.address,
+                    buy_token = %buy_token.address,
+                    "Token has zero decimals or no symbol, skipping pair"
+                );
+                continue;
+            }
+            if !sells_at_a_spread(registry, config, id, state.as_ref(), sell_token, buy_token) {
+                trace!(
+                    component = %id,
+                    sell_token = %sell_token.address,
+                    buy_token = %buy_token.address,
+                    "No positive spread selling this side, skipping direction"
+                );
+                continue;
+            }
+            jobs.push(QuoteJob {
+                id: id.clone(),
+                component: component.clone(),
+                state: state.clone_box(),
+                sell_token: sell_token.clone(),
+                buy_token: buy_token.clone(),
+            });
+        }
+    }
+
+    let settings = Arc::new(QuoteSettings {
+        amount_strategy: config.amount_strategy.clone(),
+        amount_in: config.amount_in.clone(),
+        pool_limit_dust: config.pool_limit_dust.clone(),
+        max_round_trip_loss_bps: config.max_round_trip_loss_bps,
+    });
+    // get_amount_out is CPU bound, VM pools especially, so it stays off the async workers
+    let mut outcomes = futures::stream::iter(jobs)
+        .map(|job| {
+            let settings = Arc::clone(&settings);
+            tokio::task::spawn_blocking(move || quote_job(job, &settings))
+        })
+        .buffer_unordered(config.quote_concurrency);
+
+    let mut quoted = Vec::new();
+    while let Some(outcome) = outcomes.next().await {
+        match outcome {
+            Ok(QuoteOutcome::Quoted(swap)) => quoted.push(swap),
+            Ok(QuoteOutcome::Failed {
+                id,
+                sell_token,
+                buy_token,
+                error,
+            }) => {
+                // Every failure is counted, but hook pools with custom fee logic can fail on
+                // every update, so each pool is only logged once
+                metrics::quote_failed();
+                if quote_failures.insert(id.clone()) {
+                    debug!(
+                        component = %id,
+                        sell_token = %sell_token.symbol,
+                        buy_token = %buy_token.symbol,
+                        %error,
+                        "Quote failed, not logging further failures for this pool"
+                    );
+                }
+            }
+            Ok(QuoteOutcome::Skipped) => {}
+            Err(e) => error!("❌ Quote task panicked: {}", e),
+        }
+    }
+    quoted
+}
+
+fn quote_job(job: QuoteJob, settings: &QuoteSettings) -> QuoteOutcome {
+    let QuoteJob {
+        id,
+        component,
+        state,
+        sell_token,
+        buy_token,
+    } = job;
+
+    let (amount_in, searched) = match &settings.amount_strategy {
+        Some(AmountStrategy::OptimalForProfit { min_amount }) => {
+            match optimal_trade(&component, state.as_ref(), &sell_token, &buy_token, min_amount) {
+                Some(trade) => (trade.amount_in.clone(), Some(trade)),
+                None => return QuoteOutcome::Skipped,
+            }
+        }
+        Some(strategy) => (
+            compute_amount_in(&component, state.as_ref(), &sell_token, &buy_token, strategy),
+            None,
+        ),
+        None => match settings.amount_in.for_token(&sell_token) {
+            Ok(amount_in) => (amount_in, None),
+            Err(e) => {
+                return QuoteOutcome::Failed {
+                    id,
+                    sell_token,
+                    buy_token,
+                    error: format!("{e:#}"),
+                };
+            }
+        },
+    };
+    let Some(amount_in) = clamp_to_limits(
+        &component,
+        state.as_ref(),
+        &sell_token,
+        &buy_token,
+        amount_in,
+        &settings.pool_limit_dust,
+    ) else {
+        return QuoteOutcome::Skipped;
+    };
+
+    let (amount_out, gas) = match searched {
+        // The search already quoted the size it picked
+        Some(trade) if trade.amount_in == amount_in => (trade.amount_out, trade.gas),
+        _ => match state.get_amount_out(amount_in.clone(), &sell_token, &buy_token) {
+            Ok(result) => (result.amount, result.gas),
+            Err(e) => {
+                return QuoteOutcome::Failed {
+                    id,
+                    sell_token,
+                    buy_token,
+                    error: e.to_string(),
+                };
+            }
+        },
+    };
+    metrics::swap_evaluated();
+
+    // Quoting the output back catches stale or manipulated states before
+    // spending an RPC call on gas estimation
+    if let Some(max_loss_bps) = settings.max_round_trip_loss_bps {
+        let round_trip_out =
+            match state.get_amount_out(amount_out.clone(), &buy_token, &sell_token) {
+                Ok(result) => result.amount,
+                Err(e) => {
+                    debug!(component = %id, error = %e, "Reverse quote failed, skipping");
+                    return QuoteOutcome::Skipped;
+                }
+            };
+        let min_round_trip_out =
+            &amount_in * BigUint::from(10_000 - max_loss_bps) / BigUint::from(10_000u32);
+        if round_trip_out < min_round_trip_out {
+            warn!(
+                component = %id,
+                amount_in = %format_token_amount(&amount_in, &sell_token),
+                round_trip_out = %format_token_amount(&round_trip_out, &sell_token),
+                max_loss_bps,
+                "⚠️ Round trip loses more than MAX_ROUND_TRIP_LOSS_BPS, skipping suspicious pool state"
+            );
+            return QuoteOutcome::Skipped;
+        }
+    }
+
+    QuoteOutcome::Quoted(QuotedSwap {
+        id,
+        sell_token,
+        buy_token,
+        amount_in,
+        amount_out,
+        gas,
+    })
+}
+
+/// Looks for pools of the same pair quoting far enough apart, and trades the loop
+/// `quote -> base` on the cheap pool then `base -> quote` on the rich one when the
+/// quoted round trip beats gas and the profit thresholds.
+async fn handle_spreads(
+    update: &Update,
+    registry: &PoolRegistry,
+    config: &AppConfig,
+    swap_settings: &SwapSettings,
+    encoder: &dyn TychoEncoder,
+    executor: &Executor<impl Provider>,
+    oracle: Option<&impl PriceOracle>,
+) {
+    let opportunities = find_spreads(registry, update, config.min_spread_bps);
+    if opportunities.is_empty() {
+        return;
+    }
+
+    let Some(gas_price) = gas_price(config, executor.provider()).await else {
+        return;
+    };
+
+    for opportunity in opportunities {
+        // Loop through whichever side of the pair the bot is configured to hold
+        let opportunity = if config.is_target_token(opportunity.quote) {
+            opportunity
+        } else if config.is_target_token(opportunity.base) {
+            opportunity.flipped()
+        } else {
+            continue;
+        };
+        if has_suspicious_metadata(opportunity.base) || has_suspicious_metadata(opportunity.quote) {
+            debug!(
+                cheap = %opportunity.cheap_id,
+                rich = %opportunity.rich_id,
+                "Token has zero decimals or no symbol, skipping spread"
+            );
+            continue;
+        }
+        let start = opportunity.quote;
+        let middle = opportunity.base;
+        let label = format!("{}+{}", opportunity.cheap_id, opportunity.rich_id);
+        if [opportunity.cheap_id, opportunity.rich_id]
+            .iter()
+            .any(|pool_id| executor.is_pending(pool_id))
+        {
+            debug!(route = %label, "Transaction touching these pools still pending, skipping");
+            continue;
+        }
+
+        info!(
+            pair = %format!("{}/{}", middle.symbol, start.symbol),
+            cheap = %opportunity.cheap_id,
+            rich = %opportunity.rich_id,
+            spread_bps = opportunity.spread_bps,
+            "🔀 Cross-pool spread"
+        );
+
+        let (Some(cheap_state), Some(rich_state)) = (
+            registry.state(opportunity.cheap_id),
+            registry.state(opportunity.rich_id),
+        ) else {
+            continue;
+        };
+
+        let amount_in = match config.amount_in.for_token(start) {
+            Ok(amount_in) => amount_in,
+            Err(e) => {
+                debug!(route = %label, "Can't size the spread, skipping: {:#}", e);
+                continue;
+            }
+        };
+        let Some(amount_in) = clamp_to_limits(
+            opportunity.cheap,
+            cheap_state,
+            start,
+            middle,
+            amount_in,
+            &config.pool_limit_dust,
+        ) else {
+            continue;
+        };
+
+        let hops = vec![
+            (opportunity.cheap.clone(), start.clone(), middle.clone()),
+            (opportunity.rich.clone(), middle.clone(), start.clone()),
+        ];
+        let route = match quote_route(&hops, &[cheap_state, rich_state], amount_in.clone()) {
+            Ok(route) => route,
+            Err(e) => {
+                debug!(route = %label, error = %e, "Spread quote failed, skipping");
+                continue;
+            }
+        };
+        metrics::swap_evaluated();
+
+        let not_submitted = |profit: Option<&BigInt>| Opportunity {
+            block: update.block_number_or_timestamp,
+            component: &label,
+            sell_token: start,
+            buy_token: start,
+            amount_in: &amount_in,
+            amount_out: &route.amount_out,
+            estimated_profit: profit,
+            estimated_gas: &route.gas,
+            submitted: false,
+        };
+        let profit = match check_profit(
+            config,
+            oracle,
+            start,
+            &amount_in,
+            &route.amount_out,
+            &route.gas,
+            gas_price,
+        )
+        .await
+        {
+            Ok(profit) if profit > BigInt::ZERO => {
+                metrics::record_profit(&profit, start);
+                info!(
+                    route = %label,
+                    %profit,
+                    simulated_gas = %route.gas,
+                    "💰 Profitable cross-pool spread"
+                );
+                profit
+            }
+            Ok(profit) => {
+                debug!(route = %label, %profit, "Spread doesn't cover gas, skipping");
+                opportunity_log::record(&not_submitted(Some(&profit)));
+                continue;
+            }
+            Err(miss) => {
+                debug!(
+                    route = %label,
+                    threshold = miss.threshold,
+                    shortfall = %miss.shortfall,
+                    "Spread below profit threshold, skipping"
+                );
+                opportunity_log::record(&not_submitted(None));
+                continue;
+            }
+        };
+
+        let encoded = process_route(
+            hops,
+            route.hop_amounts_in,
+            route.amount_out.clone(),
+            &config.signer,
+            swap_settings,
+            encoder,
+        );
+        let submitted = execute_swap(
+            &label,
+            update.block_number_or_timestamp,
+            encoded,
+            &amount_in,
+            start,
+            &route.amount_out,
+            start,
+            &route.gas,
+            config,
+            swap_settings,
+            executor,
+        )
+        .await;
+        opportunity_log::record(&Opportunity {
+            estimated_profit: Some(&profit),
+            submitted,
+            ..not_submitted(None)
+        });
+    }
+}
+
+/// Logs the encoded swap on a dry run, otherwise estimates its gas over RPC and submits it.
+/// Returns whether a transaction was sent. `block` is the block of the update it was quoted on.
+#[allow(clippy::too_many_arguments)]
+async fn execute_swap(
+    id: &str,
+    block: u64,
+    encoded: Result<TransactionRequest, EncodingError>,
+    amount_in: &BigUint,
+    sell_token: &Token,
+    amount_out: &BigUint,
+    buy_token: &Token,
+    simulated_gas: &BigUint,
+    config: &AppConfig,
+    swap_settings: &SwapSettings,
+    executor: &Executor<impl Provider>,
+) -> bool {
+    match encoded {
+        Ok(tx_request) if config.dry_run => {
+            metrics::swap_encoded();
+            let calldata = tx_request.input.input().cloned().unwrap_or_default();
+            info!(
+                component = %id,
+                amount_in = %format_token_amount(amount_in, sell_token),
+                amount_out = %format_token_amount(amount_out, buy_token),
+                executor = %swap_settings.executor_contract,
+                %calldata,
+                "[DRY RUN] Swap encoded, skipping gas estimation and submission"
+            );
+            false
+        }
+        Ok(tx_request) => {
+            metrics::swap_encoded();
+            let tx_request = executor
+                .skip_covered_approvals(tx_request, biguint_to_u256(amount_in))
+                .await;
+            if let Some(slots) = &config.state_overrides {
+                let simulated = simulate_with_overrides(
+                    executor.provider(),
+                    tx_request.clone(),
+                    sell_token,
+                    biguint_to_u256(amount_in),
+                    slots,
+                )
+                .await;
+                match simulated {
+                    Ok(SwapSimulation {
+                        amount_out: Some(amount_out),
+                        min_amount_out: Some(min_amount_out),
+                    }) if amount_out < min_amount_out => {
+                        metrics::simulation_reverted();
+                        error!(
+                            component = %id,
+                            %amount_out,
+                            %min_amount_out,
+                            "❌ Simulated output below minAmountOut, not submitting"
+                        );
+                        return false;
+                    }
+                    Ok(_) => {}
+                    Err(_) => {
+                        metrics::simulation_reverted();
+                        error!(component = %id, "❌ Swap reverts in simulation, not submitting");
+                        return false;
+                    }
+                }
+            } else if config.simulate_calls
+                && simulate_call(executor.provider(), tx_request.clone()).await.is_err()
+            {
+                metrics::simulation_reverted();
+                error!(component = %id, "❌ Swap reverts in simulation, not submitting");
+                return false;
+            }
+            match executor.estimate_gas(tx_request.clone()).await {
+                Ok(gas) => {
+                    // The RPC estimate includes router and executor overhead on top
+                    // of the protocol's own gas, log both to compare them over time
+                    info!(component = %id, %simulated_gas, rpc_gas = gas, "⛽ Estimated gas");
+                    if let Some(max_gas_units) = config.max_gas_units
+                        && gas > max_gas_units
+                    {
+                        warn!(
+                            component = %id,
+                            estimated_gas = gas,
+                            max_gas_units,
+                            "⛽ Gas estimate above MAX_GAS_UNITS, rejecting swap as uneconomical"
+                        );
+                        return false;
+                    }
+                    match executor.submit_transaction(id, tx_request, gas, block).await {
+                        Ok(_) => true,
+                        Err(e @ ExecutorError::GasPriceCap { .. }) => {
+                            info!(component = %id, "⛽ Skipping swap: {}", e);
+                            false
+                        }
+                        Err(e) => {
+                            metrics::submission_failed();
+                            error!(component = %id, "❌ Failed to submit transaction: {}", e);
+                            false
+                        }
+                    }
+                }
+                Err(e) => {
+                    metrics::gas_estimate_failed();
+                    error!(component = %id, "❌ {}", e);
+                    false
+                }
+            }
+        }
+        Err(e) => {
+            metrics::encoding_failed();
+            error!("❌ Failed to process swap: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy::transports::mock::Asserter;
+    use tycho_simulation::tycho_common::hex_bytes::Bytes;
+    use tycho_simulation::tycho_common::models::Chain;
+
+    use super::*;
+    use crate::encoding::RouterFunction;
+    use crate::testing::{
+        MockEncoder, WETH, component, config, encoded, executor, pool, swap_settings, token, update,
+    };
+
+    const ONE: u128 = 1_000_000_000_000_000_000;
+
+    /// WETH at its Ethereum address, the one token gas is priced in without an oracle.
+    fn weth() -> Token {
+        Token::new(&Bytes::from(WETH.as_slice()), "WETH", 18, 0, &[], Chain::Ethereum, 100)
+    }
+
+    #[tokio::test]
+    async fn a_removed_pool_is_no_longer_quoted() {
+        let config = config(&[]);
+        let tokens = [token(0x11, "AAA", 18), token(0x22, "BBB", 18)];
+        let mut registry = PoolRegistry::default();
+        let mut quote_failures = HashSet::new();
+
+        let added = update(1, &[("pool", &tokens, pool(1_000 * ONE, 1_000 * ONE))]);
+        assert!(registry.apply(&added).is_empty());
+        let quoted = quote_update(&added, &registry, &config, &mut quote_failures).await;
+        assert!(quoted.iter().any(|swap| swap.id == "pool"));
+
+        let mut removal = update(2, &[]);
+        removal
+            .removed_pairs
+            .insert("pool".to_string(), component("pool", &tokens));
+        assert_eq!(registry.apply(&removal), ["pool"]);
+        assert_eq!(registry.len(), 0);
+
+        // A late state for the pool, as Tycho can still send one
+        let mut states: HashMap<String, Box<dyn ProtocolSim>> = HashMap::new();
+        states.insert("pool".to_string(), Box::new(pool(1_000 * ONE, 1_100 * ONE)));
+        let late = Update::new(3, states, HashMap::new());
+        assert!(registry.apply(&late).is_empty());
+        assert!(registry.state("pool").is_none());
+        assert!(quote_update(&late, &registry, &config, &mut quote_failures).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn round_trip_loss_only_skips_pools_when_configured() {
+        let tokens = [token(0x11, "AAA", 18), token(0x22, "BBB", 18)];
+        let pools = [("pool", &tokens[..], pool(1_000 * ONE, 1_000 * ONE))];
+        let registry = crate::testing::registry(&pools);
+        let update = update(2, &pools);
+
+        // Two 0.3% fees lose about 60 bps on the way back
+        let strict = config(&[("MAX_ROUND_TRIP_LOSS_BPS", "10")]);
+        assert!(quote_update(&update, &registry, &strict, &mut HashSet::new()).await.is_empty());
+
+        let lenient = config(&[("MAX_ROUND_TRIP_LOSS_BPS", "100")]);
+        assert!(!quote_update(&update, &registry, &lenient, &mut HashSet::new()).await.is_empty());
+
+        let unchecked = config(&[]);
+        assert!(!quote_update(&update, &registry, &unchecked, &mut HashSet::new()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn quote_failures_are_forgotten_with_their_pool() {
+        let config = config(&[]);
+        let registry = PoolRegistry::default();
+        let mut quote_failures = HashSet::from(["gone".to_string()]);
+
+        quote_update(&update(2, &[]), &registry, &config, &mut quote_failures).await;
+
+        assert!(quote_failures.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_three_token_pool_is_quoted_in_all_six_directions() {
+        let tokens = [token(0x11, "AAA", 18), token(0x22, "BBB", 18), token(0x33, "CCC", 18)];
+        let pools = [("tri", &tokens[..], pool(1_000 * ONE, 1_000 * ONE))];
+        let registry = crate::testing::registry(&pools);
+        let update = update(2, &pools);
+
+        let directions = |quoted: Vec<QuotedSwap>| {
+            let mut directions: Vec<(String, String)> = quoted
+                .into_iter()
+                .map(|swap| (swap.sell_token.symbol, swap.buy_token.symbol))
+                .collect();
+            directions.sort();
+            directions
+        };
+
+        let every_token = config(&[]);
+        let quoted = quote_update(&update, &registry, &every_token, &mut HashSet::new()).await;
+        assert_eq!(directions(quoted).len(), 6);
+
+        // Only the held token is sold, towards both others
+        let only_bbb = config(&[("TARGET_TOKENS", "0x2222222222222222222222222222222222222222")]);
+        let quoted = quote_update(&update, &registry, &only_bbb, &mut HashSet::new()).await;
+        assert_eq!(
+            directions(quoted),
+            [
+                ("BBB".to_string(), "AAA".to_string()),
+                ("BBB".to_string(), "CCC".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn both_directions_of_a_pair_are_quoted_on_their_own() {
+        let tokens = [token(0x11, "AAA", 18), token(0x22, "BBB", 18)];
+        // One BBB is worth two AAA
+        let state = pool(2_000 * ONE, 1_000 * ONE);
+        let pools = [("pool", &tokens[..], state.clone())];
+        let registry = crate::testing::registry(&pools);
+
+        let mut quoted =
+            quote_update(&update(2, &pools), &registry, &config(&[]), &mut HashSet::new()).await;
+        quoted.sort_by(|a, b| a.sell_token.symbol.cmp(&b.sell_token.symbol));
+
+        let [aaa_to_bbb, bbb_to_aaa] = &quoted[..] else {
+            panic!("expected one quote per direction, got {}", quoted.len());
+        };
+        assert_eq!((aaa_to_bbb.sell_token.symbol.as_str(), aaa_to_bbb.buy_token.symbol.as_str()), ("AAA", "BBB"));
+        assert_eq!((bbb_to_aaa.sell_token.symbol.as_str(), bbb_to_aaa.buy_token.symbol.as_str()), ("BBB", "AAA"));
+        for swap in [aaa_to_bbb, bbb_to_aaa] {
+            let quote = state
+                .get_amount_out(swap.amount_in.clone(), &swap.sell_token, &swap.buy_token)
+                .unwrap();
+            assert_eq!(swap.amount_out, quote.amount);
+        }
+        // Neither direction is derived from the other's output
+        assert_eq!(aaa_to_bbb.amount_in, bbb_to_aaa.amount_in);
+        assert!(aaa_to_bbb.amount_out < aaa_to_bbb.amount_in);
+        assert!(bbb_to_aaa.amount_out > bbb_to_aaa.amount_in);
+    }
+
+    #[tokio::test]
+    async fn each_pool_is_only_quoted_in_the_direction_with_a_spread() {
+        let tokens = [token(0x11, "AAA", 18), token(0x22, "BBB", 18)];
+        // AAA buys 2 BBB on one pool and 1.8 on the other
+        let pools = [
+            ("rich_in_bbb", &tokens[..], pool(1_000 * ONE, 2_000 * ONE)),
+            ("poor_in_bbb", &tokens[..], pool(1_000 * ONE, 1_800 * ONE)),
+        ];
+        let registry = crate::testing::registry(&pools);
+        let config = config(&[("AMOUNT_IN", "1")]);
+
+        let mut quoted =
+            quote_update(&update(2, &pools), &registry, &config, &mut HashSet::new()).await;
+        quoted.sort_by(|a, b| a.id.cmp(&b.id));
+
+        // Buy BBB where it's cheap, buy AAA back where BBB fetches more of it
+        let directions: Vec<_> = quoted
+            .iter()
+            .map(|swap| (swap.id.as_str(), swap.sell_token.symbol.as_str()))
+            .collect();
+        assert_eq!(directions, [("poor_in_bbb", "BBB"), ("rich_in_bbb", "AAA")]);
+
+        // The same price on both pools leaves no side to quote
+        let flat = [
+            ("a", &tokens[..], pool(1_000 * ONE, 1_000 * ONE)),
+            ("b", &tokens[..], pool(1_000 * ONE, 1_000 * ONE)),
+        ];
+        let registry = crate::testing::registry(&flat);
+        assert!(quote_update(&update(2, &flat), &registry, &config, &mut HashSet::new())
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn optimal_sizing_hands_its_quote_to_the_swap() {
+        let tokens = [token(0x11, "AAA", 18), token(0x22, "BBB", 18)];
+        let state = pool(1_000 * ONE, 1_000 * ONE);
+        let pools = [("pool", &tokens[..], state.clone())];
+        let registry = crate::testing::registry(&pools);
+        let config = config(&[("AMOUNT_STRATEGY", "optimal:1000000")]);
+
+        let quoted = quote_update(&update(2, &pools), &registry, &config, &mut HashSet::new()).await;
+
+        assert_eq!(quoted.len(), 2);
+        for swap in quoted {
+            let quote = state
+                .get_amount_out(swap.amount_in.clone(), &swap.sell_token, &swap.buy_token)
+                .unwrap();
+            assert!(swap.amount_in >= BigUint::from(1_000_000u32));
+            assert_eq!(swap.amount_out, quote.amount);
+            assert_eq!(swap.gas, quote.gas);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_spread_between_two_pools_is_encoded_as_a_loop_through_both() {
+        let weth = weth();
+        let bbb = token(0x22, "BBB", 18);
+        let tokens = [bbb.clone(), weth.clone()];
+        // BBB sorts first, so reserve0 is BBB: WETH buys 2 BBB on one pool and 1.8 on the other
+        let pools = [
+            ("rich_in_bbb", &tokens[..], pool(2_000 * ONE, 1_000 * ONE)),
+            ("poor_in_bbb", &tokens[..], pool(1_800 * ONE, 1_000 * ONE)),
+        ];
+        let registry = crate::testing::registry(&pools);
+        let config = config(&[
+            ("DRY_RUN", "true"),
+            ("AMOUNT_IN", "1"),
+            ("TARGET_TOKENS", "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+        ]);
+        // Nothing queued, so any RPC call fails and the loop is never encoded
+        let executor = executor(&config, Asserter::new());
+        let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SequentialSwap));
+
+        handle_spreads(
+            &update(2, &pools),
+            &registry,
+            &config,
+            &swap_settings(),
+            &encoder,
+            &executor,
+            None::<&Oracle>,
+        )
+        .await;
+
+        let calls = encoder.calls();
+        assert_eq!(calls.len(), 1);
+        let solution = &calls[0];
+        assert_eq!(solution.given_token, weth.address);
+        assert_eq!(solution.checked_token, weth.address);
+        assert_eq!(solution.given_amount, BigUint::from(ONE));
+        assert!(solution.checked_amount > solution.given_amount);
+
+        // Sell WETH where it fetches the most BBB, buy it back where BBB is worth more
+        let (entry, exit) = (&pools[0].2, &pools[1].2);
+        let bbb_out = entry.get_amount_out(BigUint::from(ONE), &weth, &bbb).unwrap().amount;
+        let hops: Vec<_> = solution
+            .swaps
+            .iter()
+            .map(|swap| {
+                (
+                    swap.token_in.clone(),
+                    swap.token_out.clone(),
+                    swap.estimated_amount_in.clone().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            hops,
+            [
+                (weth.address.clone(), bbb.address.clone(), BigUint::from(ONE)),
+                (bbb.address.clone(), weth.address.clone(), bbb_out.clone()),
+            ]
+        );
+        let weth_back = exit.get_amount_out(bbb_out, &bbb, &weth).unwrap().amount;
+        assert!(weth_back > BigUint::from(ONE));
+    }
+
+    #[tokio::test]
+    async fn a_dry_run_prices_and_encodes_without_any_rpc_call() {
+        let weth = weth();
+        let bbb = token(0x22, "BBB", 18);
+        let tokens = [bbb.clone(), weth.clone()];
+        let pools = [
+            ("rich_in_bbb", &tokens[..], pool(2_000 * ONE, 1_000 * ONE)),
+            ("poor_in_bbb", &tokens[..], pool(1_800 * ONE, 1_000 * ONE)),
+        ];
+        let registry = crate::testing::registry(&pools);
+        let config = config(&[
+            ("DRY_RUN", "true"),
+            ("AMOUNT_IN", "1"),
+            ("MAX_STATE_AGE_BLOCKS", "1"),
+            ("TARGET_TOKENS", "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+        ]);
+        let executor = executor(&config, Asserter::new());
+        let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwap));
+        let update = update(2, &pools);
+
+        assert!(!is_stale(&update, &config, executor.provider()).await);
+        assert_eq!(
+            gas_price(&config, executor.provider()).await,
+            Some(config.gas.dry_run_gas_price)
+        );
+        handle_update(
+            update,
+            &registry,
+            &config,
+            &swap_settings(),
+            &encoder,
+            &executor,
+            None::<&Oracle>,
+            &mut HashSet::new(),
+        )
+        .await;
+
+        // Only buying BBB where it's cheap and selling it on the other pool pays
+        let calls = encoder.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].given_token, weth.address);
+        assert_eq!(calls[0].given_amount, BigUint::from(ONE));
+    }
+
+    #[tokio::test]
+    async fn a_pool_with_no_exit_price_is_never_encoded() {
+        let tokens = [token(0x11, "AAA", 18), token(0x22, "BBB", 18)];
+        // Lopsided enough to look like a spread, but there's no other pool or oracle to exit on
+        let pools = [("alone", &tokens[..], pool(1_000 * ONE, 2_000 * ONE))];
+        let registry = crate::testing::registry(&pools);
+
+        for gate in [&[][..], &[("MIN_PROFIT_WEI", "1")][..]] {
+            let config = config(&[&[("DRY_RUN", "true")][..], gate].concat());
+            let executor = executor(&config, Asserter::new());
+            let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwap));
+
+            handle_update(
+                update(2, &pools),
+                &registry,
+                &config,
+                &swap_settings(),
+                &encoder,
+                &executor,
+                None::<&Oracle>,
+                &mut HashSet::new(),
+            )
+            .await;
+
+            assert!(encoder.calls().is_empty(), "encoded with {gate:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn splits_use_the_best_quoting_pools_and_must_pay_for_their_gas() {
+        let aaa = token(0x11, "AAA", 18);
+        let weth = weth();
+        let tokens = [aaa.clone(), weth.clone()];
+        let pools = [
+            ("shallow", &tokens[..], pool(10 * ONE, 10 * ONE)),
+            ("deep_a", &tokens[..], pool(1_000 * ONE, 1_000 * ONE)),
+            ("deep_b", &tokens[..], pool(1_000 * ONE, 1_000 * ONE)),
+        ];
+        let registry = crate::testing::registry(&pools);
+        let config = config(&[("MAX_SPLIT_POOLS", "2")]);
+        let amount_in = BigUint::from(100 * ONE);
+        let single = pools[1].2.get_amount_out(amount_in.clone(), &aaa, &weth).unwrap();
+        let split = |gas_price| {
+            split_if_better(
+                &registry,
+                &config,
+                None::<&Oracle>,
+                |_: &str| false,
+                &aaa,
+                &weth,
+                &amount_in,
+                &single.amount,
+                &single.gas,
+                gas_price,
+            )
+        };
+
+        let (mut pool_ids, components, quote) =
+            split(1_000_000_000).await.expect("two deep pools beat one");
+        pool_ids.sort();
+        assert_eq!(pool_ids, ["deep_a", "deep_b"]);
+        let mut ids: Vec<_> = components.iter().map(|component| component.id.clone()).collect();
+        ids.sort();
+        assert_eq!(ids, [Bytes::from("deep_a".as_bytes()), Bytes::from("deep_b".as_bytes())]);
+        assert!(quote.amount_out > single.amount);
+
+        // A pool with a transaction in flight is left out of the split
+        let without_deep_b = split_if_better(
+            &registry,
+            &config,
+            None::<&Oracle>,
+            |pool_id: &str| pool_id == "deep_b",
+            &aaa,
+            &weth,
+            &amount_in,
+            &single.amount,
+            &single.gas,
+            1_000_000_000,
+        )
+        .await;
+        assert!(without_deep_b.is_none_or(|(pool_ids, _, _)| !pool_ids.contains(&"deep_b".to_string())));
+
+        // At 1M gwei the second swap's gas costs more WETH than splitting saves
+        assert!(quote.gas > single.gas);
+        assert!(split(1_000_000_000_000_000).await.is_none());
+    }
+}
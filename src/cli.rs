@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use alloy::transports::http::reqwest::Url;
+use clap::Parser;
+
+/// Command line flags. Each one overrides the matching environment / config file value.
+#[derive(Debug, Default, Parser)]
+#[command(version, about = "Tycho stream arbitrage bot")]
+pub struct Cli {
+    /// Decode the executeInteractions calldata in this hex file and exit
+    #[arg(long, value_name = "HEXFILE")]
+    pub inspect: Option<PathBuf>,
+
+    /// TOML config file [env: CONFIG_PATH]
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// JSON-RPC endpoint used for gas estimation, replaces RPC_URLS [env: RPC_URL]
+    #[arg(long)]
+    pub rpc_url: Option<Url>,
+
+    /// Chain to trade on: ethereum, base or unichain [env: CHAIN]
+    #[arg(long)]
+    pub chain: Option<String>,
+
+    /// Slippage tolerance in basis points [env: SLIPPAGE_BPS]
+    #[arg(long)]
+    pub slippage_bps: Option<u32>,
+
+    /// Build transactions without ever submitting them [env: DRY_RUN]
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// isTest flag sent to the executor contract: test or live [env: EXECUTION_MODE]
+    #[arg(long)]
+    pub execution_mode: Option<String>,
+
+    /// TVL above which pools are tracked, applied to every exchange [env: TVL_ADD_THRESHOLD]
+    #[arg(long)]
+    pub tvl_threshold: Option<f64>,
+
+    /// Sell token address to trade, repeat for several [env: TARGET_TOKENS]
+    #[arg(long = "target-token")]
+    pub target_tokens: Vec<String>,
+}
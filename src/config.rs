@@ -1,31 +1,1507 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alloy::primitives::Address;
+use alloy::signers::local::PrivateKeySigner;
 use alloy::transports::http::reqwest::Url;
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use num_bigint::BigUint;
+use tycho_execution::encoding::models::UserTransferType;
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::Chain;
+use tracing::warn;
+use tycho_simulation::tycho_common::models::token::Token;
+
+use crate::amount_in_strategy::AmountStrategy;
+use crate::cli::Cli;
+use crate::encoding::{ApproveStrategy, ExecutionMode};
+use crate::error::ConfigError;
+use crate::amounts::AmountInConfig;
+use crate::exchanges::{ExchangeConfig, SUPPORTED_EXCHANGES, V4_HOOKS_EXCHANGE};
+use crate::gas::GasConfig;
+use crate::preflight::PreflightConfig;
+use crate::price_oracle::OracleConfig;
+use crate::receipts::WatchConfig;
+use crate::replacement::ReplacementPolicy;
+use crate::simulation::SlotConfig;
+use crate::submitter::SubmissionConfig;
+
+const DEFAULT_SLIPPAGE_BPS: u32 = 50;
+const DEFAULT_MIN_SPREAD_BPS: u32 = 30;
+const DEFAULT_QUOTE_CONCURRENCY: usize = 8;
+const DEFAULT_TVL_ADD_THRESHOLD: f64 = 100.0;
+const DEFAULT_TVL_REMOVE_THRESHOLD: f64 = 90.0;
+const DEFAULT_EXCHANGES: &str = "uniswap_v4";
+const DEFAULT_STREAM_RETRY_BASE_DELAY_MS: u64 = 1_000;
+const DEFAULT_TOKENS_TTL_SECS: u64 = 6 * 60 * 60;
+const DEFAULT_MIN_TOKENS: usize = 1;
+const DEFAULT_PENDING_TX_TTL_SECS: u64 = 120;
+const DEFAULT_NONCE_RESYNC_SECS: u64 = 60;
+const DEFAULT_HEALTH_STALE_AFTER_SECS: u64 = 60;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const SUPPORTED_CHAINS: &str = "ethereum, base, unichain";
+
+/// Keys a config file may set, in their env var spelling.
+const KNOWN_KEYS: &[&str] = &[
+    "RPC_URL",
+    "RPC_URLS",
+    "CHAIN",
+    "TYCHO_URL",
+    "TYCHO_API_KEY",
+    "KEYSTORE_PATH",
+    "EXECUTOR_CONTRACT",
+    "OUR_CONTRACT",
+    "WALLET_ADDRESS",
+    "ARBITRAGE_WALLET",
+    "RECEIVER_ADDRESS",
+    "SLIPPAGE_BPS",
+    "PAIR_SLIPPAGE",
+    "USER_TRANSFER_TYPE",
+    "APPROVE_STRATEGY",
+    "TARGET_TOKENS",
+    "TOKEN_ALLOWLIST",
+    "COMPONENT_BLOCKLIST",
+    "AMOUNT_IN",
+    "AMOUNT_STRATEGY",
+    "POOL_LIMIT_DUST",
+    "DRY_RUN",
+    "SIMULATE_CALLS",
+    "SIMULATE_STATE_OVERRIDES",
+    "TOKEN_SLOTS",
+    "IS_TEST",
+    "EXECUTION_MODE",
+    "TVL_ADD_THRESHOLD",
+    "TVL_REMOVE_THRESHOLD",
+    "TVL_MAX",
+    "TVL_MIN",
+    "EXCHANGES",
+    "ENABLE_V4_HOOKS",
+    "V4_HOOK_ADDRESSES",
+    "MIN_PROFIT_WEI",
+    "MAX_ROUND_TRIP_LOSS_BPS",
+    "MIN_SPREAD_BPS",
+    "MAX_SPLIT_POOLS",
+    "MAX_GAS_UNITS",
+    "MAX_STATE_AGE_BLOCKS",
+    "QUOTE_CONCURRENCY",
+    "MIN_PROFIT_USD",
+    "MIN_PROFIT_BPS",
+    "PRICE_ORACLE",
+    "COINGECKO_API_KEY",
+    "STREAM_MAX_RETRIES",
+    "STREAM_RETRY_BASE_DELAY_MS",
+    "TOKENS_TTL_SECS",
+    "MIN_TOKENS",
+    "PENDING_TX_TTL_SECS",
+    "NONCE_RESYNC_SECS",
+    "METRICS_PORT",
+    "HEALTH_PORT",
+    "HEALTH_STALE_AFTER_SECS",
+    "SHUTDOWN_TIMEOUT_SECS",
+    "OPPORTUNITY_LOG",
+    "GAS_PRICING",
+    "MAX_FEE_PER_GAS_GWEI",
+    "MAX_PRIORITY_FEE_GWEI",
+    "BASE_FEE_MULTIPLIER",
+    "GAS_LIMIT_MULTIPLIER",
+    "PRIORITY_FEE_PERCENTILE",
+    "GAS_PRICE_CAP_GWEI",
+    "DRY_RUN_GAS_PRICE_GWEI",
+    "CONFIRMATIONS",
+    "STUCK_AFTER_BLOCKS",
+    "REPLACEMENT_FEE_BUMP_PERCENT",
+    "OPPORTUNITY_TTL_BLOCKS",
+    "PREFLIGHT_MODE",
+    "PREFLIGHT_INTERVAL_BLOCKS",
+    "MIN_ETH_BALANCE",
+    "MIN_TOKEN_BALANCE",
+    "SUBMISSION_MODE",
+    "PRIVATE_RPC_URL",
+    "BUNDLE_RELAY_URL",
+    "BUNDLE_BLOCKS",
+];
+/// Prefixes of per-token and per-exchange keys such as `AMOUNT_IN_WBTC`.
+const KNOWN_PREFIXES: &[&str] = &[
+    "AMOUNT_IN_",
+    "TVL_ADD_THRESHOLD_",
+    "TVL_REMOVE_THRESHOLD_",
+    "TVL_MAX_",
+    "TVL_MIN_",
+    "PRICE_USD_",
+    "TOKEN_SLOTS_",
+    "MIN_TOKEN_BALANCE_",
+];
+/// Keys that make a config file worth keeping private.
+const SECRET_KEYS: &[&str] = &["PRIVATE_KEY", "KEYSTORE_PASSWORD", "FLASHBOTS_SIGNING_KEY"];
+
+#[cfg(unix)]
+fn readable_by_others(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o077 != 0)
+}
+
+#[cfg(not(unix))]
+fn readable_by_others(_path: &Path) -> bool {
+    false
+}
+
+/// Flat `KEY -> value` view over an optional TOML file, with environment variables on top.
+/// File keys are matched case-insensitively and nested tables are joined with `_`, so
+/// `[tvl] add_threshold = 10` is the same setting as `TVL_ADD_THRESHOLD=10`.
+#[derive(Debug)]
+pub struct ConfigSource {
+    file: HashMap<String, String>,
+    use_env: bool,
+}
+
+impl ConfigSource {
+    pub fn env() -> Self {
+        Self {
+            file: HashMap::new(),
+            use_env: true,
+        }
+    }
+
+    pub fn file(path: &Path) -> Result<Self, ConfigError> {
+        let raw = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let table: toml::Table = raw.parse().map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let mut file = HashMap::new();
+        flatten_toml("", &toml::Value::Table(table), &mut file);
+
+        let secrets: Vec<_> = SECRET_KEYS
+            .iter()
+            .filter(|key| file.contains_key(**key))
+            .copied()
+            .collect();
+        if !secrets.is_empty() && readable_by_others(path) {
+            warn!(
+                file = %path.display(),
+                keys = %secrets.join(", "),
+                "Config file holds secrets but is readable by other users, consider chmod 600"
+            );
+        }
+
+        for key in file.keys() {
+            let known = KNOWN_KEYS.contains(&key.as_str())
+                || KNOWN_PREFIXES.iter().any(|prefix| key.starts_with(prefix));
+            if !known {
+                warn!(key = %key.to_lowercase(), file = %path.display(), "Unknown key in config file, ignoring");
+            }
+        }
+
+        Ok(Self {
+            file,
+            use_env: false,
+        })
+    }
+
+    pub fn with_env(self) -> Self {
+        Self {
+            use_env: true,
+            ..self
+        }
+    }
+
+    /// A source over `pairs` alone, ignoring the environment.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn from_pairs(pairs: &[(&str, &str)]) -> Self {
+        Self {
+            file: pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            use_env: false,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let from_env = if self.use_env {
+            std::env::var(key).ok()
+        } else {
+            None
+        };
+
+        from_env.or_else(|| self.file.get(key).cloned())
+    }
+
+    /// All keys starting with `prefix`, from both the file and the environment.
+    pub fn keys_with_prefix(&self, prefix: &str) -> BTreeSet<String> {
+        let env_keys: Vec<String> = if self.use_env {
+            std::env::vars().map(|(key, _)| key).collect()
+        } else {
+            Vec::new()
+        };
+
+        self.file
+            .keys()
+            .cloned()
+            .chain(env_keys)
+            .filter(|key| key.starts_with(prefix))
+            .collect()
+    }
+
+    fn parse<T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.get(key)
+            .map(|raw| {
+                raw.trim()
+                    .parse()
+                    .with_context(|| format!("Can't parse {key}={raw:?}"))
+            })
+            .transpose()
+    }
+
+    /// Reads a boolean flag, accepting `true/false`, `1/0` and `yes/no`.
+    fn parse_bool(&self, key: &str) -> Result<Option<bool>> {
+        let Some(raw) = self.get(key) else {
+            return Ok(None);
+        };
+
+        match raw.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" => Ok(Some(true)),
+            "false" | "0" | "no" | "" => Ok(Some(false)),
+            other => bail!("Can't parse {key}={other:?} as a boolean"),
+        }
+    }
+
+    /// Checks every required key up front so a single error lists all that are missing.
+    fn check_required(&self) -> Result<(), ConfigError> {
+        let mut missing: Vec<&str> = ["TYCHO_API_KEY"]
+            .into_iter()
+            .filter(|key| self.get(key).is_none())
+            .collect();
+        if self.get("EXECUTOR_CONTRACT").is_none() && self.get("OUR_CONTRACT").is_none() {
+            missing.push(
+                "EXECUTOR_CONTRACT or OUR_CONTRACT (builds before it was configurable used \
+                 0x6b94d3be850ece1736d8bface0e5bb69bf8e4139)",
+            );
+        }
+        if self.get("RPC_URL").is_none() && self.get("RPC_URLS").is_none() {
+            missing.push("RPC_URL or RPC_URLS");
+        }
+        if self.get("PRIVATE_KEY").is_none() && self.get("KEYSTORE_PATH").is_none() {
+            missing.push("PRIVATE_KEY or KEYSTORE_PATH");
+        }
+
+        if !missing.is_empty() {
+            return Err(ConfigError::Missing(missing.into_iter().map(String::from).collect()));
+        }
+        Ok(())
+    }
+}
+
+fn flatten_toml(prefix: &str, value: &toml::Value, out: &mut HashMap<String, String>) {
+    let key = prefix.to_uppercase();
+    match value {
+        toml::Value::Table(table) => {
+            for (name, value) in table {
+                let nested = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{prefix}_{name}")
+                };
+                flatten_toml(&nested, value, out);
+            }
+        }
+        toml::Value::Array(items) => {
+            let joined = items
+                .iter()
+                .map(toml_scalar)
+                .collect::<Vec<_>>()
+                .join(",");
+            out.insert(key, joined);
+        }
+        scalar => {
+            out.insert(key, toml_scalar(scalar));
+        }
+    }
+}
+
+fn toml_scalar(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct AppConfig {
-    pub rpc_url: Url,
+    /// JSON-RPC endpoints in failover order, from `RPC_URLS` or a single `RPC_URL`.
+    pub rpc_urls: Vec<Url>,
+    pub chain: Chain,
+    /// Bare Tycho indexer host, without a scheme.
+    pub tycho_url: String,
     pub tycho_api_key: String,
-    pub private_key: String,
+    pub signer: PrivateKeySigner,
+    /// Contract receiving the `executeInteractions` call. Required, there is no default
+    /// deployment: builds that hardcoded it used 0x6b94d3be850ece1736d8bface0e5bb69bf8e4139.
+    pub executor_contract: Address,
+    /// Sender of the transaction, derived from the private key when `None`.
+    pub wallet_address: Option<Address>,
+    /// Where swap proceeds are sent, e.g. a treasury. The signer's address when `None`.
+    pub receiver: Option<Address>,
+    pub slippage: Slippage,
+    /// Sell token addresses the bot trades. Empty means every pair is processed.
+    pub target_tokens: Vec<Bytes>,
+    /// Symbols or addresses from `TOKEN_ALLOWLIST`, `None` allows every token.
+    pub token_allowlist: Option<Vec<String>>,
+    /// Lowercased component ids from `COMPONENT_BLOCKLIST` that are never quoted.
+    pub component_blocklist: HashSet<String>,
+    pub amount_in: AmountInConfig,
+    /// Overrides `amount_in` when set, see `AMOUNT_STRATEGY`.
+    pub amount_strategy: Option<AmountStrategy>,
+    /// Pools whose sell limit is at or below this many base units are skipped.
+    pub pool_limit_dust: BigUint,
+    /// How the router pulls the sell token, shared by the encoder and the interactions.
+    pub user_transfer_type: UserTransferType,
+    /// `APPROVE_STRATEGY`: exact, unlimited or reset.
+    pub approve_strategy: ApproveStrategy,
+    pub dry_run: bool,
+    /// `eth_call` every transaction before gas estimation to surface revert reasons.
+    pub simulate_calls: bool,
+    /// Token storage layouts for simulating with a faked balance and allowance, `Some`
+    /// when `SIMULATE_STATE_OVERRIDES` is set.
+    pub state_overrides: Option<SlotConfig>,
+    /// `EXECUTION_MODE`, or `IS_TEST` when unset. Always `Test` in dry runs.
+    pub execution_mode: ExecutionMode,
+    /// Exchanges from `EXCHANGES`, each with its effective TVL thresholds.
+    pub exchanges: Vec<ExchangeConfig>,
+    /// Hook contracts accepted on `uniswap_v4_hooks` on top of the Euler hooks.
+    pub v4_hook_addresses: Vec<Bytes>,
+    /// Minimum net round-trip profit in sell token base units, `None` disables the gate.
+    pub min_profit_wei: Option<BigUint>,
+    /// Largest loss, in bps of `amount_in`, a sell -> buy -> sell quote may show before the
+    /// pool state is treated as broken and the swap skipped. `None` skips the check.
+    pub max_round_trip_loss_bps: Option<u32>,
+    /// Spot price gap, in bps, between two pools of a pair before the spread is quoted.
+    pub min_spread_bps: u32,
+    /// Most pools of a pair a single trade is split across, 1 disables splitting.
+    pub max_split_pools: usize,
+    /// Swaps whose RPC gas estimate exceeds this are rejected, `None` disables the ceiling.
+    pub max_gas_units: Option<u64>,
+    /// Updates more than this many blocks behind the chain head are not traded on,
+    /// `None` disables the check.
+    pub max_state_age_blocks: Option<u64>,
+    /// Most pairs quoted at once on blocking threads.
+    pub quote_concurrency: usize,
+    /// Minimum net round-trip profit in USD, valued through `price_oracle`.
+    pub min_profit_usd: Option<f64>,
+    /// Minimum net round-trip profit in bps of `amount_in`.
+    pub min_profit_bps: Option<u32>,
+    /// Source of USD prices for notionals and `min_profit_usd`, `None` disables USD valuation.
+    pub price_oracle: Option<OracleConfig>,
+    /// Reconnect attempts before giving up, `None` retries forever.
+    pub stream_max_retries: Option<u32>,
+    /// First reconnect delay, doubled on every further attempt.
+    pub stream_retry_base_delay: Duration,
+    /// How long tokens loaded from Tycho are reused across reconnects.
+    pub tokens_ttl: Duration,
+    /// Fewest tokens a Tycho token load must return, fewer means a wrong chain or a bad
+    /// response.
+    pub min_tokens: usize,
+    /// How long a submitted transaction blocks new ones for the same opportunity when
+    /// it isn't seen mined.
+    pub pending_tx_ttl: Duration,
+    /// Idle time after which the nonce is refetched, covering dropped transactions.
+    pub nonce_resync_after: Duration,
+    /// Port of the Prometheus `/metrics` endpoint, `None` disables the exporter.
+    pub metrics_port: Option<u16>,
+    /// Port of the `/health` and `/ready` endpoints, `None` disables them.
+    pub health_port: Option<u16>,
+    /// Time without a stream message after which `/health` reports unhealthy.
+    pub health_stale_after: Duration,
+    /// How long a shutdown waits for the update being handled, submissions included.
+    pub shutdown_timeout: Duration,
+    /// CSV file every evaluated opportunity is appended to, `None` disables it.
+    pub opportunity_log: Option<PathBuf>,
+    pub gas: GasConfig,
+    pub submission: SubmissionConfig,
+    pub watch: WatchConfig,
+    pub replacement: ReplacementPolicy,
+    pub preflight: PreflightConfig,
+}
+
+/// Pools are tracked once their TVL rises above `add` and dropped when it falls below `remove`,
+/// in the units Tycho reports TVL in.
+///
+/// - `add`: `TVL_ADD_THRESHOLD`, or its alias `TVL_MAX`, default 100.
+/// - `remove`: `TVL_REMOVE_THRESHOLD`, or its alias `TVL_MIN`, default 90 capped at `add`.
+///
+/// There are no separate `tvl_min`/`tvl_max` settings, the aliases set these same two
+/// thresholds and the full names win when both are given. Any of the four suffixed with an
+/// exchange, e.g. `TVL_MAX_UNISWAP_V4`, overrides that exchange only.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TvlThresholds {
+    pub remove: f64,
+    pub add: f64,
+}
+
+impl TvlThresholds {
+    fn from_source(source: &ConfigSource, suffix: &str, fallback: TvlThresholds) -> Result<Self> {
+        let add = source
+            .parse(&format!("TVL_ADD_THRESHOLD{suffix}"))?
+            .or(source.parse(&format!("TVL_MAX{suffix}"))?)
+            .unwrap_or(fallback.add);
+        let remove = source
+            .parse(&format!("TVL_REMOVE_THRESHOLD{suffix}"))?
+            .or(source.parse(&format!("TVL_MIN{suffix}"))?)
+            .unwrap_or(fallback.remove.min(add));
+        ensure!(
+            remove <= add,
+            "TVL_REMOVE_THRESHOLD{suffix} ({remove}) must not exceed TVL_ADD_THRESHOLD{suffix} ({add})"
+        );
+
+        Ok(Self { remove, add })
+    }
+}
+
+/// `SLIPPAGE_BPS` with per-pair overrides from `PAIR_SLIPPAGE`, e.g. `WBTC/WETH=30,USDC/USDT=5`.
+/// Pairs are matched by symbol, case-insensitively and in either direction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Slippage {
+    pub default_bps: u32,
+    pairs: HashMap<(String, String), u32>,
+}
+
+impl Slippage {
+    /// `default_bps` for every pair, without overrides.
+    pub fn flat(default_bps: u32) -> Self {
+        Self {
+            default_bps,
+            pairs: HashMap::new(),
+        }
+    }
+
+    fn from_source(source: &ConfigSource) -> Result<Self> {
+        let default_bps = source
+            .parse::<u32>("SLIPPAGE_BPS")?
+            .unwrap_or(DEFAULT_SLIPPAGE_BPS);
+        ensure!(
+            default_bps < 10_000,
+            "SLIPPAGE_BPS must be below 10000 (100%), got {}",
+            default_bps
+        );
+
+        let mut pairs = HashMap::new();
+        for entry in parse_list(&source.get("PAIR_SLIPPAGE").unwrap_or_default()) {
+            let (pair, bps) = entry
+                .split_once('=')
+                .with_context(|| format!("PAIR_SLIPPAGE entry {entry:?} must look like WBTC/WETH=30"))?;
+            let (a, b) = pair
+                .split_once('/')
+                .with_context(|| format!("PAIR_SLIPPAGE pair {pair:?} must look like WBTC/WETH"))?;
+            let bps: u32 = bps
+                .trim()
+                .parse()
+                .with_context(|| format!("Can't parse PAIR_SLIPPAGE bps for {pair:?}"))?;
+            ensure!(
+                bps < 10_000,
+                "PAIR_SLIPPAGE for {pair} must be below 10000 (100%), got {bps}"
+            );
+            pairs.insert(symbol_pair(a, b), bps);
+        }
+
+        Ok(Self { default_bps, pairs })
+    }
+
+    /// Slippage for a swap between `sell` and `buy`, the `PAIR_SLIPPAGE` entry when there is
+    /// one and `SLIPPAGE_BPS` otherwise.
+    pub fn for_pair(&self, sell: &Token, buy: &Token) -> u32 {
+        self.pairs
+            .get(&symbol_pair(&sell.symbol, &buy.symbol))
+            .copied()
+            .unwrap_or(self.default_bps)
+    }
+}
+
+fn symbol_pair(a: &str, b: &str) -> (String, String) {
+    let (a, b) = (a.trim().to_uppercase(), b.trim().to_uppercase());
+    if a <= b { (a, b) } else { (b, a) }
 }
 
 impl AppConfig {
-    pub fn from_env() -> Result<Self> {
+    pub fn from_env() -> Result<Self, ConfigError> {
         dotenv::dotenv().ok();
+        Self::from_source(&ConfigSource::env())
+    }
+
+    /// Reads every setting from a TOML file only, without the environment `load` layers on
+    /// top.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        Self::from_source(&ConfigSource::file(path)?)
+    }
+
+    /// Loads `config_path` (or `CONFIG_PATH`) when there is one, with environment variables
+    /// taking precedence over its values.
+    pub fn load(config_path: Option<&Path>) -> Result<Self, ConfigError> {
+        dotenv::dotenv().ok();
+
+        let path = config_path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var("CONFIG_PATH").ok().map(PathBuf::from));
+
+        match path {
+            Some(path) => Self::from_source(&ConfigSource::file(&path)?.with_env()),
+            None => Self::from_env(),
+        }
+    }
+
+    /// Applies command line overrides, giving the precedence CLI > env > file > default.
+    pub fn merge_cli(self, cli: &Cli) -> Result<Self, ConfigError> {
+        self.apply_cli(cli)
+            .map_err(|e| ConfigError::Invalid(format!("{e:#}")))
+    }
+
+    fn apply_cli(mut self, cli: &Cli) -> Result<Self> {
+        if let Some(rpc_url) = &cli.rpc_url {
+            self.rpc_urls = vec![rpc_url.clone()];
+        }
+
+        if let Some(chain) = &cli.chain {
+            let chain = parse_chain(chain)?;
+            // Follow the chain unless the Tycho host was set explicitly
+            if self.tycho_url == default_tycho_url(self.chain) {
+                self.tycho_url = default_tycho_url(chain).to_string();
+            }
+            self.chain = chain;
+        }
+
+        if let Some(slippage_bps) = cli.slippage_bps {
+            ensure!(
+                slippage_bps < 10_000,
+                "--slippage-bps must be below 10000 (100%), got {}",
+                slippage_bps
+            );
+            self.slippage.default_bps = slippage_bps;
+        }
+
+        if let Some(mode) = &cli.execution_mode {
+            self.execution_mode = mode.parse().context("Can't parse --execution-mode")?;
+        }
+
+        if cli.dry_run {
+            self.dry_run = true;
+            self.execution_mode = ExecutionMode::Test;
+        }
+
+        if let Some(threshold) = cli.tvl_threshold {
+            for exchange in &mut self.exchanges {
+                exchange.tvl = TvlThresholds {
+                    remove: exchange.tvl.remove.min(threshold),
+                    add: threshold,
+                };
+            }
+        }
+
+        if !cli.target_tokens.is_empty() {
+            self.target_tokens = parse_token_addresses("--target-token", &cli.target_tokens)?;
+        }
+
+        Ok(self)
+    }
+
+    pub fn from_source(source: &ConfigSource) -> Result<Self, ConfigError> {
+        source.check_required()?;
+        // Typed errors such as a keystore that won't decrypt pass through as they are
+        Self::parse(source).map_err(|e| {
+            e.downcast::<ConfigError>()
+                .unwrap_or_else(|e| ConfigError::Invalid(format!("{e:#}")))
+        })
+    }
 
-        let rpc_url = std::env::var("RPC_URL")
-            .context("RPC_URL not found in environment. Please add it to .env")?;
-        let rpc_url = Url::parse(&rpc_url).context("Can't parse RPC_URL")?;
+    fn parse(source: &ConfigSource) -> Result<Self> {
+        let rpc_urls = match source.get("RPC_URLS") {
+            Some(raw) => parse_list(&raw)
+                .iter()
+                .map(|url| {
+                    Url::parse(url).with_context(|| format!("Can't parse RPC_URLS entry {url:?}"))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => {
+                let rpc_url = source.get("RPC_URL").unwrap_or_default();
+                vec![Url::parse(&rpc_url).context("Can't parse RPC_URL")?]
+            }
+        };
+        ensure!(!rpc_urls.is_empty(), "RPC_URLS must list at least one endpoint");
 
-        let tycho_api_key = std::env::var("TYCHO_API_KEY")
-            .context("TYCHO_API_KEY not found in environment. Please add it to .env")?;
+        let chain = match source.get("CHAIN") {
+            Some(raw) => parse_chain(&raw)?,
+            None => Chain::Ethereum,
+        };
+        let tycho_url = match source.get("TYCHO_URL") {
+            Some(raw) => normalize_tycho_url(&raw)?,
+            None => default_tycho_url(chain).to_string(),
+        };
 
-        let private_key = std::env::var("PRIVATE_KEY")
-            .context("PRIVATE_KEY not found in environment. Please add it to .env")?;
+        let tycho_api_key = source.get("TYCHO_API_KEY").unwrap_or_default();
+
+        let signer = load_signer(source)?;
+
+        // OUR_CONTRACT and ARBITRAGE_WALLET carry the names of the constants they replaced,
+        // EXECUTOR_CONTRACT and WALLET_ADDRESS win when both are set
+        let executor_contract = match source.get("EXECUTOR_CONTRACT") {
+            Some(raw) => parse_address("EXECUTOR_CONTRACT", &raw)?,
+            None => parse_address("OUR_CONTRACT", &source.get("OUR_CONTRACT").unwrap_or_default())?,
+        };
+
+        let wallet_address = match (source.get("WALLET_ADDRESS"), source.get("ARBITRAGE_WALLET")) {
+            (Some(raw), _) => Some(parse_address("WALLET_ADDRESS", &raw)?),
+            (None, Some(raw)) => Some(parse_address("ARBITRAGE_WALLET", &raw)?),
+            (None, None) => None,
+        };
+
+        let receiver = source
+            .get("RECEIVER_ADDRESS")
+            .map(|raw| parse_address("RECEIVER_ADDRESS", &raw))
+            .transpose()?;
+
+        let slippage = Slippage::from_source(source)?;
+
+        let target_tokens = parse_token_addresses(
+            "TARGET_TOKENS",
+            &parse_list(&source.get("TARGET_TOKENS").unwrap_or_default()),
+        )?;
+
+        let token_allowlist = source
+            .get("TOKEN_ALLOWLIST")
+            .map(|raw| parse_list(&raw))
+            .filter(|list| !list.is_empty());
+
+        let component_blocklist = parse_list(&source.get("COMPONENT_BLOCKLIST").unwrap_or_default())
+            .into_iter()
+            .map(|id| id.to_lowercase())
+            .collect();
+
+        let amount_in = AmountInConfig::from_source(source)?;
+
+        let pool_limit_dust = source.parse("POOL_LIMIT_DUST")?.unwrap_or_default();
+
+        let amount_strategy = source
+            .get("AMOUNT_STRATEGY")
+            .map(|raw| raw.parse::<AmountStrategy>().context("Can't parse AMOUNT_STRATEGY"))
+            .transpose()?;
+
+        let user_transfer_type = source
+            .get("USER_TRANSFER_TYPE")
+            .map(|raw| parse_user_transfer_type(&raw))
+            .transpose()?
+            .unwrap_or(UserTransferType::TransferFrom);
+        // Permit2 takes the permit owner from msg.sender, which is the executor contract
+        // running the batch, so a permit signed by our key never authorizes its tokens
+        ensure!(
+            user_transfer_type != UserTransferType::TransferFromPermit2,
+            "USER_TRANSFER_TYPE=permit2 is not supported through EXECUTOR_CONTRACT: Permit2 \
+             would check the permit against the executor contract, not the signing key. \
+             Use transfer_from or transfer"
+        );
+        let approve_strategy = source
+            .get("APPROVE_STRATEGY")
+            .map(|raw| raw.parse().context("Can't parse APPROVE_STRATEGY"))
+            .transpose()?
+            .unwrap_or_default();
+
+        let dry_run = source.parse_bool("DRY_RUN")?.unwrap_or(false);
+        let simulate_calls = source.parse_bool("SIMULATE_CALLS")?.unwrap_or(false);
+        let state_overrides = if source.parse_bool("SIMULATE_STATE_OVERRIDES")?.unwrap_or(false) {
+            Some(SlotConfig::from_source(source)?)
+        } else {
+            None
+        };
+        let execution_mode = match source.get("EXECUTION_MODE") {
+            Some(raw) => raw.parse().context("Can't parse EXECUTION_MODE")?,
+            None if source.parse_bool("IS_TEST")?.unwrap_or(true) => ExecutionMode::Test,
+            None => ExecutionMode::Live,
+        };
+        // Dry runs never produce live calldata, whatever EXECUTION_MODE says
+        let execution_mode = if dry_run { ExecutionMode::Test } else { execution_mode };
+
+        let tvl = TvlThresholds::from_source(
+            source,
+            "",
+            TvlThresholds {
+                remove: DEFAULT_TVL_REMOVE_THRESHOLD,
+                add: DEFAULT_TVL_ADD_THRESHOLD,
+            },
+        )?;
+        let mut tvl_overrides = HashMap::new();
+        const TVL_PREFIXES: [&str; 4] =
+            ["TVL_ADD_THRESHOLD_", "TVL_REMOVE_THRESHOLD_", "TVL_MAX_", "TVL_MIN_"];
+        let override_keys = TVL_PREFIXES
+            .iter()
+            .flat_map(|prefix| source.keys_with_prefix(prefix));
+        for key in override_keys {
+            let exchange = TVL_PREFIXES
+                .iter()
+                .find_map(|prefix| key.strip_prefix(prefix));
+            if let Some(exchange) = exchange
+                && !tvl_overrides.contains_key(exchange)
+            {
+                let thresholds =
+                    TvlThresholds::from_source(source, &format!("_{exchange}"), tvl)?;
+                tvl_overrides.insert(exchange.to_string(), thresholds);
+            }
+        }
+
+        let mut exchange_names = parse_list(
+            &source
+                .get("EXCHANGES")
+                .unwrap_or_else(|| DEFAULT_EXCHANGES.to_string()),
+        );
+        if source.parse_bool("ENABLE_V4_HOOKS")?.unwrap_or(false)
+            && !exchange_names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(V4_HOOKS_EXCHANGE))
+        {
+            exchange_names.push(V4_HOOKS_EXCHANGE.to_string());
+        }
+        let exchanges = exchange_names
+            .into_iter()
+            .map(|name| {
+                let name = name.to_lowercase();
+                ensure!(
+                    SUPPORTED_EXCHANGES.contains(&name.as_str()),
+                    "Unsupported exchange {name:?} in EXCHANGES, supported values: {}",
+                    SUPPORTED_EXCHANGES.join(", ")
+                );
+                let tvl = tvl_overrides.get(&env_key(&name)).copied().unwrap_or(tvl);
+                Ok(ExchangeConfig { name, tvl })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(!exchanges.is_empty(), "EXCHANGES must list at least one exchange");
+
+        let v4_hook_addresses = parse_list(&source.get("V4_HOOK_ADDRESSES").unwrap_or_default())
+            .iter()
+            .map(|raw| {
+                parse_address("V4_HOOK_ADDRESSES", raw).map(|addr| Bytes::from(addr.as_slice()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let min_profit_wei = source.parse("MIN_PROFIT_WEI")?;
+        let max_round_trip_loss_bps = source.parse::<u32>("MAX_ROUND_TRIP_LOSS_BPS")?;
+        if let Some(max_loss_bps) = max_round_trip_loss_bps {
+            ensure!(
+                max_loss_bps <= 10_000,
+                "MAX_ROUND_TRIP_LOSS_BPS must be at most 10000 (100%), got {}",
+                max_loss_bps
+            );
+        }
+
+        let min_spread_bps = source
+            .parse("MIN_SPREAD_BPS")?
+            .unwrap_or(DEFAULT_MIN_SPREAD_BPS);
+
+        let max_split_pools = source.parse("MAX_SPLIT_POOLS")?.unwrap_or(1);
+        ensure!(max_split_pools >= 1, "MAX_SPLIT_POOLS must be at least 1");
+
+        let max_gas_units = source.parse("MAX_GAS_UNITS")?;
+        let max_state_age_blocks = source.parse("MAX_STATE_AGE_BLOCKS")?;
+
+        let quote_concurrency = source
+            .parse("QUOTE_CONCURRENCY")?
+            .unwrap_or(DEFAULT_QUOTE_CONCURRENCY);
+        ensure!(quote_concurrency >= 1, "QUOTE_CONCURRENCY must be at least 1");
+
+        let price_oracle = OracleConfig::from_source(source)?;
+        let min_profit_usd = source.parse::<f64>("MIN_PROFIT_USD")?;
+        ensure!(
+            min_profit_usd.is_none() || price_oracle.is_some(),
+            "MIN_PROFIT_USD requires PRICE_ORACLE or PRICE_USD_* prices"
+        );
+
+        let min_profit_bps = source.parse("MIN_PROFIT_BPS")?;
+
+        let stream_max_retries = source.parse("STREAM_MAX_RETRIES")?;
+        let stream_retry_base_delay = Duration::from_millis(
+            source
+                .parse("STREAM_RETRY_BASE_DELAY_MS")?
+                .unwrap_or(DEFAULT_STREAM_RETRY_BASE_DELAY_MS),
+        );
+        let tokens_ttl = Duration::from_secs(
+            source
+                .parse("TOKENS_TTL_SECS")?
+                .unwrap_or(DEFAULT_TOKENS_TTL_SECS),
+        );
+        let min_tokens = source.parse("MIN_TOKENS")?.unwrap_or(DEFAULT_MIN_TOKENS);
+        let pending_tx_ttl = Duration::from_secs(
+            source
+                .parse("PENDING_TX_TTL_SECS")?
+                .unwrap_or(DEFAULT_PENDING_TX_TTL_SECS),
+        );
+        let nonce_resync_after = Duration::from_secs(
+            source
+                .parse("NONCE_RESYNC_SECS")?
+                .unwrap_or(DEFAULT_NONCE_RESYNC_SECS),
+        );
+
+        let metrics_port = source.parse("METRICS_PORT")?;
+        let health_port = source.parse("HEALTH_PORT")?;
+        let health_stale_after = Duration::from_secs(
+            source
+                .parse("HEALTH_STALE_AFTER_SECS")?
+                .unwrap_or(DEFAULT_HEALTH_STALE_AFTER_SECS),
+        );
+        let shutdown_timeout = Duration::from_secs(
+            source
+                .parse("SHUTDOWN_TIMEOUT_SECS")?
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+        );
+        let opportunity_log = source.get("OPPORTUNITY_LOG").map(PathBuf::from);
+
+        let gas = GasConfig::from_source(source)?;
+        let submission = SubmissionConfig::from_source(source)?;
+        let watch = WatchConfig::from_source(source)?;
+        let replacement = ReplacementPolicy::from_source(source)?;
+        // A transaction is only reported stuck STUCK_AFTER_BLOCKS after it was sent, which is
+        // at or after the block its trade was quoted on
+        ensure!(
+            watch.stuck_after_blocks < replacement.opportunity_ttl_blocks,
+            "STUCK_AFTER_BLOCKS ({}) must be below OPPORTUNITY_TTL_BLOCKS ({}), or stuck transactions are always cancelled and never sped up",
+            watch.stuck_after_blocks,
+            replacement.opportunity_ttl_blocks
+        );
+        let preflight = PreflightConfig::from_source(source)?;
 
         Ok(Self {
-            rpc_url,
+            rpc_urls,
+            chain,
+            tycho_url,
             tycho_api_key,
-            private_key,
+            signer,
+            executor_contract,
+            wallet_address,
+            receiver,
+            slippage,
+            target_tokens,
+            token_allowlist,
+            component_blocklist,
+            amount_in,
+            amount_strategy,
+            pool_limit_dust,
+            user_transfer_type,
+            approve_strategy,
+            dry_run,
+            simulate_calls,
+            state_overrides,
+            execution_mode,
+            exchanges,
+            v4_hook_addresses,
+            min_profit_wei,
+            max_round_trip_loss_bps,
+            min_spread_bps,
+            max_split_pools,
+            max_gas_units,
+            max_state_age_blocks,
+            quote_concurrency,
+            min_profit_usd,
+            min_profit_bps,
+            price_oracle,
+            stream_max_retries,
+            stream_retry_base_delay,
+            tokens_ttl,
+            min_tokens,
+            pending_tx_ttl,
+            nonce_resync_after,
+            metrics_port,
+            health_port,
+            health_stale_after,
+            shutdown_timeout,
+            opportunity_log,
+            gas,
+            submission,
+            watch,
+            replacement,
+            preflight,
+        })
+    }
+
+    /// Whether any profit threshold is configured, see `profit::check_profit`.
+    pub fn has_profit_gate(&self) -> bool {
+        self.min_profit_wei.is_some() || self.min_profit_bps.is_some() || self.min_profit_usd.is_some()
+    }
+
+    pub fn is_target_token(&self, token: &Token) -> bool {
+        let targeted = self.target_tokens.is_empty() || self.target_tokens.contains(&token.address);
+        let allowed = match &self.token_allowlist {
+            Some(list) => list.iter().any(|entry| token_matches(token, entry)),
+            None => true,
+        };
+
+        targeted && allowed
+    }
+
+    pub fn is_blocked_component(&self, id: &str) -> bool {
+        self.component_blocklist.contains(&id.to_lowercase())
+    }
+}
+
+/// Builds the signer from `PRIVATE_KEY` or from an encrypted `KEYSTORE_PATH`.
+/// The password comes from `KEYSTORE_PASSWORD` or is prompted for on the terminal.
+/// Errors never include the key or password.
+fn load_signer(source: &ConfigSource) -> Result<PrivateKeySigner, ConfigError> {
+    if let Some(private_key) = source.get("PRIVATE_KEY") {
+        return private_key.trim().parse::<PrivateKeySigner>().map_err(|_| {
+            ConfigError::Invalid("PRIVATE_KEY is not a valid hex encoded secp256k1 key".into())
+        });
+    }
+
+    let Some(keystore_path) = source.get("KEYSTORE_PATH") else {
+        return Err(ConfigError::Missing(vec!["PRIVATE_KEY or KEYSTORE_PATH".into()]));
+    };
+
+    let password = match source.get("KEYSTORE_PASSWORD") {
+        Some(password) => password,
+        None => rpassword::prompt_password(format!("Password for keystore {keystore_path}: "))
+            .map_err(|e| {
+                ConfigError::Invalid(format!("Can't read keystore password from the terminal: {e}"))
+            })?,
+    };
+
+    PrivateKeySigner::decrypt_keystore(&keystore_path, password)
+        .map_err(|_| ConfigError::Keystore { path: keystore_path.into() })
+}
+
+/// Parses a hex address, enforcing the EIP-55 checksum when the input is mixed case.
+pub fn parse_address(name: &str, raw: &str) -> Result<Address> {
+    let raw = raw.trim();
+    let digits = raw.strip_prefix("0x").unwrap_or(raw);
+    ensure!(
+        digits.len() == 40,
+        "{name} must be 20 bytes (40 hex characters), got {} characters",
+        digits.len()
+    );
+    ensure!(
+        digits.chars().all(|c| c.is_ascii_hexdigit()),
+        "{name} contains non-hex characters"
+    );
+
+    let prefixed = format!("0x{digits}");
+    let mixed_case = digits.chars().any(|c| c.is_ascii_lowercase())
+        && digits.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case {
+        Address::parse_checksummed(&prefixed, None)
+            .map_err(|_| anyhow!("{name} has an invalid EIP-55 checksum"))
+    } else {
+        prefixed
+            .parse()
+            .with_context(|| format!("Can't parse {name} as an address"))
+    }
+}
+
+/// Entries starting with `0x` are compared against the address, anything else against the symbol.
+fn token_matches(token: &Token, entry: &str) -> bool {
+    if entry.starts_with("0x") {
+        entry
+            .parse::<Bytes>()
+            .is_ok_and(|address| address == token.address)
+    } else {
+        token.symbol.eq_ignore_ascii_case(entry)
+    }
+}
+
+pub fn parse_chain(raw: &str) -> Result<Chain> {
+    match raw.trim().to_lowercase().as_str() {
+        "ethereum" | "mainnet" | "eth" => Ok(Chain::Ethereum),
+        "base" => Ok(Chain::Base),
+        "unichain" => Ok(Chain::Unichain),
+        other => bail!("Unsupported CHAIN {other:?}, supported values: {SUPPORTED_CHAINS}"),
+    }
+}
+
+/// `transfer_from` has the router pull an approved balance, `transfer` expects the tokens
+/// already sent to the router and `permit2` goes through the Permit2 contract. The bot
+/// itself rejects `permit2`, see `AppConfig::from_source`.
+pub fn parse_user_transfer_type(raw: &str) -> Result<UserTransferType> {
+    match raw.trim().to_lowercase().replace('_', "").as_str() {
+        "transferfrom" => Ok(UserTransferType::TransferFrom),
+        "transfer" => Ok(UserTransferType::None),
+        "permit2" => Ok(UserTransferType::TransferFromPermit2),
+        other => bail!("Unknown USER_TRANSFER_TYPE {other:?}, expected transfer_from, transfer or permit2"),
+    }
+}
+
+fn default_tycho_url(chain: Chain) -> &'static str {
+    match chain {
+        Chain::Base => "tycho-base-beta.propellerheads.xyz",
+        Chain::Unichain => "tycho-unichain-beta.propellerheads.xyz",
+        _ => "tycho-beta.propellerheads.xyz",
+    }
+}
+
+/// The stream builder expects a bare host, so `https://` style prefixes and trailing
+/// slashes are stripped. Anything with a path is rejected.
+pub fn normalize_tycho_url(raw: &str) -> Result<String> {
+    let raw = raw.trim();
+    let host = match raw.split_once("://") {
+        Some(("http" | "https" | "ws" | "wss", rest)) => rest,
+        Some((scheme, _)) => bail!("Unsupported scheme {scheme:?} in TYCHO_URL"),
+        None => raw,
+    };
+    let host = host.trim_end_matches('/');
+
+    let parsed = Url::parse(&format!("https://{host}"))
+        .with_context(|| format!("Can't parse TYCHO_URL {raw:?}"))?;
+    ensure!(
+        parsed.host_str().is_some() && parsed.path() == "/",
+        "TYCHO_URL must be a bare host such as tycho-beta.propellerheads.xyz, got {raw:?}"
+    );
+
+    Ok(host.to_string())
+}
+
+/// Turns a name like `vm:curve` into the `VM_CURVE` form used in env var suffixes.
+pub fn env_key(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+/// Splits a comma-separated env value, trimming whitespace and dropping empty entries.
+/// Token addresses from a `name` list, each checked like `parse_address` and named in its error.
+fn parse_token_addresses(name: &str, entries: &[String]) -> Result<Vec<Bytes>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let address = parse_address(&format!("{name} entry {entry:?}"), entry)?;
+            Ok(Bytes::from(address.as_slice()))
         })
+        .collect()
+}
+
+pub fn parse_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The required keys plus `extra`, later entries winning.
+    fn source(extra: &[(&str, &str)]) -> ConfigSource {
+        let mut pairs = vec![
+            ("TYCHO_API_KEY", "key"),
+            ("RPC_URL", "http://localhost:8545"),
+            ("EXECUTOR_CONTRACT", "0x00000000000000000000000000000000000e0e0e"),
+            (
+                "PRIVATE_KEY",
+                "0x1111111111111111111111111111111111111111111111111111111111111111",
+            ),
+        ];
+        pairs.extend_from_slice(extra);
+        ConfigSource::from_pairs(&pairs)
+    }
+
+    fn config_error(extra: &[(&str, &str)]) -> String {
+        format!("{:#}", AppConfig::from_source(&source(extra)).unwrap_err())
+    }
+
+    #[test]
+    fn target_tokens_are_a_comma_separated_address_list() {
+        let targets = |raw: &str| {
+            AppConfig::from_source(&source(&[("TARGET_TOKENS", raw)])).unwrap().target_tokens
+        };
+        let aaa = Bytes::from([0xaa; 20].as_slice());
+        let bbb = Bytes::from([0xbb; 20].as_slice());
+        let aaa_hex = format!("0x{}", "aa".repeat(20));
+        let bbb_hex = format!("0x{}", "bb".repeat(20));
+
+        assert_eq!(targets(&format!(" {aaa_hex} ,\t{bbb_hex}  ")), [aaa.clone(), bbb.clone()]);
+        assert_eq!(targets(&format!("{aaa_hex},")), [aaa.clone()]);
+        assert_eq!(targets(&format!("{aaa_hex},,{bbb_hex}")), [aaa, bbb]);
+        // Empty means every token is a target
+        assert!(targets("").is_empty());
+        assert!(targets(" , ").is_empty());
+        assert!(AppConfig::from_source(&source(&[])).unwrap().target_tokens.is_empty());
+    }
+
+    #[test]
+    fn a_bad_target_token_is_named_in_the_error() {
+        let aaa_hex = format!("0x{}", "aa".repeat(20));
+        let target_error = |raw: String| config_error(&[("TARGET_TOKENS", raw.as_str())]);
+
+        let error = target_error(format!("{aaa_hex}, WETH"));
+        assert!(error.contains(r#"TARGET_TOKENS entry "WETH""#), "{error}");
+
+        let error = target_error(format!("0x1234,{aaa_hex}"));
+        assert!(error.contains(r#"TARGET_TOKENS entry "0x1234" must be 20 bytes"#), "{error}");
+
+        let not_hex = format!("0x{}", "zz".repeat(20));
+        let error = target_error(format!("{aaa_hex},{not_hex}"));
+        assert!(error.contains(&format!("TARGET_TOKENS entry {not_hex:?}")), "{error}");
+        assert!(error.contains("non-hex characters"), "{error}");
+    }
+
+    #[test]
+    fn parse_address_explains_bad_hex_and_length() {
+        let short = parse_address("EXECUTOR_CONTRACT", "0x1234").unwrap_err().to_string();
+        assert!(short.contains("EXECUTOR_CONTRACT must be 20 bytes"), "{short}");
+        assert!(short.contains("got 4 characters"), "{short}");
+
+        let long = parse_address("EXECUTOR_CONTRACT", &format!("0x{}", "ab".repeat(21)))
+            .unwrap_err()
+            .to_string();
+        assert!(long.contains("got 42 characters"), "{long}");
+
+        let not_hex = parse_address("WALLET_ADDRESS", &format!("0x{}", "zz".repeat(20)))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(not_hex, "WALLET_ADDRESS contains non-hex characters");
+
+        // Mixed case is taken as EIP-55 and must checksum
+        let bad_checksum =
+            parse_address("WALLET_ADDRESS", "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756CC2")
+                .unwrap_err()
+                .to_string();
+        assert!(bad_checksum.contains("checksum"), "{bad_checksum}");
+
+        assert_eq!(
+            parse_address("WALLET_ADDRESS", " c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2 ").unwrap(),
+            parse_address("WALLET_ADDRESS", "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap()
+        );
+    }
+
+    #[test]
+    fn bad_executor_and_wallet_addresses_fail_config_load() {
+        assert!(
+            config_error(&[("EXECUTOR_CONTRACT", "0xdeadbeef")])
+                .contains("EXECUTOR_CONTRACT must be 20 bytes")
+        );
+        assert!(
+            config_error(&[("WALLET_ADDRESS", "not an address at all, forty chars long!")])
+                .contains("WALLET_ADDRESS")
+        );
+    }
+
+    #[test]
+    fn missing_executor_contract_names_the_old_default() {
+        let source = ConfigSource::from_pairs(&[
+            ("TYCHO_API_KEY", "key"),
+            ("RPC_URL", "http://localhost:8545"),
+            (
+                "PRIVATE_KEY",
+                "0x1111111111111111111111111111111111111111111111111111111111111111",
+            ),
+        ]);
+        let error = AppConfig::from_source(&source).unwrap_err().to_string();
+        assert!(error.contains("EXECUTOR_CONTRACT"), "{error}");
+        assert!(error.contains("0x6b94d3be850ece1736d8bface0e5bb69bf8e4139"), "{error}");
+    }
+
+    #[test]
+    fn tycho_url_defaults_per_chain_and_accepts_a_self_hosted_host() {
+        let config = AppConfig::from_source(&source(&[])).unwrap();
+        assert_eq!(config.tycho_url, "tycho-beta.propellerheads.xyz");
+
+        let config = AppConfig::from_source(&source(&[("CHAIN", "base")])).unwrap();
+        assert_eq!(config.tycho_url, "tycho-base-beta.propellerheads.xyz");
+
+        let config =
+            AppConfig::from_source(&source(&[("TYCHO_URL", "https://tycho.internal:4242/")]))
+                .unwrap();
+        assert_eq!(config.tycho_url, "tycho.internal:4242");
+
+        assert!(normalize_tycho_url("tycho.internal/v1").is_err());
+        assert!(normalize_tycho_url("ftp://tycho.internal").is_err());
+    }
+
+    #[test]
+    fn permit2_is_rejected_with_the_executor_contract() {
+        let error = AppConfig::from_source(&source(&[("USER_TRANSFER_TYPE", "permit2")]))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("permit2"), "{error}");
+
+        let config = AppConfig::from_source(&source(&[("USER_TRANSFER_TYPE", "transfer")])).unwrap();
+        assert_eq!(config.user_transfer_type, UserTransferType::None);
+    }
+
+    #[test]
+    fn round_trip_loss_check_is_opt_in() {
+        let config = AppConfig::from_source(&source(&[])).unwrap();
+        assert_eq!(config.max_round_trip_loss_bps, None);
+
+        let config =
+            AppConfig::from_source(&source(&[("MAX_ROUND_TRIP_LOSS_BPS", "200")])).unwrap();
+        assert_eq!(config.max_round_trip_loss_bps, Some(200));
+
+        assert!(
+            config_error(&[("MAX_ROUND_TRIP_LOSS_BPS", "10001")])
+                .contains("MAX_ROUND_TRIP_LOSS_BPS must be at most 10000")
+        );
+    }
+
+    #[test]
+    fn invalid_private_keys_fail_config_load_without_echoing_the_key() {
+        let bad_keys = [
+            "0x1234",
+            "0x11111111111111111111111111111111111111111111111111111111111111zz",
+            // Zero isn't a valid secp256k1 scalar
+            "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "0x2222222222222222222222222222222222222222222222222222222222222222ff",
+        ];
+        for key in bad_keys {
+            let error = config_error(&[("PRIVATE_KEY", key)]);
+            assert!(
+                error.contains("PRIVATE_KEY is not a valid hex encoded secp256k1 key"),
+                "{error}"
+            );
+            let digits = key.trim_start_matches("0x");
+            assert!(!error.contains(digits), "error leaks the key: {error}");
+        }
+    }
+
+    /// Web3 secret storage v3 for the key `0x22..22`, encrypted under `KEYSTORE_PASSWORD`
+    /// with a cheap pbkdf2 so the test stays fast.
+    const KEYSTORE: &str = r#"{"crypto":{"cipher":"aes-128-ctr","cipherparams":{"iv":"101112131415161718191a1b1c1d1e1f"},"ciphertext":"4dfea7dea237a92d425936028b869c29d82814d2a28a4a2d914daa6c70d996a5","kdf":"pbkdf2","kdfparams":{"c":1024,"dklen":32,"prf":"hmac-sha256","salt":"000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"},"mac":"d580c80128290a485926f3263abb36d6a67c809548c303f1a53aa9f1fe0b2a4a"},"id":"8b4c7d1e-2f3a-4b5c-9d6e-7f8a9b0c1d2e","version":3}"#;
+    const KEYSTORE_PASSWORD: &str = "correct horse battery staple";
+
+    /// Loads the config with a signer from `KEYSTORE` written to a temporary directory.
+    fn from_keystore(name: &str, password: &str) -> (Result<AppConfig, ConfigError>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("eulerswap-keystore-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{name}.json"));
+        std::fs::write(&path, KEYSTORE).unwrap();
+
+        let loaded = AppConfig::from_source(&ConfigSource::from_pairs(&[
+            ("TYCHO_API_KEY", "key"),
+            ("RPC_URL", "http://localhost:8545"),
+            ("EXECUTOR_CONTRACT", "0x00000000000000000000000000000000000e0e0e"),
+            ("KEYSTORE_PATH", path.to_str().unwrap()),
+            ("KEYSTORE_PASSWORD", password),
+        ]));
+        std::fs::remove_file(&path).unwrap();
+        (loaded, path)
+    }
+
+    #[test]
+    fn keystores_decrypt_to_their_key() {
+        let (loaded, _) = from_keystore("right", KEYSTORE_PASSWORD);
+
+        let expected = PrivateKeySigner::from_bytes(&[0x22; 32].into()).unwrap();
+        assert_eq!(loaded.unwrap().signer.address(), expected.address());
+    }
+
+    #[test]
+    fn a_wrong_keystore_password_is_a_typed_error_without_the_secret() {
+        let password = "not the right password";
+        let (loaded, path) = from_keystore("wrong", password);
+
+        let Err(error) = loaded else {
+            panic!("decrypted with the wrong password");
+        };
+        assert!(
+            matches!(&error, ConfigError::Keystore { path: failed } if *failed == path),
+            "{error:?}"
+        );
+        for shown in [error.to_string(), format!("{error:?}")] {
+            assert!(shown.contains("check the file and password"), "{shown}");
+            assert!(!shown.contains(password), "error leaks the password: {shown}");
+            assert!(!shown.contains(&"22".repeat(32)), "error leaks the key: {shown}");
+        }
+    }
+
+    #[test]
+    fn private_keys_may_be_kept_in_the_config_file() {
+        let path = std::env::temp_dir().join(format!("config-secrets-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+tycho_api_key = "key"
+rpc_url = "http://localhost:8545"
+executor_contract = "0x00000000000000000000000000000000000e0e0e"
+private_key = "0x1111111111111111111111111111111111111111111111111111111111111111"
+"#,
+        )
+        .unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            assert!(readable_by_others(&path));
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+            assert!(!readable_by_others(&path));
+        }
+
+        let config = AppConfig::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let expected = PrivateKeySigner::from_bytes(&alloy::primitives::B256::repeat_byte(0x11))
+            .unwrap()
+            .address();
+        assert_eq!(config.unwrap().signer.address(), expected);
+    }
+
+    #[test]
+    fn cli_flags_beat_the_environment_which_beats_the_file() {
+        use clap::Parser;
+
+        let path =
+            std::env::temp_dir().join(format!("config-precedence-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+tycho_api_key = "key"
+rpc_url = "http://file.example:8545"
+executor_contract = "0x00000000000000000000000000000000000e0e0e"
+private_key = "0x1111111111111111111111111111111111111111111111111111111111111111"
+slippage_bps = 10
+execution_mode = "live"
+"#,
+        )
+        .unwrap();
+        // No other test reads the environment, so these can't leak into them
+        unsafe {
+            std::env::set_var("SLIPPAGE_BPS", "20");
+            std::env::set_var("EXECUTION_MODE", "test");
+        }
+        let loaded = ConfigSource::file(&path)
+            .and_then(|source| AppConfig::from_source(&source.with_env()));
+        unsafe {
+            std::env::remove_var("SLIPPAGE_BPS");
+            std::env::remove_var("EXECUTION_MODE");
+        }
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        // Env over file, and the file over the defaults
+        assert_eq!(loaded.slippage.default_bps, 20);
+        assert_eq!(loaded.execution_mode, ExecutionMode::Test);
+        assert_eq!(loaded.rpc_urls[0].as_str(), "http://file.example:8545/");
+        assert_eq!(loaded.chain, Chain::Ethereum);
+
+        // Without flags the CLI changes nothing
+        let untouched = loaded.clone().merge_cli(&Cli::parse_from(["eulerswap"])).unwrap();
+        assert_eq!(untouched.slippage.default_bps, 20);
+        assert_eq!(untouched.rpc_urls, loaded.rpc_urls);
+
+        let cli = Cli::parse_from([
+            "eulerswap",
+            "--slippage-bps",
+            "30",
+            "--rpc-url",
+            "http://cli.example:8545",
+        ]);
+        let merged = loaded.merge_cli(&cli).unwrap();
+        assert_eq!(merged.slippage.default_bps, 30);
+        assert_eq!(merged.rpc_urls[0].as_str(), "http://cli.example:8545/");
+        // Flags that weren't passed keep the env value
+        assert_eq!(merged.execution_mode, ExecutionMode::Test);
+    }
+
+    #[test]
+    fn tvl_min_and_max_are_aliases_for_the_remove_and_add_thresholds() {
+        let thresholds = |extra: &[(&str, &str)]| {
+            let mut pairs = vec![("EXCHANGES", "uniswap_v4,uniswap_v2")];
+            pairs.extend_from_slice(extra);
+            AppConfig::from_source(&source(&pairs))
+                .unwrap()
+                .exchanges
+                .into_iter()
+                .map(|exchange| (exchange.name, (exchange.tvl.remove, exchange.tvl.add)))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let defaults = thresholds(&[]);
+        assert_eq!(defaults["uniswap_v4"], (90.0, 100.0));
+        assert_eq!(defaults["uniswap_v2"], (90.0, 100.0));
+
+        let aliased = thresholds(&[("TVL_MIN", "5"), ("TVL_MAX", "50")]);
+        assert_eq!(aliased["uniswap_v4"], (5.0, 50.0));
+        let named = thresholds(&[("TVL_REMOVE_THRESHOLD", "5"), ("TVL_ADD_THRESHOLD", "50")]);
+        assert_eq!(named, aliased);
+
+        // The full names win over the aliases
+        let both =
+            thresholds(&[("TVL_MIN", "5"), ("TVL_REMOVE_THRESHOLD", "7"), ("TVL_MAX", "50")]);
+        assert_eq!(both["uniswap_v4"], (7.0, 50.0));
+
+        // Suffixed aliases only move their exchange
+        let per_exchange = thresholds(&[("TVL_MAX", "50"), ("TVL_MAX_UNISWAP_V2", "500")]);
+        assert_eq!(per_exchange["uniswap_v4"], (50.0, 50.0));
+        assert_eq!(per_exchange["uniswap_v2"], (50.0, 500.0));
+        let per_exchange = thresholds(&[("TVL_MIN_UNISWAP_V2", "1")]);
+        assert_eq!(per_exchange["uniswap_v2"], (1.0, 100.0));
+        assert_eq!(per_exchange["uniswap_v4"], (90.0, 100.0));
+
+        let error = config_error(&[("TVL_MIN", "60"), ("TVL_MAX", "50")]);
+        assert!(error.contains("TVL_REMOVE_THRESHOLD (60) must not exceed"), "{error}");
+    }
+
+    #[test]
+    fn pair_slippage_overrides_the_default_in_either_direction() {
+        let config = AppConfig::from_source(&source(&[
+            ("SLIPPAGE_BPS", "50"),
+            ("PAIR_SLIPPAGE", "wbtc/WETH=30, USDC/USDT=5"),
+        ]))
+        .unwrap();
+        let token = |byte, symbol: &str| crate::testing::token(byte, symbol, 18);
+        let (wbtc, weth, usdc, usdt) =
+            (token(0x22, "WBTC"), token(0xc0, "WETH"), token(0xa0, "USDC"), token(0xd0, "USDT"));
+
+        assert_eq!(config.slippage.for_pair(&wbtc, &weth), 30);
+        assert_eq!(config.slippage.for_pair(&weth, &wbtc), 30);
+        assert_eq!(config.slippage.for_pair(&usdt, &usdc), 5);
+        // Pairs without an override, even sharing a token with one, take SLIPPAGE_BPS
+        assert_eq!(config.slippage.for_pair(&wbtc, &usdc), 50);
+
+        let defaults = AppConfig::from_source(&source(&[("PAIR_SLIPPAGE", "USDC/USDT=5")])).unwrap();
+        assert_eq!(defaults.slippage.for_pair(&wbtc, &weth), DEFAULT_SLIPPAGE_BPS);
+        assert_eq!(defaults.slippage.for_pair(&usdc, &usdt), 5);
+
+        assert!(config_error(&[("PAIR_SLIPPAGE", "WBTC-WETH=30")]).contains("must look like WBTC/WETH"));
+        assert!(config_error(&[("PAIR_SLIPPAGE", "WBTC/WETH=10000")]).contains("must be below 10000"));
+    }
+
+    #[test]
+    fn stuck_transactions_are_reported_while_they_can_still_be_sped_up() {
+        let defaults = AppConfig::from_source(&source(&[])).unwrap();
+        assert!(defaults.watch.stuck_after_blocks < defaults.replacement.opportunity_ttl_blocks);
+
+        let error = config_error(&[("STUCK_AFTER_BLOCKS", "10"), ("OPPORTUNITY_TTL_BLOCKS", "10")]);
+        assert!(error.contains("STUCK_AFTER_BLOCKS (10) must be below OPPORTUNITY_TTL_BLOCKS (10)"), "{error}");
+        assert!(AppConfig::from_source(&source(&[("STUCK_AFTER_BLOCKS", "10"), ("OPPORTUNITY_TTL_BLOCKS", "11")])).is_ok());
+    }
+
+    #[test]
+    fn load_failures_are_typed() {
+        let missing =
+            AppConfig::from_source(&ConfigSource::from_pairs(&[("RPC_URL", "http://localhost:8545")]));
+        let Err(ConfigError::Missing(keys)) = missing else {
+            panic!("expected missing keys, got {missing:?}");
+        };
+        assert_eq!(keys[0], "TYCHO_API_KEY");
+        assert!(!keys.iter().any(|key| key.contains("RPC_URL")), "{keys:?}");
+
+        let path =
+            std::env::temp_dir().join(format!("eulerswap-missing-{}.toml", std::process::id()));
+        assert!(matches!(AppConfig::from_file(&path), Err(ConfigError::Read { .. })));
+
+        let path =
+            std::env::temp_dir().join(format!("eulerswap-broken-{}.toml", std::process::id()));
+        std::fs::write(&path, "rpc_url = ").unwrap();
+        let broken = AppConfig::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(broken, Err(ConfigError::Parse { .. })), "{broken:?}");
+
+        assert!(matches!(
+            AppConfig::from_source(&source(&[("SLIPPAGE_BPS", "lots")])),
+            Err(ConfigError::Invalid(_))
+        ));
     }
 }
@@ -3,7 +3,11 @@ use alloy::primitives::{address, Address};
 
 #[allow(dead_code)]
 pub const EULER_SWAP_CONTRACT_ADDRESS: Address = address!("0xD3a349EE0A21eA0A7E9513ac236ae614b5FD513E");
-#[allow(dead_code)]
-pub const OUR_CONTRACT: Address = address!("0x6b94d3be850ece1736d8bface0e5bb69bf8e4139");
-#[allow(dead_code)]
-pub static ARBITRAGE_WALLET_ADDRESS: Address = address!("0xECDDB7f4390105AA4B247Ddc9598A2739E3eDBD7");
+/// Sentinel address Tycho uses for the chain's native token (ETH on mainnet).
+pub const NATIVE_ETH_ADDRESS: Address = address!("0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE");
+
+pub const WETH_ETHEREUM: Address = address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+/// WETH predeploy shared by OP stack chains such as Base and Unichain.
+pub const WETH_OP_STACK: Address = address!("0x4200000000000000000000000000000000000006");
+/// Canonical Permit2 deployment, the same address on every chain.
+pub const PERMIT2_ADDRESS: Address = address!("0x000000000022D473030F116dDEE9F6B43aC78BA3");
@@ -1,62 +1,621 @@
+use std::str::FromStr;
+
+use alloy::hex;
 use alloy::primitives::{Address, Bytes as AlloyBytes, Keccak256, U256};
 use alloy::sol;
-use alloy::sol_types::SolCall;
-use anyhow::Result;
+use alloy::sol_types::{SolCall, SolValue};
+
+use crate::error::{EncodingError, StateErrors};
+
+const PERMIT2_SINGLE: &str = "((address,uint160,uint48,uint48),address,uint256)";
+
+/// Tycho router entry points the encoder can produce calldata for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterFunction {
+    SingleSwap,
+    SingleSwapPermit2,
+    SequentialSwap,
+    SequentialSwapPermit2,
+    SplitSwap,
+    SplitSwapPermit2,
+}
+
+impl RouterFunction {
+    pub const ALL: [Self; 6] = [
+        Self::SingleSwap,
+        Self::SingleSwapPermit2,
+        Self::SequentialSwap,
+        Self::SequentialSwapPermit2,
+        Self::SplitSwap,
+        Self::SplitSwapPermit2,
+    ];
+
+    /// The exact signature the router encoder emits for this function.
+    pub fn signature(self) -> String {
+        match self {
+            Self::SingleSwap => {
+                "singleSwap(uint256,address,address,uint256,bool,bool,address,bool,bytes)"
+                    .to_string()
+            }
+            Self::SingleSwapPermit2 => format!(
+                "singleSwapPermit2(uint256,address,address,uint256,bool,bool,address,{PERMIT2_SINGLE},bytes,bytes)"
+            ),
+            Self::SequentialSwap => {
+                "sequentialSwap(uint256,address,address,uint256,bool,bool,address,bool,bytes)"
+                    .to_string()
+            }
+            Self::SequentialSwapPermit2 => format!(
+                "sequentialSwapPermit2(uint256,address,address,uint256,bool,bool,address,{PERMIT2_SINGLE},bytes,bytes)"
+            ),
+            Self::SplitSwap => {
+                "splitSwap(uint256,address,address,uint256,bool,bool,uint256,address,bool,bytes)"
+                    .to_string()
+            }
+            Self::SplitSwapPermit2 => format!(
+                "splitSwapPermit2(uint256,address,address,uint256,bool,bool,uint256,address,{PERMIT2_SINGLE},bytes,bytes)"
+            ),
+        }
+    }
+
+    pub fn is_permit2(self) -> bool {
+        matches!(
+            self,
+            Self::SingleSwapPermit2 | Self::SequentialSwapPermit2 | Self::SplitSwapPermit2
+        )
+    }
+
+    /// The `transferFrom` argument of the non-Permit2 functions, read from its head word.
+    pub fn transfer_from_flag(self, calldata: &[u8]) -> Option<bool> {
+        let index = match self {
+            Self::SingleSwap | Self::SequentialSwap => 7,
+            Self::SplitSwap => 8,
+            _ => return None,
+        };
+        let word = calldata.get(4 + 32 * index..4 + 32 * (index + 1))?;
+        Some(word[31] != 0)
+    }
+
+    /// Identifies the function a router calldata calls from its first four bytes.
+    pub fn from_calldata(calldata: &[u8]) -> Result<Self, StateErrors> {
+        let selector = calldata.get(..4).ok_or_else(|| {
+            StateErrors::UnsupportedRouterFunction(format!(
+                "router calldata is {} bytes, shorter than a selector",
+                calldata.len()
+            ))
+        })?;
+
+        Self::ALL
+            .into_iter()
+            .find(|function| function_selector(&function.signature()) == selector)
+            .ok_or_else(|| {
+                StateErrors::UnsupportedRouterFunction(format!("selector 0x{}", hex::encode(selector)))
+            })
+    }
+}
+
+impl FromStr for RouterFunction {
+    type Err = StateErrors;
+
+    /// Parses a full signature, so `splitSwapPermit2(...)` never passes for `splitSwap`.
+    fn from_str(signature: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|function| function.signature() == signature.trim())
+            .ok_or_else(|| StateErrors::UnsupportedRouterFunction(signature.to_string()))
+    }
+}
+
+sol! {
+    /// The router functions that pull the sell token with `transferFrom` or expect it sent
+    /// ahead, see `permit2.rs` for the Permit2 ones.
+    interface ITychoRouter {
+        function singleSwap(
+            uint256 amountIn,
+            address tokenIn,
+            address tokenOut,
+            uint256 minAmountOut,
+            bool wrapEth,
+            bool unwrapEth,
+            address receiver,
+            bool transferFrom,
+            bytes swaps
+        ) external payable returns (uint256);
+
+        function sequentialSwap(
+            uint256 amountIn,
+            address tokenIn,
+            address tokenOut,
+            uint256 minAmountOut,
+            bool wrapEth,
+            bool unwrapEth,
+            address receiver,
+            bool transferFrom,
+            bytes swaps
+        ) external payable returns (uint256);
+
+        function splitSwap(
+            uint256 amountIn,
+            address tokenIn,
+            address tokenOut,
+            uint256 minAmountOut,
+            bool wrapEth,
+            bool unwrapEth,
+            uint256 nTokens,
+            address receiver,
+            bool transferFrom,
+            bytes swaps
+        ) external payable returns (uint256);
+    }
+}
+
+sol! {
+    interface IERC20 {
+        function approve(address spender, uint256 amount) external returns (bool);
+        function transfer(address to, uint256 amount) external returns (bool);
+        function balanceOf(address owner) external view returns (uint256);
+        function allowance(address owner, address spender) external view returns (uint256);
+    }
+}
+
+pub fn erc20_approve(spender: Address, amount: U256) -> Vec<u8> {
+    IERC20::approveCall { spender, amount }.abi_encode()
+}
 
-pub fn encode_input(selector: &str, mut encoded_args: Vec<u8>) -> Vec<u8> {
+pub fn erc20_transfer(to: Address, amount: U256) -> Vec<u8> {
+    IERC20::transferCall { to, amount }.abi_encode()
+}
+
+/// Calldata for an ad-hoc `signature` with no `sol!` definition. `args` are encoded as
+/// function parameters, so no offset word precedes them whatever their types.
+pub fn encode_input<T: SolValue>(signature: &str, args: &T) -> Result<Vec<u8>, EncodingError> {
+    if signature.trim().is_empty() {
+        return Err(EncodingError::UnsupportedFunctionSignature(
+            "function signature is empty".to_string(),
+        ));
+    }
+
+    let mut call_data = function_selector(signature).to_vec();
+    call_data.extend(args.abi_encode_params());
+
+    Ok(call_data)
+}
+
+/// First four bytes of `keccak256(signature)`.
+pub fn function_selector(signature: &str) -> [u8; 4] {
     let mut hasher = Keccak256::new();
-    hasher.update(selector.as_bytes());
-    let selector_bytes = &hasher.finalize()[..4];
-    let mut call_data = selector_bytes.to_vec();
+    hasher.update(signature.as_bytes());
+    let hash = hasher.finalize();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+sol! {
+    /// One call made by the executor contract.
+    #[derive(Debug, PartialEq, Eq)]
+    struct Data {
+        address target;
+        uint256 value;
+        bytes callData;
+    }
+
+    function executeInteractions(Data[] interactions, address tokenAddress, uint8 isTest) external payable;
+}
+
+/// Value of the `isTest` flag passed to `executeInteractions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExecutionMode {
+    Live = 0,
+    Test = 1,
+}
+
+impl ExecutionMode {
+    pub fn flag(self) -> u8 {
+        self as u8
+    }
+}
+
+impl TryFrom<u8> for ExecutionMode {
+    type Error = EncodingError;
+
+    fn try_from(flag: u8) -> Result<Self, Self::Error> {
+        match flag {
+            0 => Ok(Self::Live),
+            1 => Ok(Self::Test),
+            other => Err(EncodingError::AbiEncode(format!("unknown isTest flag {other}"))),
+        }
+    }
+}
+
+impl FromStr for ExecutionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> anyhow::Result<Self> {
+        match raw.trim().to_lowercase().as_str() {
+            "live" => Ok(Self::Live),
+            "test" => Ok(Self::Test),
+            other => anyhow::bail!("Unknown execution mode {other:?}, expected test or live"),
+        }
+    }
+}
+
+/// How the executor contract approves the sell token before the router call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApproveStrategy {
+    /// Approve exactly the amount sold.
+    #[default]
+    Exact,
+    /// Approve `U256::MAX` once, later trades then skip the approval.
+    Unlimited,
+    /// Approve 0 before the amount, for USDT-style tokens that revert on a nonzero to
+    /// nonzero approval.
+    Reset,
+}
+
+impl FromStr for ApproveStrategy {
+    type Err = anyhow::Error;
 
-    // Remove extra prefix if present (32 bytes for dynamic data)
-    // Alloy encoding is including a prefix for dynamic data indicating the offset or length
-    // but at this point we don't want that
-    if encoded_args.len() > 32
-        && encoded_args[..32]
-            == [0u8; 31]
-                .into_iter()
-                .chain([32].to_vec())
-                .collect::<Vec<u8>>()
-    {
-        encoded_args = encoded_args[32..].to_vec();
+    fn from_str(raw: &str) -> anyhow::Result<Self> {
+        match raw.trim().to_lowercase().as_str() {
+            "exact" => Ok(Self::Exact),
+            "unlimited" => Ok(Self::Unlimited),
+            "reset" => Ok(Self::Reset),
+            other => anyhow::bail!("Unknown approve strategy {other:?}, expected exact, unlimited or reset"),
+        }
     }
+}
 
-    call_data.extend(encoded_args);
-    call_data
+/// Calls for one `executeInteractions` transaction, run by the executor contract in the
+/// order they were added.
+#[derive(Debug, Default)]
+pub struct InteractionBatch {
+    interactions: Vec<Data>,
 }
 
+impl InteractionBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_call(mut self, target: Address, value: U256, calldata: Vec<u8>) -> Self {
+        self.interactions.push(Data {
+            target,
+            value,
+            callData: AlloyBytes::from(calldata),
+        });
+        self
+    }
+
+    pub fn add_approval(self, token: Address, spender: Address, amount: U256) -> Self {
+        self.add_call(token, U256::ZERO, erc20_approve(spender, amount))
+    }
+
+    /// The approval calls `strategy` needs for `spender` to pull `amount`.
+    pub fn add_approvals(
+        self,
+        token: Address,
+        spender: Address,
+        amount: U256,
+        strategy: ApproveStrategy,
+    ) -> Self {
+        match strategy {
+            ApproveStrategy::Exact => self.add_approval(token, spender, amount),
+            ApproveStrategy::Unlimited => self.add_approval(token, spender, U256::MAX),
+            ApproveStrategy::Reset => self
+                .add_approval(token, spender, U256::ZERO)
+                .add_approval(token, spender, amount),
+        }
+    }
+
+    /// Drops the `approve(spender, _)` calls on `token`, once the allowance is known to
+    /// cover the trade.
+    pub fn without_approvals(mut self, token: Address, spender: Address) -> Self {
+        self.interactions.retain(|interaction| {
+            let approves_spender = interaction.target == token
+                && IERC20::approveCall::abi_decode(&interaction.callData)
+                    .is_ok_and(|call| call.spender == spender);
+            !approves_spender
+        });
+        self
+    }
+
+    pub fn build(self, token_address: Address, mode: ExecutionMode) -> Vec<u8> {
+        executeInteractionsCall::new((self.interactions, token_address, mode.flag())).abi_encode()
+    }
+}
+
+/// The usual batch: approvals of `token_address` for `approval`'s spender as `strategy`
+/// requires when there is one, then the router call. `value` is forwarded to the router
+/// call for native ETH input.
 pub fn create_multitrade_calldata(
     token_address: Address,
-    executor_address: Address,
-    approve_calldata: Vec<u8>,
+    router_address: Address,
+    approval: Option<(Address, U256)>,
+    strategy: ApproveStrategy,
     swap_calldata: Vec<u8>,
-) -> Result<Vec<u8>> {
-    sol!(
-        struct Data {
-            address target;
-            uint256 value;
-            bytes callData;
-        }
-       #[sol(rpc)]
-       function executeInteractions(Data[] interactions, address tokenAddress, uint8 isTest) external payable;
-    );
-
-    let interactions = vec![
-        Data {
-            target: token_address,
-            value: U256::ZERO,
-            callData: AlloyBytes::from(approve_calldata),
-        },
-        Data {
-            target: executor_address,
-            value: U256::ZERO,
-            callData: AlloyBytes::from(swap_calldata),
-        },
-    ];
+    value: U256,
+    mode: ExecutionMode,
+) -> Vec<u8> {
+    let mut batch = InteractionBatch::new();
+    if let Some((spender, amount)) = approval {
+        batch = batch.add_approvals(token_address, spender, amount, strategy);
+    }
+    batch
+        .add_call(router_address, value, swap_calldata)
+        .build(token_address, mode)
+}
+
+/// One call of a decoded `executeInteractions` batch.
+pub type Interaction = Data;
+
+/// Everything an `executeInteractions` calldata carries, from `decode_multitrade_calldata`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MultitradeCall {
+    pub interactions: Vec<Interaction>,
+    pub token_address: Address,
+    pub mode: ExecutionMode,
+}
+
+impl From<MultitradeCall> for InteractionBatch {
+    fn from(call: MultitradeCall) -> Self {
+        Self {
+            interactions: call.interactions,
+        }
+    }
+}
 
-    let args = executeInteractionsCall::new((interactions, token_address, 1u8));
-    let encoded_args = args.abi_encode();
+/// Reverses `InteractionBatch::build`. Malformed input is an error, never a panic.
+pub fn decode_multitrade_calldata(calldata: &[u8]) -> Result<MultitradeCall, EncodingError> {
+    let call = executeInteractionsCall::abi_decode(calldata)
+        .map_err(|e| EncodingError::AbiEncode(format!("not executeInteractions calldata: {e}")))?;
+
+    Ok(MultitradeCall {
+        interactions: call.interactions,
+        token_address: call.tokenAddress,
+        mode: ExecutionMode::try_from(call.isTest)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, keccak256};
+
+    use super::*;
+
+    sol! {
+        function take(bytes data);
+        function pair(uint256 amount, address owner);
+        function ping();
+    }
+
+    #[test]
+    fn encode_input_rejects_an_empty_signature() {
+        assert!(matches!(
+            encode_input(" ", &(U256::from(1),)),
+            Err(EncodingError::UnsupportedFunctionSignature(_))
+        ));
+    }
 
-    Ok(encoded_args)
+    #[test]
+    fn encode_input_lays_out_arguments_as_function_parameters() {
+        let data = AlloyBytes::from(vec![0xde, 0xad, 0xbe, 0xef]);
+        let owner = address!("0x00000000000000000000000000000000000e0e0e");
+
+        // A lone dynamic argument keeps its head offset, as in any call, and nothing more
+        let dynamic = encode_input("take(bytes)", &(data.clone(),)).unwrap();
+        assert_eq!(dynamic, takeCall { data: data.clone() }.abi_encode());
+        assert_eq!(dynamic.len(), 4 + 32 * 3);
+        assert_eq!(U256::from_be_slice(&dynamic[4..36]), U256::from(32));
+
+        // Static arguments sit in place, with no offset word at all
+        let static_args = encode_input("pair(uint256,address)", &(U256::from(7), owner)).unwrap();
+        assert_eq!(
+            static_args,
+            pairCall { amount: U256::from(7), owner }.abi_encode()
+        );
+        assert_eq!(static_args.len(), 4 + 32 * 2);
+
+        let no_args = encode_input("ping()", &()).unwrap();
+        assert_eq!(no_args, pingCall {}.abi_encode());
+        assert_eq!(no_args.len(), 4);
+    }
+
+    #[test]
+    fn encode_input_agrees_with_the_router_calls() {
+        // A small xorshift so every run checks the same spread of values
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..64 {
+            let amount_in = U256::from(next()) << (next() % 192) as usize;
+            let min_amount_out = U256::from(next() % 64);
+            let token_in = Address::repeat_byte(next() as u8);
+            let token_out = Address::repeat_byte(next() as u8);
+            let receiver = Address::repeat_byte(next() as u8);
+            let (wrap_eth, unwrap_eth, transfer_from) = (next() % 2 == 0, next() % 2 == 0, next() % 2 == 0);
+            let swaps = AlloyBytes::from(vec![next() as u8; (next() % 100) as usize]);
+            let n_tokens = U256::from(next() % 8);
+
+            let single = encode_input(
+                &RouterFunction::SingleSwap.signature(),
+                &(
+                    amount_in, token_in, token_out, min_amount_out, wrap_eth, unwrap_eth, receiver,
+                    transfer_from, swaps.clone(),
+                ),
+            )
+            .unwrap();
+            assert_eq!(
+                single,
+                ITychoRouter::singleSwapCall {
+                    amountIn: amount_in,
+                    tokenIn: token_in,
+                    tokenOut: token_out,
+                    minAmountOut: min_amount_out,
+                    wrapEth: wrap_eth,
+                    unwrapEth: unwrap_eth,
+                    receiver,
+                    transferFrom: transfer_from,
+                    swaps: swaps.clone(),
+                }
+                .abi_encode()
+            );
+
+            let sequential = encode_input(
+                &RouterFunction::SequentialSwap.signature(),
+                &(
+                    amount_in, token_in, token_out, min_amount_out, wrap_eth, unwrap_eth, receiver,
+                    transfer_from, swaps.clone(),
+                ),
+            )
+            .unwrap();
+            assert_eq!(
+                sequential,
+                ITychoRouter::sequentialSwapCall {
+                    amountIn: amount_in,
+                    tokenIn: token_in,
+                    tokenOut: token_out,
+                    minAmountOut: min_amount_out,
+                    wrapEth: wrap_eth,
+                    unwrapEth: unwrap_eth,
+                    receiver,
+                    transferFrom: transfer_from,
+                    swaps: swaps.clone(),
+                }
+                .abi_encode()
+            );
+
+            let split = encode_input(
+                &RouterFunction::SplitSwap.signature(),
+                &(
+                    amount_in, token_in, token_out, min_amount_out, wrap_eth, unwrap_eth, n_tokens,
+                    receiver, transfer_from, swaps.clone(),
+                ),
+            )
+            .unwrap();
+            assert_eq!(
+                split,
+                ITychoRouter::splitSwapCall {
+                    amountIn: amount_in,
+                    tokenIn: token_in,
+                    tokenOut: token_out,
+                    minAmountOut: min_amount_out,
+                    wrapEth: wrap_eth,
+                    unwrapEth: unwrap_eth,
+                    nTokens: n_tokens,
+                    receiver,
+                    transferFrom: transfer_from,
+                    swaps,
+                }
+                .abi_encode()
+            );
+        }
+    }
+
+    #[test]
+    fn a_leading_amount_of_32_is_kept() {
+        // 32 is also what a dynamic argument's offset word holds, it must not be mistaken for one
+        let spender = Address::repeat_byte(0x42);
+        let calldata = encode_input("approve(address,uint256)", &(spender, U256::from(32))).unwrap();
+        assert_eq!(calldata, erc20_approve(spender, U256::from(32)));
+
+        let calldata = encode_input("transfer(uint256,bytes)", &(U256::from(32), AlloyBytes::new())).unwrap();
+        assert_eq!(calldata.len(), 4 + 32 * 3);
+        assert_eq!(U256::from_be_slice(&calldata[4..36]), U256::from(32));
+        assert_eq!(U256::from_be_slice(&calldata[36..68]), U256::from(64));
+    }
+
+    #[test]
+    fn execution_mode_only_changes_the_is_test_word() {
+        // Selector, then the interactions offset and tokenAddress words before isTest
+        const IS_TEST_BYTE: usize = 4 + 32 * 2 + 31;
+        let token = Address::repeat_byte(0x11);
+        let router = Address::repeat_byte(0x22);
+        let calldata = |mode| {
+            create_multitrade_calldata(
+                token,
+                router,
+                Some((router, U256::from(1_000u32))),
+                ApproveStrategy::Exact,
+                vec![0xab; 4],
+                U256::ZERO,
+                mode,
+            )
+        };
+
+        let (test, live) = (calldata(ExecutionMode::Test), calldata(ExecutionMode::Live));
+
+        assert_eq!(test.len(), live.len());
+        assert_eq!(test[IS_TEST_BYTE], 1);
+        assert_eq!(live[IS_TEST_BYTE], 0);
+        let differing: Vec<usize> = (0..test.len()).filter(|&i| test[i] != live[i]).collect();
+        assert_eq!(differing, [IS_TEST_BYTE]);
+        assert!(test[4 + 64..IS_TEST_BYTE].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn function_selector_is_the_head_of_the_signature_hash() {
+        let signature = "transfer(address,uint256)";
+        assert_eq!(function_selector(signature), keccak256(signature)[..4]);
+        assert_eq!(function_selector(signature), [0xa9, 0x05, 0x9c, 0xbb]);
+        assert_eq!(function_selector(signature), IERC20::transferCall::SELECTOR);
+    }
+
+    #[test]
+    fn every_router_function_matches_the_router_abi() {
+        use crate::permit2::ITychoRouterPermit2;
+
+        let abi = [
+            (RouterFunction::SingleSwap, ITychoRouter::singleSwapCall::SIGNATURE),
+            (RouterFunction::SequentialSwap, ITychoRouter::sequentialSwapCall::SIGNATURE),
+            (RouterFunction::SplitSwap, ITychoRouter::splitSwapCall::SIGNATURE),
+            (
+                RouterFunction::SingleSwapPermit2,
+                ITychoRouterPermit2::singleSwapPermit2Call::SIGNATURE,
+            ),
+            (
+                RouterFunction::SequentialSwapPermit2,
+                ITychoRouterPermit2::sequentialSwapPermit2Call::SIGNATURE,
+            ),
+            (
+                RouterFunction::SplitSwapPermit2,
+                ITychoRouterPermit2::splitSwapPermit2Call::SIGNATURE,
+            ),
+        ];
+        assert_eq!(abi.len(), RouterFunction::ALL.len());
+
+        for (function, signature) in abi {
+            assert_eq!(function.signature(), signature);
+            assert_eq!(signature.parse::<RouterFunction>().unwrap(), function);
+            let selector = function_selector(signature);
+            let mut calldata = selector.to_vec();
+            calldata.extend([0u8; 32]);
+            assert_eq!(RouterFunction::from_calldata(&calldata).unwrap(), function);
+        }
+    }
+
+    #[test]
+    fn unknown_router_functions_are_reported_as_such() {
+        let unknown = "swap(uint256,bytes)".parse::<RouterFunction>().unwrap_err();
+        assert!(matches!(unknown, StateErrors::UnsupportedRouterFunction(ref s) if s == "swap(uint256,bytes)"));
+
+        let calldata = erc20_transfer(Address::ZERO, U256::from(1));
+        let unknown = RouterFunction::from_calldata(&calldata).unwrap_err();
+        assert!(matches!(unknown, StateErrors::UnsupportedRouterFunction(_)));
+        assert!(matches!(EncodingError::from(unknown), EncodingError::UnsupportedRouterFunction(_)));
+    }
+
+    #[test]
+    fn short_router_calldata_is_an_error_not_a_panic() {
+        for calldata in [&[][..], &[0x5c, 0x4b][..], &[0x5c, 0x4b, 0x63][..]] {
+            let error = RouterFunction::from_calldata(calldata).unwrap_err();
+            assert!(
+                error.to_string().contains("shorter than a selector"),
+                "{error}"
+            );
+        }
+    }
 }
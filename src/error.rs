@@ -1,9 +1,117 @@
+use std::path::PathBuf;
 
+use alloy::primitives::{Bytes, U256};
+use alloy::transports::TransportError;
 use thiserror::Error;
+use tycho_execution::encoding::errors::EncodingError as TychoEncodingError;
 use tycho_simulation::tycho_core::simulation::errors::SimulationError;
 
+/// Why the configuration can't be loaded.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Can't read config file {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Can't parse config file {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("Missing required configuration: {}", .0.join(", "))]
+    Missing(Vec<String>),
+    /// Wrong password or a damaged file, never with the password or key.
+    #[error("Can't decrypt keystore {}, check the file and password", path.display())]
+    Keystore { path: PathBuf },
+    /// A value that doesn't parse or fails validation, with the reason chain.
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Why `run` stopped other than through a shutdown signal.
+#[derive(Error, Debug)]
+pub enum RunError {
+    #[error("Can't load tokens from Tycho: {0}")]
+    Tokens(String),
+    #[error("Can't build the router encoder: {0}")]
+    Encoder(#[from] TychoEncodingError),
+    #[error("Can't set up the RPC connection: {0}")]
+    Provider(String),
+    #[error("Can't set up transaction submission: {0}")]
+    Submission(String),
+    #[error(transparent)]
+    Executor(#[from] ExecutorError),
+    #[error("{0}")]
+    Preflight(String),
+    #[error("Can't start the {service}: {reason}")]
+    Service { service: &'static str, reason: String },
+    #[error("{0}")]
+    Stream(String),
+}
+
 #[derive(Error, Debug)]
 pub enum StateErrors {
     #[error("Can't connect to the server")]
     Disconnect(#[from] SimulationError),
+    #[error("Unsupported router function: {0}")]
+    UnsupportedRouterFunction(String),
+}
+
+#[derive(Error, Debug)]
+pub enum EncodingError {
+    #[error("Unsupported function signature: {0}")]
+    UnsupportedFunctionSignature(String),
+    #[error("Can't decode address: {0}")]
+    AddressDecode(String),
+    #[error("Can't ABI encode calldata: {0}")]
+    AbiEncode(String),
+    #[error("Invalid route: {0}")]
+    InvalidRoute(String),
+    #[error("Tycho router encoding failed: {0}")]
+    Router(#[from] TychoEncodingError),
+    #[error(transparent)]
+    UnsupportedRouterFunction(#[from] StateErrors),
+    #[error("Quoting a hop failed: {0}")]
+    Quote(#[from] SimulationError),
+}
+
+/// Why an `eth_call` of a transaction reverted.
+#[derive(Error, Debug)]
+pub enum RevertReason {
+    #[error("reverted: {0}")]
+    Error(String),
+    #[error("panicked with code {0:#x}")]
+    Panic(U256),
+    #[error("reverted with {0} ({1})")]
+    Named(&'static str, Bytes),
+    #[error("reverted with custom data {0}")]
+    Custom(Bytes),
+    #[error("reverted without data")]
+    Empty,
+    #[error("call failed: {0}")]
+    Rpc(String),
+}
+
+/// Stage at which submitting a transaction failed.
+#[derive(Error, Debug)]
+pub enum ExecutorError {
+    #[error("Gas estimation failed, {reason}")]
+    GasEstimate {
+        reason: RevertReason,
+        #[source]
+        source: TransportError,
+    },
+    #[error("Can't fetch the account nonce: {0}")]
+    Nonce(#[source] TransportError),
+    #[error("Can't determine EIP-1559 fees: {0}")]
+    Fees(String),
+    #[error("max_fee_per_gas {max_fee_per_gas} wei is above GAS_PRICE_CAP_GWEI ({cap} wei)")]
+    GasPriceCap { max_fee_per_gas: u128, cap: u128 },
+    #[error("Nonce {0} rejected as too low or too high")]
+    NonceGap(u64),
+    #[error("Sending the transaction failed: {0}")]
+    Send(#[source] TransportError),
 }
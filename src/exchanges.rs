@@ -0,0 +1,168 @@
+use anyhow::{Result, bail};
+use tycho_simulation::evm::engine_db::tycho_db::PreCachedDB;
+use tycho_simulation::evm::protocol::filters::{
+    balancer_v2_pool_filter, curve_pool_filter, uniswap_v4_euler_hook_pool_filter,
+};
+use tycho_simulation::evm::protocol::uniswap_v2::state::UniswapV2State;
+use tycho_simulation::evm::protocol::uniswap_v3::state::UniswapV3State;
+use tycho_simulation::evm::protocol::uniswap_v4::state::UniswapV4State;
+use tycho_simulation::evm::protocol::vm::state::EVMPoolState;
+use tycho_simulation::evm::stream::ProtocolStreamBuilder;
+use tycho_simulation::protocol::models::ProtocolComponent;
+use tycho_simulation::tycho_client::feed::component_tracker::ComponentFilter;
+use tycho_simulation::tycho_client::feed::synchronizer::ComponentWithState;
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+
+use crate::config::TvlThresholds;
+
+pub const V4_HOOKS_EXCHANGE: &str = "uniswap_v4_hooks";
+
+pub const SUPPORTED_EXCHANGES: &[&str] = &[
+    "uniswap_v2",
+    "sushiswap",
+    "uniswap_v3",
+    "uniswap_v4",
+    V4_HOOKS_EXCHANGE,
+    "vm:balancer_v2",
+    "vm:curve",
+];
+
+/// An exchange to subscribe to on the protocol stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeConfig {
+    pub name: String,
+    pub tvl: TvlThresholds,
+}
+
+impl ExchangeConfig {
+    pub fn tvl_filter(&self) -> ComponentFilter {
+        ComponentFilter::with_tvl_range(self.tvl.remove, self.tvl.add)
+    }
+
+    /// VM protocols are quoted by running their contracts in a local EVM instead of native math.
+    pub fn is_vm(&self) -> bool {
+        self.name.starts_with("vm:")
+    }
+}
+
+/// Stream filter for `uniswap_v4_hooks` when `V4_HOOK_ADDRESSES` is set: Euler hook pools
+/// plus every pool with a hook. The builder only takes plain `fn` filters, so the configured
+/// hooks are checked once components reach the registry, see `accepts_v4_hook_pool`.
+fn any_hook_pool_filter(component: &ComponentWithState) -> bool {
+    uniswap_v4_euler_hook_pool_filter(component)
+        || component
+            .component
+            .static_attributes
+            .get("hooks")
+            .is_some_and(is_hook)
+}
+
+fn is_hook(address: &Bytes) -> bool {
+    address.iter().any(|byte| *byte != 0)
+}
+
+/// Whether `component` is one the stream filters would let through with `extra_hooks`
+/// configured: anything but a `uniswap_v4_hooks` pool with a hook that is neither Euler's
+/// nor listed.
+pub fn accepts_v4_hook_pool(component: &ProtocolComponent, extra_hooks: &[Bytes]) -> bool {
+    if component.protocol_system != V4_HOOKS_EXCHANGE || extra_hooks.is_empty() {
+        return true;
+    }
+    let attributes = &component.static_attributes;
+    match attributes.get("hooks") {
+        Some(hook) if is_hook(hook) => {
+            // Tycho tags Euler's hook pools with this identifier
+            extra_hooks.contains(hook)
+                || attributes
+                    .get("hook_identifier")
+                    .is_some_and(|identifier| identifier.as_ref() == b"euler_v1")
+        }
+        // Hookless pools only get here through the Euler filter
+        _ => true,
+    }
+}
+
+pub fn register_exchanges(
+    mut builder: ProtocolStreamBuilder,
+    exchanges: &[ExchangeConfig],
+    extra_v4_hooks: &[Bytes],
+) -> Result<ProtocolStreamBuilder> {
+    let hooks_filter = if extra_v4_hooks.is_empty() {
+        uniswap_v4_euler_hook_pool_filter
+    } else {
+        any_hook_pool_filter
+    };
+
+    for exchange in exchanges {
+        let filter = exchange.tvl_filter();
+        builder = match exchange.name.as_str() {
+            "uniswap_v2" => builder.exchange::<UniswapV2State>("uniswap_v2", filter, None),
+            // Tycho indexes SushiSwap under its v2 protocol system name
+            "sushiswap" => builder.exchange::<UniswapV2State>("sushiswap_v2", filter, None),
+            "uniswap_v3" => builder.exchange::<UniswapV3State>("uniswap_v3", filter, None),
+            "uniswap_v4" => builder.exchange::<UniswapV4State>("uniswap_v4", filter, None),
+            V4_HOOKS_EXCHANGE => builder.exchange::<UniswapV4State>(
+                V4_HOOKS_EXCHANGE,
+                filter,
+                Some(hooks_filter),
+            ),
+            "vm:balancer_v2" => builder.exchange::<EVMPoolState<PreCachedDB>>(
+                "vm:balancer_v2",
+                filter,
+                Some(balancer_v2_pool_filter),
+            ),
+            "vm:curve" => builder.exchange::<EVMPoolState<PreCachedDB>>(
+                "vm:curve",
+                filter,
+                Some(curve_pool_filter),
+            ),
+            other => bail!(
+                "Unsupported exchange {other:?}, supported values: {}",
+                SUPPORTED_EXCHANGES.join(", ")
+            ),
+        };
+    }
+
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::testing::{component, token};
+
+    fn hook_pool(attributes: &[(&str, &[u8])]) -> ProtocolComponent {
+        let mut pool = component("pool", &[token(0x11, "AAA", 18), token(0x22, "BBB", 18)]);
+        pool.protocol_system = V4_HOOKS_EXCHANGE.to_string();
+        pool.static_attributes = attributes
+            .iter()
+            .map(|(key, value)| (key.to_string(), Bytes::from(value.to_vec())))
+            .collect::<HashMap<_, _>>();
+        pool
+    }
+
+    #[test]
+    fn listed_and_euler_hook_pools_are_accepted() {
+        let listed = Bytes::from([0x44; 20].as_slice());
+        let extra = [listed.clone()];
+
+        assert!(accepts_v4_hook_pool(&hook_pool(&[("hooks", &[0x44; 20])]), &extra));
+        assert!(accepts_v4_hook_pool(
+            &hook_pool(&[("hooks", &[0x55; 20]), ("hook_identifier", b"euler_v1")]),
+            &extra
+        ));
+        assert!(accepts_v4_hook_pool(&hook_pool(&[("hooks", &[0; 20])]), &extra));
+        assert!(!accepts_v4_hook_pool(&hook_pool(&[("hooks", &[0x55; 20])]), &extra));
+    }
+
+    #[test]
+    fn other_pools_and_an_empty_list_are_left_to_the_stream_filter() {
+        assert!(accepts_v4_hook_pool(&hook_pool(&[("hooks", &[0x55; 20])]), &[]));
+
+        let mut v4 = hook_pool(&[("hooks", &[0x55; 20])]);
+        v4.protocol_system = "uniswap_v4".to_string();
+        assert!(accepts_v4_hook_pool(&v4, &[Bytes::from([0x44; 20].as_slice())]));
+    }
+}
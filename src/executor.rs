@@ -0,0 +1,702 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use alloy::primitives::{Address, TxHash, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::transports::TransportError;
+use futures::future::BoxFuture;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use crate::allowance::AllowanceCache;
+use crate::encoding::decode_multitrade_calldata;
+use crate::error::ExecutorError;
+use crate::gas::GasConfig;
+use crate::metrics;
+use crate::pending::PendingTracker;
+use crate::receipts::{Ledger, ReceiptWatcher, TxOutcome, WatchedTx};
+use crate::replacement::{Replacement, ReplacementPolicy};
+use crate::revert::revert_reason;
+
+/// Replacements sent at one nonce before the transaction is left to the pending TTL.
+const MAX_REPLACEMENTS: u32 = 3;
+
+/// A replacement send, handed back to `prune_pending` from its task.
+struct ReplacementAttempt {
+    stuck: WatchedTx,
+    request: TransactionRequest,
+    decision: Replacement,
+    result: Result<TxHash, TransportError>,
+}
+
+/// Hands out account nonces locally so back-to-back submissions don't race on
+/// `eth_getTransactionCount`. The chain is asked again after a nonce error, and once no
+/// submission went out for `resync_after`, in case a transaction was dropped and left a gap.
+pub struct NonceManager {
+    from: Address,
+    resync_after: Duration,
+    state: Mutex<NonceState>,
+}
+
+#[derive(Debug, Default)]
+struct NonceState {
+    next: Option<u64>,
+    last_used: Option<Instant>,
+}
+
+/// Exclusive hold on the next nonce. Concurrent submitters wait on the lock, so each one
+/// gets the nonce after the previous submission.
+pub struct NonceLease<'a> {
+    state: MutexGuard<'a, NonceState>,
+    nonce: u64,
+}
+
+impl NonceLease<'_> {
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// The transaction was accepted, the next lease gets the following nonce.
+    pub fn commit(mut self) {
+        self.state.next = Some(self.nonce + 1);
+        self.state.last_used = Some(Instant::now());
+    }
+
+    /// The submission failed in a way that may mean the local nonce is wrong.
+    pub fn invalidate(mut self) {
+        self.state.next = None;
+    }
+}
+
+impl NonceManager {
+    pub fn new(from: Address, resync_after: Duration) -> Self {
+        Self {
+            from,
+            resync_after,
+            state: Mutex::new(NonceState::default()),
+        }
+    }
+
+    /// Fetches the pending nonce from the chain, at startup or after an error.
+    pub async fn sync(&self, provider: &impl Provider) -> Result<u64, ExecutorError> {
+        let mut state = self.state.lock().await;
+        let nonce = self.fetch(provider).await?;
+        state.next = Some(nonce);
+        info!(from = %self.from, nonce, "🔢 Nonce synced from chain");
+        Ok(nonce)
+    }
+
+    /// Waits for the lock and returns the nonce to use, refetching it when unknown or
+    /// idle for longer than `resync_after`.
+    pub async fn lease(&self, provider: &impl Provider) -> Result<NonceLease<'_>, ExecutorError> {
+        let mut state = self.state.lock().await;
+        let stale = state
+            .last_used
+            .is_some_and(|last_used| last_used.elapsed() > self.resync_after);
+        let nonce = match state.next {
+            Some(nonce) if !stale => nonce,
+            _ => {
+                let nonce = self.fetch(provider).await?;
+                if let Some(local) = state.next
+                    && local != nonce
+                {
+                    warn!(local, chain = nonce, "Local nonce drifted from chain, resyncing");
+                }
+                nonce
+            }
+        };
+
+        Ok(NonceLease { state, nonce })
+    }
+
+    /// Drops the local nonce, the next lease asks the chain. For when a transaction we
+    /// counted will never be mined.
+    pub async fn forget(&self) {
+        self.state.lock().await.next = None;
+    }
+
+    pub async fn peek(&self) -> Option<u64> {
+        self.state.lock().await.next
+    }
+
+    async fn fetch(&self, provider: &impl Provider) -> Result<u64, ExecutorError> {
+        provider
+            .get_transaction_count(self.from)
+            .pending()
+            .await
+            .map_err(ExecutorError::Nonce)
+    }
+}
+
+/// Sends a fully populated transaction and returns its hash, see `SubmissionMode` for the
+/// implementations.
+pub trait TxSubmitter: Send + Sync {
+    fn submit(&self, tx: TransactionRequest) -> BoxFuture<'_, Result<TxHash, TransportError>>;
+
+    /// Hashes of submitted transactions that will never be mined, since the last call.
+    /// Only bundles expire, a transaction sent to an RPC stays in its mempool.
+    fn take_expired(&self) -> Vec<TxHash> {
+        Vec::new()
+    }
+
+    /// Waits for the submitter's background work until `deadline`, on shutdown.
+    fn drain(&self, _deadline: tokio::time::Instant) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Waits for every task of `tasks` until `deadline` and aborts the ones still running, at
+/// shutdown. Returns the outputs of those that finished, `what` names them in the logs.
+pub async fn join_until<T: 'static>(
+    mut tasks: JoinSet<T>,
+    deadline: tokio::time::Instant,
+    what: &str,
+) -> Vec<T> {
+    let mut finished = Vec::new();
+    loop {
+        match tokio::time::timeout_at(deadline, tasks.join_next()).await {
+            Ok(Some(Ok(output))) => finished.push(output),
+            Ok(Some(Err(e))) => warn!(error = %e, "{what} task failed"),
+            Ok(None) => break,
+            Err(_) => {
+                warn!(
+                    remaining = tasks.len(),
+                    "{what} tasks still running after SHUTDOWN_TIMEOUT_SECS, abandoning them"
+                );
+                tasks.abort_all();
+                break;
+            }
+        }
+    }
+    finished
+}
+
+/// Prepares transactions with nonces from a `NonceManager` and fees from `GasConfig`, and
+/// hands them to a `TxSubmitter`.
+pub struct Executor<P> {
+    provider: P,
+    submitter: Arc<dyn TxSubmitter>,
+    from: Address,
+    gas: GasConfig,
+    nonces: NonceManager,
+    pending: PendingTracker,
+    allowances: AllowanceCache,
+    receipts: ReceiptWatcher,
+    replacement: ReplacementPolicy,
+    ledger: std::sync::Mutex<Ledger>,
+    /// Replacements being sent, see `replace_stuck`.
+    replacements: std::sync::Mutex<JoinSet<ReplacementAttempt>>,
+}
+
+impl<P: Provider> Executor<P> {
+    pub fn new(
+        provider: P,
+        submitter: Box<dyn TxSubmitter>,
+        receipts: ReceiptWatcher,
+        replacement: ReplacementPolicy,
+        from: Address,
+        gas: GasConfig,
+        pending_ttl: Duration,
+        nonce_resync_after: Duration,
+    ) -> Self {
+        Self {
+            provider,
+            submitter: Arc::from(submitter),
+            from,
+            gas,
+            nonces: NonceManager::new(from, nonce_resync_after),
+            pending: PendingTracker::new(pending_ttl),
+            allowances: AllowanceCache::default(),
+            receipts,
+            replacement,
+            ledger: std::sync::Mutex::new(Ledger::default()),
+            replacements: std::sync::Mutex::new(JoinSet::new()),
+        }
+    }
+
+    /// Loads the account nonce before the first submission.
+    pub async fn sync_nonce(&self) -> Result<u64, ExecutorError> {
+        self.nonces.sync(&self.provider).await
+    }
+
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    /// Whether a transaction trading on `component` is still waiting to be mined.
+    pub fn is_pending(&self, component: &str) -> bool {
+        self.pending.is_pending(component)
+    }
+
+    /// Stops speeding up submissions trading on `component`, a pool the stream removed.
+    /// Ones reported stuck from now on are cancelled instead.
+    pub fn cancel_pool(&self, component: &str) {
+        for key in self.pending.cancel(component) {
+            info!(%key, pool = %component, "🚫 Pool removed, its pending transaction is cancelled rather than sped up if it gets stuck");
+        }
+    }
+
+    /// Books the outcomes the receipt watcher reported and forgets pending submissions that
+    /// were mined, expired or outlived their TTL. Stuck ones are replaced, see
+    /// `replace_stuck`.
+    pub async fn prune_pending(&self) {
+        let expired = self.submitter.take_expired();
+        if !expired.is_empty() {
+            // Their nonces were never used, the local count is ahead of the chain
+            self.nonces.forget().await;
+            for (key, tx_hash) in self.pending.in_flight() {
+                if expired.contains(&tx_hash) {
+                    info!(%key, %tx_hash, "Bundle expired, resyncing the nonce");
+                    self.pending.remove(&key);
+                }
+            }
+        }
+        let finished: Vec<_> = {
+            let mut replacements = self.replacements.lock().expect("replacements lock poisoned");
+            std::iter::from_fn(|| replacements.try_join_next()).collect()
+        };
+        for attempt in finished {
+            match attempt {
+                Ok(attempt) => self.replaced(attempt),
+                Err(e) => warn!(error = %e, "Replacement task failed"),
+            }
+        }
+
+        for (tx, head) in self.book_outcomes() {
+            self.replace_stuck(tx, head);
+        }
+        // Drops the entries past their TTL
+        self.pending.in_flight();
+    }
+
+    /// Records the receipt watcher's outcomes and forgets the mined transactions. Returns
+    /// the stuck ones with the block they were reported at.
+    fn book_outcomes(&self) -> Vec<(WatchedTx, u64)> {
+        let outcomes = self.receipts.drain();
+        let mut stuck = Vec::new();
+        if outcomes.is_empty() {
+            return stuck;
+        }
+        let mut ledger = self.ledger.lock().expect("ledger lock poisoned");
+        for outcome in outcomes {
+            ledger.record(&outcome);
+            match outcome {
+                TxOutcome::Mined { success: true, .. } => metrics::transaction_succeeded(),
+                TxOutcome::Mined { .. } => metrics::transaction_reverted(),
+                TxOutcome::Stuck { tx, head, .. } => {
+                    metrics::transaction_stuck();
+                    stuck.push((tx, head));
+                    continue;
+                }
+            }
+            self.pending.remove(&outcome.tx().key);
+        }
+        stuck
+    }
+
+    /// Re-sends a stuck transaction at its nonce with bumped fees, as a speed-up while the
+    /// opportunity may still be live and as a cancel once it's older than
+    /// `OPPORTUNITY_TTL_BLOCKS` at `head`. The send runs on its own task, `prune_pending`
+    /// picks up its result. The nonce manager isn't involved, the nonce is already
+    /// committed.
+    fn replace_stuck(&self, stuck: WatchedTx, head: u64) {
+        if stuck.replacements >= MAX_REPLACEMENTS {
+            warn!(key = %stuck.key, tx_hash = %stuck.tx_hash, nonce = stuck.nonce, "Replacement limit reached, leaving the transaction to its TTL");
+            self.receipts.watch(stuck);
+            return;
+        }
+        let decision = if self.pending.is_cancelled(&stuck.key) {
+            Replacement::Cancel
+        } else {
+            self.replacement.decide(stuck.origin_block, head)
+        };
+        let request = self.replacement.replace(decision, &stuck.request, self.from);
+        if let Some(max_fee_per_gas) = request.max_fee_per_gas
+            && decision == Replacement::SpeedUp
+            && self.gas.exceeds_cap(max_fee_per_gas)
+        {
+            info!(tx_hash = %stuck.tx_hash, max_fee_per_gas, "Bumped fees exceed GAS_PRICE_CAP_GWEI, not speeding up");
+            self.receipts.watch(stuck);
+            return;
+        }
+
+        let submitter = Arc::clone(&self.submitter);
+        self.replacements
+            .lock()
+            .expect("replacements lock poisoned")
+            .spawn(async move {
+                let result = submitter.submit(request.clone()).await;
+                ReplacementAttempt {
+                    stuck,
+                    request,
+                    decision,
+                    result,
+                }
+            });
+    }
+
+    /// Follows up on a replacement send. Whatever happened, every hash sent at the nonce
+    /// stays watched until one of them is mined.
+    fn replaced(&self, attempt: ReplacementAttempt) {
+        let ReplacementAttempt {
+            stuck,
+            request,
+            decision,
+            result,
+        } = attempt;
+        match result {
+            Ok(tx_hash) => {
+                info!(
+                    key = %stuck.key,
+                    replaced = %stuck.tx_hash,
+                    %tx_hash,
+                    nonce = stuck.nonce,
+                    ?decision,
+                    "🔁 Stuck transaction replaced"
+                );
+                self.pending.insert(&stuck.key, tx_hash);
+                let mut replaced = stuck.replaced;
+                replaced.push(stuck.tx_hash);
+                self.receipts.watch(WatchedTx {
+                    tx_hash,
+                    replaced,
+                    request,
+                    replacements: stuck.replacements + 1,
+                    ..stuck
+                });
+            }
+            Err(e) if e.to_string().to_lowercase().contains("nonce too low") => {
+                // The original, or an earlier replacement, landed after all
+                info!(key = %stuck.key, nonce = stuck.nonce, "Stuck transaction's nonce already used, waiting for its receipt");
+                self.receipts.watch(stuck);
+            }
+            Err(e) => {
+                warn!(key = %stuck.key, tx_hash = %stuck.tx_hash, error = %e, "Can't replace the stuck transaction");
+                self.receipts.watch(stuck);
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for replacements being sent, bundle resubmissions and receipt
+    /// watchers, books what they report, then logs the nonce the next submission would use
+    /// and the transactions still waiting for a receipt.
+    pub async fn shutdown(&self, timeout: Duration) {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let replacements =
+            std::mem::take(&mut *self.replacements.lock().expect("replacements lock poisoned"));
+        for attempt in join_until(replacements, deadline, "Replacement").await {
+            self.replaced(attempt);
+        }
+        self.submitter.drain(deadline).await;
+        self.receipts.wait(deadline).await;
+        for (tx, _) in self.book_outcomes() {
+            warn!(key = %tx.key, tx_hash = %tx.tx_hash, "Transaction stuck at shutdown, not replacing it");
+        }
+
+        for (key, tx_hash) in self.pending.in_flight() {
+            warn!(%key, %tx_hash, "Transaction still pending at shutdown, no longer watched");
+        }
+        match self.nonces.peek().await {
+            Some(nonce) => info!(from = %self.from, next_nonce = nonce, "Executor idle, no submission in flight"),
+            None => info!(from = %self.from, "Executor idle, no nonce tracked yet"),
+        }
+    }
+
+    /// Drops the approve calls of `tx` the executor contract's allowance already covers.
+    pub async fn skip_covered_approvals(
+        &self,
+        tx: TransactionRequest,
+        amount: U256,
+    ) -> TransactionRequest {
+        self.allowances
+            .skip_covered_approvals(&self.provider, tx, amount)
+            .await
+    }
+
+    /// RPC gas estimate for `tx`, before any headroom is added.
+    pub async fn estimate_gas(&self, tx: TransactionRequest) -> Result<u64, ExecutorError> {
+        self.provider
+            .estimate_gas(tx)
+            .await
+            .map_err(|source| ExecutorError::GasEstimate {
+                reason: revert_reason(&source),
+                source,
+            })
+    }
+
+    /// Sends `tx` with its gas limit set from `gas_estimate` plus `GAS_LIMIT_MULTIPLIER`
+    /// headroom, and returns its hash, tracking it as pending under `key`, the `+` joined
+    /// ids of the pools it trades on. A nonce gap rejection triggers one retry with a nonce
+    /// refetched from the chain. `origin_block` is the block of the update the trade was
+    /// quoted on.
+    pub async fn submit_transaction(
+        &self,
+        key: &str,
+        tx: TransactionRequest,
+        gas_estimate: u64,
+        origin_block: u64,
+    ) -> Result<TxHash, ExecutorError> {
+        let tx = tx.gas_limit(self.gas.gas_limit(gas_estimate));
+        // Our trade spends or replaces the cached allowances
+        let batch = tx
+            .input
+            .input()
+            .and_then(|calldata| decode_multitrade_calldata(calldata).ok());
+        let (tx_hash, request) = match self.send(tx.clone()).await {
+            Err(ExecutorError::NonceGap(nonce)) => {
+                warn!(nonce, "Nonce rejected, refetching and retrying once");
+                self.send(tx).await
+            }
+            result => result,
+        }?;
+        self.pending.insert(key, tx_hash);
+        self.receipts.watch(WatchedTx {
+            key: key.to_string(),
+            tx_hash,
+            replaced: Vec::new(),
+            nonce: request.nonce.unwrap_or_default(),
+            request,
+            origin_block,
+            replacements: 0,
+        });
+        if let Some(batch) = &batch {
+            self.allowances.invalidate_touched(batch);
+        }
+        Ok(tx_hash)
+    }
+
+    /// Returns the hash of the sent transaction and the request with its nonce and fees.
+    async fn send(
+        &self,
+        tx: TransactionRequest,
+    ) -> Result<(TxHash, TransactionRequest), ExecutorError> {
+        // The lease holds the nonce lock for the whole send
+        let lease = self.nonces.lease(&self.provider).await?;
+        let nonce = lease.nonce();
+
+        let (max_fee_per_gas, max_priority_fee_per_gas) = self
+            .gas
+            .fees(&self.provider)
+            .await
+            .map_err(|e| ExecutorError::Fees(format!("{e:#}")))?;
+        if let Some(cap) = self.gas.gas_price_cap
+            && max_fee_per_gas > cap
+        {
+            return Err(ExecutorError::GasPriceCap { max_fee_per_gas, cap });
+        }
+        let tx = tx
+            .from(self.from)
+            .nonce(nonce)
+            .max_fee_per_gas(max_fee_per_gas)
+            .max_priority_fee_per_gas(max_priority_fee_per_gas);
+
+        match self.submitter.submit(tx.clone()).await {
+            Ok(tx_hash) => {
+                lease.commit();
+                info!(%tx_hash, nonce, "📤 Transaction submitted");
+                Ok((tx_hash, tx))
+            }
+            Err(e) => {
+                // Force a refetch, the local view may be what went wrong
+                lease.invalidate();
+                let message = e.to_string().to_lowercase();
+                if message.contains("nonce too low") || message.contains("nonce too high") {
+                    Err(ExecutorError::NonceGap(nonce))
+                } else {
+                    Err(ExecutorError::Send(e))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::U64;
+    use alloy::providers::ProviderBuilder;
+    use alloy::transports::mock::Asserter;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_leases_hand_out_consecutive_nonces() {
+        let asserter = Asserter::new();
+        // A second fetch would find no response and fail its lease
+        asserter.push_success(&U64::from(5));
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let nonces = NonceManager::new(Address::repeat_byte(0x01), Duration::from_secs(60));
+
+        let leased = futures::future::join_all((0..8).map(|_| async {
+            let lease = nonces.lease(&provider).await.unwrap();
+            let nonce = lease.nonce();
+            // Give the other submitters a chance to race for the lock
+            tokio::task::yield_now().await;
+            lease.commit();
+            nonce
+        }))
+        .await;
+
+        let mut sorted = leased.clone();
+        sorted.sort();
+        assert_eq!(sorted, (5..13).collect::<Vec<u64>>());
+        assert_eq!(nonces.peek().await, Some(13));
+
+        // A failed submission makes the next lease ask the chain again
+        asserter.push_success(&U64::from(20));
+        nonces.lease(&provider).await.unwrap().invalidate();
+        assert_eq!(nonces.lease(&provider).await.unwrap().nonce(), 20);
+    }
+
+    struct ExpiredSubmitter(TxHash);
+
+    impl TxSubmitter for ExpiredSubmitter {
+        fn submit(&self, _: TransactionRequest) -> BoxFuture<'_, Result<TxHash, TransportError>> {
+            Box::pin(async move { Ok(self.0) })
+        }
+
+        fn take_expired(&self) -> Vec<TxHash> {
+            vec![self.0]
+        }
+    }
+
+    #[tokio::test]
+    async fn an_expired_bundle_resyncs_the_nonce_and_frees_its_pools() {
+        let config = crate::testing::config(&[]);
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone()).erased();
+        let tx_hash = TxHash::repeat_byte(0x42);
+        let executor = Executor::new(
+            provider.clone(),
+            Box::new(ExpiredSubmitter(tx_hash)),
+            ReceiptWatcher::new(provider, config.watch),
+            config.replacement,
+            Address::repeat_byte(0x01),
+            config.gas.clone(),
+            config.pending_tx_ttl,
+            config.nonce_resync_after,
+        );
+        asserter.push_success(&U64::from(7));
+        executor.sync_nonce().await.unwrap();
+        executor.pending.insert("a+b", tx_hash);
+
+        executor.prune_pending().await;
+
+        assert_eq!(executor.nonces.peek().await, None);
+        assert!(!executor.is_pending("a"));
+        assert!(!executor.is_pending("b"));
+    }
+
+    struct BumpingSubmitter(TxHash);
+
+    impl TxSubmitter for BumpingSubmitter {
+        fn submit(&self, _: TransactionRequest) -> BoxFuture<'_, Result<TxHash, TransportError>> {
+            Box::pin(async move { Ok(self.0) })
+        }
+    }
+
+    #[tokio::test]
+    async fn a_stuck_transaction_is_sped_up_and_booked_when_only_the_bump_is_mined() {
+        let config = crate::testing::config(&[]);
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone()).erased();
+        let (original, bumped) = (TxHash::repeat_byte(0x01), TxHash::repeat_byte(0x02));
+        let executor = Executor::new(
+            provider.clone(),
+            Box::new(BumpingSubmitter(bumped)),
+            ReceiptWatcher::new(provider, config.watch),
+            config.replacement,
+            Address::repeat_byte(0x01),
+            config.gas.clone(),
+            config.pending_tx_ttl,
+            config.nonce_resync_after,
+        );
+        // No receipt for the original until it's stuck, then only the bump is mined
+        for head in [100u64, 102] {
+            asserter.push_success(&U64::from(head));
+            asserter.push_success(&None::<alloy::rpc::types::TransactionReceipt>);
+        }
+        asserter.push_success(&U64::from(103));
+        asserter.push_success(&crate::testing::receipt(bumped, 103));
+
+        executor.pending.insert("pool", original);
+        executor.receipts.watch(WatchedTx {
+            key: "pool".to_string(),
+            tx_hash: original,
+            replaced: Vec::new(),
+            nonce: 4,
+            request: TransactionRequest::default()
+                .nonce(4)
+                .max_fee_per_gas(10_000_000_000)
+                .max_priority_fee_per_gas(1_000_000_000),
+            origin_block: 100,
+            replacements: 0,
+        });
+
+        let booked = tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                executor.prune_pending().await;
+                if executor.ledger.lock().unwrap().succeeded == 1 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+
+        assert!(booked.is_ok(), "the bumped transaction was never booked");
+        assert!(!executor.is_pending("pool"));
+        assert_eq!(executor.ledger.lock().unwrap().gas_spent_wei, 90_000 * 2_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_finished_tasks_and_aborts_long_running_ones() {
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            1
+        });
+        tasks.spawn(async { 2 });
+        // Dropped when the task is aborted
+        let (alive, aborted) = tokio::sync::oneshot::channel::<()>();
+        tasks.spawn(async move {
+            let _alive = alive;
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            3
+        });
+
+        let started = tokio::time::Instant::now();
+        let mut finished = join_until(tasks, started + Duration::from_millis(500), "Test").await;
+
+        finished.sort();
+        assert_eq!(finished, vec![1, 2]);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(aborted.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fees_above_the_cap_are_not_submitted() {
+        let config = crate::testing::config(&[
+            ("GAS_PRICING", "fixed"),
+            ("MAX_FEE_PER_GAS_GWEI", "50"),
+            ("GAS_PRICE_CAP_GWEI", "40"),
+        ]);
+        let asserter = Asserter::new();
+        asserter.push_success(&U64::from(3));
+        let executor = crate::testing::executor(&config, asserter);
+
+        let result = executor
+            .submit_transaction("pool", TransactionRequest::default(), 100_000, 1)
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(ExecutorError::GasPriceCap {
+                max_fee_per_gas: 50_000_000_000,
+                cap: 40_000_000_000
+            })
+        ));
+        assert!(!executor.is_pending("pool"));
+    }
+}
@@ -0,0 +1,321 @@
+use alloy::eips::BlockNumberOrTag;
+use alloy::providers::Provider;
+use anyhow::{Context, Result, bail, ensure};
+
+use crate::config::ConfigSource;
+
+const WEI_PER_GWEI: f64 = 1e9;
+const DEFAULT_PRIORITY_FEE_GWEI: f64 = 1.0;
+const DEFAULT_BASE_FEE_MULTIPLIER: f64 = 2.0;
+const DEFAULT_GAS_LIMIT_MULTIPLIER: f64 = 1.2;
+const DEFAULT_DRY_RUN_GAS_PRICE_GWEI: f64 = 20.0;
+/// Blocks of `eth_feeHistory` the priority fee percentile is taken over.
+const FEE_HISTORY_BLOCKS: u64 = 10;
+
+/// Where EIP-1559 fees come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasPricing {
+    /// Whatever the provider's fee estimator suggests.
+    Provider,
+    /// `MAX_FEE_PER_GAS_GWEI` and `MAX_PRIORITY_FEE_GWEI` as configured.
+    Fixed,
+    /// `latest base fee * BASE_FEE_MULTIPLIER + priority fee`, the priority fee being
+    /// `MAX_PRIORITY_FEE_GWEI` or the `PRIORITY_FEE_PERCENTILE` of recent blocks.
+    BaseFee,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GasConfig {
+    pub pricing: GasPricing,
+    pub max_fee_per_gas: Option<u128>,
+    pub max_priority_fee_per_gas: u128,
+    pub base_fee_multiplier: f64,
+    /// Percentile of recent priority fees to pay under `GasPricing::BaseFee`, replacing
+    /// `max_priority_fee_per_gas`.
+    pub priority_fee_percentile: Option<f64>,
+    /// Fees above this `max_fee_per_gas` pause trading, `None` disables the cap.
+    pub gas_price_cap: Option<u128>,
+    /// Headroom on top of the RPC gas estimate, state can move before inclusion.
+    pub gas_limit_multiplier: f64,
+    /// What gas is priced at on a dry run, which never asks the RPC.
+    pub dry_run_gas_price: u128,
+}
+
+impl GasConfig {
+    pub fn from_source(source: &ConfigSource) -> Result<Self> {
+        let pricing = match source.get("GAS_PRICING").as_deref().map(str::trim) {
+            None | Some("provider") => GasPricing::Provider,
+            Some("fixed") => GasPricing::Fixed,
+            Some("base_fee") => GasPricing::BaseFee,
+            Some(other) => bail!("Unknown GAS_PRICING {other:?}, expected provider, fixed or base_fee"),
+        };
+
+        let max_fee_per_gas = source
+            .get("MAX_FEE_PER_GAS_GWEI")
+            .map(|raw| parse_gwei("MAX_FEE_PER_GAS_GWEI", &raw))
+            .transpose()?;
+        let max_priority_fee_per_gas = match source.get("MAX_PRIORITY_FEE_GWEI") {
+            Some(raw) => parse_gwei("MAX_PRIORITY_FEE_GWEI", &raw)?,
+            None => (DEFAULT_PRIORITY_FEE_GWEI * WEI_PER_GWEI) as u128,
+        };
+        let base_fee_multiplier = match source.get("BASE_FEE_MULTIPLIER") {
+            Some(raw) => raw
+                .trim()
+                .parse()
+                .context("Can't parse BASE_FEE_MULTIPLIER as a number")?,
+            None => DEFAULT_BASE_FEE_MULTIPLIER,
+        };
+
+        let priority_fee_percentile = source
+            .get("PRIORITY_FEE_PERCENTILE")
+            .map(|raw| {
+                raw.trim()
+                    .parse::<f64>()
+                    .context("Can't parse PRIORITY_FEE_PERCENTILE as a number")
+            })
+            .transpose()?;
+        let gas_price_cap = source
+            .get("GAS_PRICE_CAP_GWEI")
+            .map(|raw| parse_gwei("GAS_PRICE_CAP_GWEI", &raw))
+            .transpose()?;
+
+        let gas_limit_multiplier = match source.get("GAS_LIMIT_MULTIPLIER") {
+            Some(raw) => raw
+                .trim()
+                .parse()
+                .context("Can't parse GAS_LIMIT_MULTIPLIER as a number")?,
+            None => DEFAULT_GAS_LIMIT_MULTIPLIER,
+        };
+        let dry_run_gas_price = match source.get("DRY_RUN_GAS_PRICE_GWEI") {
+            Some(raw) => parse_gwei("DRY_RUN_GAS_PRICE_GWEI", &raw)?,
+            None => (DEFAULT_DRY_RUN_GAS_PRICE_GWEI * WEI_PER_GWEI) as u128,
+        };
+
+        ensure!(
+            pricing != GasPricing::Fixed || max_fee_per_gas.is_some(),
+            "GAS_PRICING=fixed requires MAX_FEE_PER_GAS_GWEI"
+        );
+        // A node rejects a transaction whose tip exceeds its fee cap, every submit would fail
+        if let (GasPricing::Fixed, Some(max_fee)) = (pricing, max_fee_per_gas) {
+            ensure!(
+                max_priority_fee_per_gas <= max_fee,
+                "MAX_PRIORITY_FEE_GWEI ({} gwei) must not exceed MAX_FEE_PER_GAS_GWEI ({} gwei) with GAS_PRICING=fixed",
+                max_priority_fee_per_gas as f64 / WEI_PER_GWEI,
+                max_fee as f64 / WEI_PER_GWEI
+            );
+        }
+        ensure!(
+            base_fee_multiplier >= 1.0,
+            "BASE_FEE_MULTIPLIER must be at least 1.0, got {}",
+            base_fee_multiplier
+        );
+        ensure!(
+            priority_fee_percentile.is_none_or(|percentile| (0.0..=100.0).contains(&percentile)),
+            "PRIORITY_FEE_PERCENTILE must be between 0 and 100"
+        );
+        ensure!(
+            gas_limit_multiplier >= 1.0,
+            "GAS_LIMIT_MULTIPLIER must be at least 1.0, got {}",
+            gas_limit_multiplier
+        );
+
+        Ok(Self {
+            pricing,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            base_fee_multiplier,
+            priority_fee_percentile,
+            gas_price_cap,
+            gas_limit_multiplier,
+            dry_run_gas_price,
+        })
+    }
+
+    pub fn gas_limit(&self, estimate: u64) -> u64 {
+        (estimate as f64 * self.gas_limit_multiplier).ceil() as u64
+    }
+
+    /// Returns `(max_fee_per_gas, max_priority_fee_per_gas)` in wei.
+    pub async fn fees(&self, provider: &impl Provider) -> Result<(u128, u128)> {
+        match self.pricing {
+            GasPricing::Provider => {
+                let estimate = provider.estimate_eip1559_fees().await?;
+                Ok((estimate.max_fee_per_gas, estimate.max_priority_fee_per_gas))
+            }
+            GasPricing::Fixed => Ok((
+                self.max_fee_per_gas.unwrap_or_default(),
+                self.max_priority_fee_per_gas,
+            )),
+            GasPricing::BaseFee => {
+                let (base_fee, priority_fee) = match self.priority_fee_percentile {
+                    Some(percentile) => self.fee_history(provider, percentile).await?,
+                    None => {
+                        let block = provider
+                            .get_block_by_number(BlockNumberOrTag::Latest)
+                            .await?
+                            .context("Latest block not available")?;
+                        let base_fee = block
+                            .header
+                            .base_fee_per_gas
+                            .context("Latest block has no base fee")?;
+                        (u128::from(base_fee), self.max_priority_fee_per_gas)
+                    }
+                };
+                let max_fee = (base_fee as f64 * self.base_fee_multiplier) as u128 + priority_fee;
+                Ok((max_fee, priority_fee))
+            }
+        }
+    }
+
+    /// Next block's base fee and the mean `percentile` priority fee over the last
+    /// `FEE_HISTORY_BLOCKS` blocks.
+    async fn fee_history(&self, provider: &impl Provider, percentile: f64) -> Result<(u128, u128)> {
+        let history = provider
+            .get_fee_history(FEE_HISTORY_BLOCKS, BlockNumberOrTag::Latest, &[percentile])
+            .await?;
+        let base_fee = history
+            .base_fee_per_gas
+            .last()
+            .copied()
+            .context("Fee history has no base fee")?;
+        let rewards: Vec<u128> = history
+            .reward
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|block| block.first().copied())
+            .collect();
+        ensure!(!rewards.is_empty(), "Fee history has no priority fee rewards");
+
+        Ok((base_fee, rewards.iter().sum::<u128>() / rewards.len() as u128))
+    }
+
+    /// Whether `max_fee_per_gas` is above `GAS_PRICE_CAP_GWEI`.
+    pub fn exceeds_cap(&self, max_fee_per_gas: u128) -> bool {
+        self.gas_price_cap.is_some_and(|cap| max_fee_per_gas > cap)
+    }
+}
+
+fn parse_gwei(key: &str, raw: &str) -> Result<u128> {
+    let gwei: f64 = raw
+        .trim()
+        .parse()
+        .with_context(|| format!("Can't parse {key} as a number of gwei"))?;
+    ensure!(gwei >= 0.0, "{key} must not be negative");
+    Ok((gwei * WEI_PER_GWEI) as u128)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::providers::ProviderBuilder;
+    use alloy::rpc::types::FeeHistory;
+    use alloy::transports::mock::Asserter;
+
+    use super::*;
+
+    fn gas_config(pairs: &[(&str, &str)]) -> Result<GasConfig> {
+        GasConfig::from_source(&ConfigSource::from_pairs(pairs))
+    }
+
+    #[test]
+    fn fixed_pricing_takes_the_configured_fees() {
+        let config = gas_config(&[
+            ("GAS_PRICING", "fixed"),
+            ("MAX_FEE_PER_GAS_GWEI", "30"),
+            ("MAX_PRIORITY_FEE_GWEI", "2"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.max_fee_per_gas, Some(30_000_000_000));
+        assert_eq!(config.max_priority_fee_per_gas, 2_000_000_000);
+    }
+
+    #[test]
+    fn dry_runs_price_gas_at_the_configured_or_default_price() {
+        assert_eq!(gas_config(&[]).unwrap().dry_run_gas_price, 20_000_000_000);
+        let config = gas_config(&[("DRY_RUN_GAS_PRICE_GWEI", "3.5")]).unwrap();
+        assert_eq!(config.dry_run_gas_price, 3_500_000_000);
+    }
+
+    #[test]
+    fn fixed_pricing_accepts_a_tip_equal_to_the_fee_cap() {
+        assert!(
+            gas_config(&[
+                ("GAS_PRICING", "fixed"),
+                ("MAX_FEE_PER_GAS_GWEI", "2"),
+                ("MAX_PRIORITY_FEE_GWEI", "2"),
+            ])
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn fixed_pricing_rejects_a_tip_above_the_fee_cap() {
+        let error = gas_config(&[
+            ("GAS_PRICING", "fixed"),
+            ("MAX_FEE_PER_GAS_GWEI", "1"),
+            ("MAX_PRIORITY_FEE_GWEI", "3"),
+        ])
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains("MAX_PRIORITY_FEE_GWEI (3 gwei)"), "{error}");
+        assert!(error.contains("MAX_FEE_PER_GAS_GWEI (1 gwei)"), "{error}");
+    }
+
+    #[test]
+    fn fixed_pricing_checks_the_default_tip_too() {
+        // MAX_PRIORITY_FEE_GWEI defaults to 1 gwei
+        assert!(
+            gas_config(&[("GAS_PRICING", "fixed"), ("MAX_FEE_PER_GAS_GWEI", "0.5")]).is_err()
+        );
+    }
+
+    /// Ten blocks of history ending at a 20 gwei next base fee, with 1 to 10 gwei tips.
+    fn fee_history() -> FeeHistory {
+        FeeHistory {
+            oldest_block: 100,
+            base_fee_per_gas: (0..11).map(|block| 10_000_000_000 + block * 1_000_000_000).collect(),
+            gas_used_ratio: vec![0.5; 10],
+            reward: Some((1..=10).map(|gwei| vec![gwei * 1_000_000_000]).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn base_fee_pricing_reads_the_fee_history() {
+        let config = gas_config(&[
+            ("GAS_PRICING", "base_fee"),
+            ("PRIORITY_FEE_PERCENTILE", "50"),
+            ("BASE_FEE_MULTIPLIER", "1.5"),
+            ("GAS_PRICE_CAP_GWEI", "40"),
+        ])
+        .unwrap();
+        let asserter = Asserter::new();
+        asserter.push_success(&fee_history());
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter);
+
+        let (max_fee, priority_fee) = config.fees(&provider).await.unwrap();
+
+        // Mean tip 5.5 gwei, on top of 1.5 x 20 gwei
+        assert_eq!(priority_fee, 5_500_000_000);
+        assert_eq!(max_fee, 35_500_000_000);
+        assert!(!config.exceeds_cap(max_fee));
+        assert!(config.exceeds_cap(max_fee + 5_000_000_000));
+    }
+
+    #[tokio::test]
+    async fn fee_history_without_rewards_is_an_error() {
+        let config =
+            gas_config(&[("GAS_PRICING", "base_fee"), ("PRIORITY_FEE_PERCENTILE", "50")]).unwrap();
+        let asserter = Asserter::new();
+        asserter.push_success(&FeeHistory {
+            reward: None,
+            ..fee_history()
+        });
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter);
+
+        let error = config.fees(&provider).await.unwrap_err().to_string();
+
+        assert!(error.contains("no priority fee rewards"), "{error}");
+    }
+}
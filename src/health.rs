@@ -0,0 +1,101 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+static STREAM_CONNECTED: AtomicBool = AtomicBool::new(false);
+/// Unix time in milliseconds of the last stream message, 0 before the first one.
+static LAST_MESSAGE_MS: AtomicU64 = AtomicU64::new(0);
+/// Unix time in milliseconds `install` was called, the staleness clock before any message.
+static STARTED_MS: AtomicU64 = AtomicU64::new(0);
+
+pub fn stream_connected(connected: bool) {
+    STREAM_CONNECTED.store(connected, Ordering::Relaxed);
+}
+
+pub fn message_received() {
+    LAST_MESSAGE_MS.store(now_ms(), Ordering::Relaxed);
+}
+
+/// Serves `/health` and `/ready` on `port` from a background task on the current runtime.
+/// `/ready` answers 200 once the stream is connected and has delivered a message,
+/// `/health` answers 200 while the last message, or startup, is within `stale_after`.
+/// Both answer 503 otherwise, with the same JSON body.
+pub async fn install(port: u16, stale_after: Duration) -> Result<()> {
+    STARTED_MS.store(now_ms(), Ordering::Relaxed);
+    let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Can't start health endpoint on {address}"))?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(socket, stale_after).await {
+                            debug!(error = %e, "Health request failed");
+                        }
+                    });
+                }
+                Err(e) => debug!(error = %e, "Can't accept health connection"),
+            }
+        }
+    });
+
+    info!(%address, "🩺 Health endpoint listening");
+    Ok(())
+}
+
+async fn respond(mut socket: TcpStream, stale_after: Duration) -> std::io::Result<()> {
+    let mut buffer = [0u8; 1024];
+    let read = socket.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let connected = STREAM_CONNECTED.load(Ordering::Relaxed);
+    let last_message = LAST_MESSAGE_MS.load(Ordering::Relaxed);
+    let since = if last_message == 0 {
+        STARTED_MS.load(Ordering::Relaxed)
+    } else {
+        last_message
+    };
+    let idle = Duration::from_millis(now_ms().saturating_sub(since));
+
+    let ok = match path {
+        "/health" => idle <= stale_after,
+        "/ready" => connected && last_message != 0,
+        _ => return write_response(&mut socket, "404 Not Found", "{\"error\":\"not found\"}").await,
+    };
+    let last_message_secs = if last_message == 0 {
+        "null".to_string()
+    } else {
+        idle.as_secs().to_string()
+    };
+    let body = format!(
+        "{{\"status\":\"{}\",\"stream_connected\":{connected},\"seconds_since_last_message\":{last_message_secs}}}",
+        if ok { "ok" } else { "unhealthy" }
+    );
+    let status = if ok { "200 OK" } else { "503 Service Unavailable" };
+    write_response(&mut socket, status, &body).await
+}
+
+async fn write_response(socket: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
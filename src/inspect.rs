@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use alloy::hex;
+use alloy::primitives::{Address, U256};
+use alloy::sol_types::SolCall;
+use anyhow::{Context, Result};
+
+use crate::encoding::{IERC20, Interaction, RouterFunction, decode_multitrade_calldata};
+
+/// Prints the interactions of an `executeInteractions` calldata stored as hex in `path`,
+/// e.g. the "Final calldata" of a dry run.
+pub fn run(path: &Path) -> Result<()> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("Can't read {}", path.display()))?;
+    let calldata = hex::decode(raw.trim()).context("Calldata file is not valid hex")?;
+    let call = decode_multitrade_calldata(&calldata)?;
+
+    println!("token:  {}", call.token_address);
+    println!("mode:   {:?}", call.mode);
+    for (index, interaction) in call.interactions.iter().enumerate() {
+        println!();
+        println!("[{index}] target:   {}", interaction.target);
+        println!("    value:    {}", interaction.value);
+        print_call(interaction);
+    }
+
+    Ok(())
+}
+
+fn print_call(interaction: &Interaction) {
+    let data = interaction.callData.as_ref();
+    let Some(selector) = data.get(..4) else {
+        println!("    calldata: 0x{} (no selector)", hex::encode(data));
+        return;
+    };
+    println!("    selector: 0x{}", hex::encode(selector));
+
+    if let Ok(call) = IERC20::approveCall::abi_decode(data) {
+        println!("    approve(spender: {}, amount: {})", call.spender, call.amount);
+    } else if let Ok(call) = IERC20::transferCall::abi_decode(data) {
+        println!("    transfer(to: {}, amount: {})", call.to, call.amount);
+    } else if let Ok(function) = RouterFunction::from_calldata(data) {
+        // Every router entry point starts with amountIn, tokenIn, tokenOut, minAmountOut
+        println!("    {function:?}");
+        if let Some(words) = head_words(data, 4) {
+            println!("      amount_in:      {}", words[0]);
+            println!("      token_in:       {}", word_address(&words[1]));
+            println!("      token_out:      {}", word_address(&words[2]));
+            println!("      min_amount_out: {}", words[3]);
+        }
+    } else {
+        println!("    calldata: 0x{}", hex::encode(&data[4..]));
+    }
+}
+
+/// The first `count` static argument words after the selector.
+fn head_words(data: &[u8], count: usize) -> Option<Vec<U256>> {
+    (0..count)
+        .map(|index| {
+            let word = data.get(4 + 32 * index..4 + 32 * (index + 1))?;
+            Some(U256::from_be_slice(word))
+        })
+        .collect()
+}
+
+fn word_address(word: &U256) -> Address {
+    Address::from_word(word.to_be_bytes::<32>().into())
+}
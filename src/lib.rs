@@ -0,0 +1,40 @@
+//! EulerSwap arbitrage bot: streams pool states from Tycho, quotes and encodes swaps, and
+//! submits them through the executor contract. `run` is the whole bot, the public modules
+//! are the pieces reusable on their own.
+
+mod allowance;
+mod amount_in_strategy;
+mod amounts;
+mod app;
+pub mod cli;
+pub mod config;
+mod consts;
+pub mod encoding;
+pub mod error;
+mod exchanges;
+mod executor;
+mod gas;
+mod health;
+pub mod inspect;
+mod metrics;
+mod opportunity_log;
+mod pending;
+mod permit2;
+mod pool_registry;
+mod preflight;
+mod price_oracle;
+mod profit;
+mod provider;
+mod receipts;
+mod replacement;
+mod revert;
+pub mod signer;
+mod simulation;
+mod spread;
+mod stream;
+pub mod stream_handler;
+mod submitter;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+
+pub use app::run;
@@ -0,0 +1,110 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use anyhow::{Context, Result};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use tracing::info;
+use tycho_simulation::tycho_common::models::token::Token;
+
+const MESSAGES_RECEIVED: &str = "eulerswap_messages_received_total";
+const SWAPS_EVALUATED: &str = "eulerswap_swaps_evaluated_total";
+const SWAPS_ENCODED: &str = "eulerswap_swaps_encoded_total";
+const ENCODING_FAILURES: &str = "eulerswap_encoding_failures_total";
+const GAS_ESTIMATE_FAILURES: &str = "eulerswap_gas_estimate_failures_total";
+const QUOTE_FAILURES: &str = "eulerswap_quote_failures_total";
+const SIMULATION_REVERTS: &str = "eulerswap_simulation_reverts_total";
+const SUBMISSION_FAILURES: &str = "eulerswap_submission_failures_total";
+const TRANSACTIONS_SUCCEEDED: &str = "eulerswap_transactions_succeeded_total";
+const TRANSACTIONS_REVERTED: &str = "eulerswap_transactions_reverted_total";
+const TRANSACTIONS_STUCK: &str = "eulerswap_transactions_stuck_total";
+const ESTIMATED_PROFIT: &str = "eulerswap_estimated_profit";
+const TRACKED_POOLS: &str = "eulerswap_tracked_pools";
+
+/// Serves `/metrics` on `port` from a background task on the current runtime.
+pub fn install(port: u16) -> Result<()> {
+    let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    PrometheusBuilder::new()
+        .with_http_listener(address)
+        .install()
+        .with_context(|| format!("Can't start Prometheus exporter on {address}"))?;
+
+    info!(%address, "📈 Prometheus metrics exporter listening");
+    Ok(())
+}
+
+pub fn message_received() {
+    counter!(MESSAGES_RECEIVED).increment(1);
+}
+
+pub fn swap_evaluated() {
+    counter!(SWAPS_EVALUATED).increment(1);
+}
+
+pub fn swap_encoded() {
+    counter!(SWAPS_ENCODED).increment(1);
+}
+
+pub fn encoding_failed() {
+    counter!(ENCODING_FAILURES).increment(1);
+}
+
+pub fn gas_estimate_failed() {
+    counter!(GAS_ESTIMATE_FAILURES).increment(1);
+}
+
+pub fn quote_failed() {
+    counter!(QUOTE_FAILURES).increment(1);
+}
+
+pub fn simulation_reverted() {
+    counter!(SIMULATION_REVERTS).increment(1);
+}
+
+pub fn submission_failed() {
+    counter!(SUBMISSION_FAILURES).increment(1);
+}
+
+pub fn transaction_succeeded() {
+    counter!(TRANSACTIONS_SUCCEEDED).increment(1);
+}
+
+pub fn transaction_reverted() {
+    counter!(TRANSACTIONS_REVERTED).increment(1);
+}
+
+/// Pools in the registry after the latest stream message.
+pub fn tracked_pools(count: usize) {
+    gauge!(TRACKED_POOLS).set(count as f64);
+}
+
+pub fn transaction_stuck() {
+    counter!(TRANSACTIONS_STUCK).increment(1);
+}
+
+/// Net profit of an opportunity that passed the profit gate, in whole `token` units so
+/// tokens of different decimals share a scale, labelled with the token's symbol.
+pub fn record_profit(profit: &BigInt, token: &Token) {
+    histogram!(ESTIMATED_PROFIT, "token" => token.symbol.clone()).record(whole_units(profit, token));
+}
+
+fn whole_units(amount: &BigInt, token: &Token) -> f64 {
+    amount.to_f64().unwrap_or_default() / 10f64.powi(token.decimals as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::token;
+
+    #[test]
+    fn profits_are_scaled_by_token_decimals() {
+        assert_eq!(whole_units(&BigInt::from(150_000_000), &token(0x22, "WBTC", 8)), 1.5);
+        assert_eq!(whole_units(&BigInt::from(-2_500_000), &token(0xa0, "USDC", 6)), -2.5);
+        assert_eq!(
+            whole_units(&BigInt::from(10u128.pow(18)), &token(0xc0, "WETH", 18)),
+            1.0
+        );
+    }
+}
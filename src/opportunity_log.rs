@@ -0,0 +1,118 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use num_bigint::{BigInt, BigUint};
+use tracing::{info, warn};
+use tycho_simulation::tycho_common::models::token::Token;
+
+const HEADER: &str = "timestamp,block,component,sell_token,buy_token,amount_in,amount_out,estimated_profit,estimated_gas,submitted";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+static LOG: OnceLock<OpportunityLog> = OnceLock::new();
+
+struct OpportunityLog {
+    path: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+/// One evaluated swap, whether or not it went on to be submitted.
+pub struct Opportunity<'a> {
+    pub block: u64,
+    pub component: &'a str,
+    pub sell_token: &'a Token,
+    pub buy_token: &'a Token,
+    pub amount_in: &'a BigUint,
+    pub amount_out: &'a BigUint,
+    /// Net profit from the profit gate, `None` when no gate is configured.
+    pub estimated_profit: Option<&'a BigInt>,
+    pub estimated_gas: &'a BigUint,
+    pub submitted: bool,
+}
+
+/// Appends every later `record` to the CSV file at `path`, writing the header when the file
+/// is new. Lines are buffered and flushed from a background task on the current runtime.
+pub fn install(path: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Can't open OPPORTUNITY_LOG {}", path.display()))?;
+    let is_new = file.metadata().map(|meta| meta.len() == 0).unwrap_or(true);
+    let mut writer = BufWriter::new(file);
+    if is_new {
+        writeln!(writer, "{HEADER}")?;
+    }
+
+    let log = OpportunityLog {
+        path: path.to_path_buf(),
+        writer: Mutex::new(writer),
+    };
+    if LOG.set(log).is_err() {
+        warn!("Opportunity log already installed, ignoring {}", path.display());
+        return Ok(());
+    }
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush();
+        }
+    });
+
+    info!(path = %path.display(), "📝 Logging evaluated opportunities");
+    Ok(())
+}
+
+/// Buffers one CSV line, a no-op unless `install` was called.
+pub fn record(opportunity: &Opportunity) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let line = format!(
+        "{timestamp},{},{},{},{},{},{},{},{},{}",
+        opportunity.block,
+        csv_field(opportunity.component),
+        csv_field(&opportunity.sell_token.symbol),
+        csv_field(&opportunity.buy_token.symbol),
+        opportunity.amount_in,
+        opportunity.amount_out,
+        opportunity
+            .estimated_profit
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        opportunity.estimated_gas,
+        opportunity.submitted,
+    );
+
+    let mut writer = log.writer.lock().expect("opportunity log lock poisoned");
+    if let Err(e) = writeln!(writer, "{line}") {
+        warn!(path = %log.path.display(), error = %e, "Can't write to the opportunity log");
+    }
+}
+
+/// Writes out buffered lines, also called on shutdown.
+pub fn flush() {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    if let Err(e) = log.writer.lock().expect("opportunity log lock poisoned").flush() {
+        warn!(path = %log.path.display(), error = %e, "Can't flush the opportunity log");
+    }
+}
+
+/// Quotes fields holding a comma, quote or newline, as symbols are free text.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
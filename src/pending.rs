@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use alloy::primitives::TxHash;
+use tracing::debug;
+
+/// Submissions still in flight, keyed by the ids of every pool the transaction trades on
+/// joined with `+`. The same pool shows up in consecutive stream messages, and without
+/// this every one of them would submit an overlapping transaction for the same opportunity.
+#[derive(Debug)]
+pub struct PendingTracker {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    tx_hash: TxHash,
+    submitted_at: Instant,
+    /// A pool the transaction trades on was removed from the stream since.
+    cancelled: bool,
+}
+
+/// Whether the pending tracker `key` trades on `component`.
+fn touches(key: &str, component: &str) -> bool {
+    key.split('+').any(|id| id == component)
+}
+
+impl PendingTracker {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a submission within its TTL trades on `component`, alone or along with
+    /// other pools.
+    pub fn is_pending(&self, component: &str) -> bool {
+        self.entries
+            .lock()
+            .expect("pending tracker lock poisoned")
+            .iter()
+            .any(|(key, entry)| touches(key, component) && entry.submitted_at.elapsed() < self.ttl)
+    }
+
+    /// Tracks `tx_hash` under `key`. A replacement keeps the cancellation of the
+    /// transaction it replaces.
+    pub fn insert(&self, key: &str, tx_hash: TxHash) {
+        let mut entries = self.entries.lock().expect("pending tracker lock poisoned");
+        let cancelled = entries.get(key).is_some_and(|entry| entry.cancelled);
+        entries.insert(
+            key.to_string(),
+            Entry {
+                tx_hash,
+                submitted_at: Instant::now(),
+                cancelled,
+            },
+        );
+    }
+
+    /// Marks every submission trading on `component` as cancelled, returning their keys.
+    pub fn cancel(&self, component: &str) -> Vec<String> {
+        let mut entries = self.entries.lock().expect("pending tracker lock poisoned");
+        entries
+            .iter_mut()
+            .filter(|(key, _)| touches(key, component))
+            .map(|(key, entry)| {
+                entry.cancelled = true;
+                key.clone()
+            })
+            .collect()
+    }
+
+    /// Whether the submission under `key` trades on a pool removed since it was sent.
+    pub fn is_cancelled(&self, key: &str) -> bool {
+        self.entries
+            .lock()
+            .expect("pending tracker lock poisoned")
+            .get(key)
+            .is_some_and(|entry| entry.cancelled)
+    }
+
+    /// Hashes of the entries still within their TTL, dropping the expired ones.
+    pub fn in_flight(&self) -> Vec<(String, TxHash)> {
+        let mut entries = self.entries.lock().expect("pending tracker lock poisoned");
+        entries.retain(|key, entry| {
+            let alive = entry.submitted_at.elapsed() < self.ttl;
+            if !alive {
+                debug!(%key, tx_hash = %entry.tx_hash, "Pending transaction TTL expired");
+            }
+            alive
+        });
+        entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.tx_hash))
+            .collect()
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.entries
+            .lock()
+            .expect("pending tracker lock poisoned")
+            .remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removing_a_pool_cancels_every_submission_trading_on_it() {
+        let pending = PendingTracker::new(Duration::from_secs(60));
+        pending.insert("cheap+rich", TxHash::repeat_byte(1));
+        pending.insert("rich", TxHash::repeat_byte(2));
+        pending.insert("other", TxHash::repeat_byte(3));
+
+        let mut cancelled = pending.cancel("rich");
+        cancelled.sort();
+
+        assert_eq!(cancelled, ["cheap+rich", "rich"]);
+        assert!(pending.is_cancelled("cheap+rich"));
+        assert!(pending.is_cancelled("rich"));
+        assert!(!pending.is_cancelled("other"));
+        // Still pending, only no longer worth speeding up
+        assert!(pending.is_pending("rich"));
+    }
+
+    #[test]
+    fn a_replacement_stays_cancelled() {
+        let pending = PendingTracker::new(Duration::from_secs(60));
+        pending.insert("pool", TxHash::repeat_byte(1));
+        pending.cancel("pool");
+
+        pending.insert("pool", TxHash::repeat_byte(2));
+
+        assert!(pending.is_cancelled("pool"));
+        assert!(pending.cancel("richer").is_empty());
+    }
+
+    #[test]
+    fn every_pool_of_a_route_is_pending_with_it() {
+        let pending = PendingTracker::new(Duration::from_secs(60));
+        pending.insert("cheap+rich", TxHash::repeat_byte(1));
+
+        assert!(pending.is_pending("cheap"));
+        assert!(pending.is_pending("rich"));
+        assert!(!pending.is_pending("cheap+"));
+        assert!(!pending.is_pending("other"));
+
+        pending.remove("cheap+rich");
+        assert!(!pending.is_pending("rich"));
+    }
+}
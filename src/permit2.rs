@@ -0,0 +1,304 @@
+use alloy::primitives::aliases::{U48, U160};
+use alloy::primitives::{Address, Bytes as AlloyBytes, U256};
+use alloy::sol;
+use alloy::sol_types::{SolCall, SolStruct, eip712_domain};
+use num_bigint::BigUint;
+use tycho_execution::encoding::models::{
+    EncodedSolution, NativeAction, PermitSingle as TychoPermitSingle, Solution,
+};
+use tycho_simulation::evm::protocol::u256_num::biguint_to_u256;
+
+use crate::consts::PERMIT2_ADDRESS;
+use crate::encoding::RouterFunction;
+use crate::error::EncodingError;
+use crate::signer::TradeSigner;
+use crate::stream_handler::decode_address;
+
+sol! {
+    struct PermitDetails {
+        address token;
+        uint160 amount;
+        uint48 expiration;
+        uint48 nonce;
+    }
+
+    struct PermitSingle {
+        PermitDetails details;
+        address spender;
+        uint256 sigDeadline;
+    }
+
+    interface ITychoRouterPermit2 {
+        function singleSwapPermit2(
+            uint256 amountIn,
+            address tokenIn,
+            address tokenOut,
+            uint256 minAmountOut,
+            bool wrapEth,
+            bool unwrapEth,
+            address receiver,
+            PermitSingle permitSingle,
+            bytes signature,
+            bytes swaps
+        ) external payable returns (uint256);
+
+        function sequentialSwapPermit2(
+            uint256 amountIn,
+            address tokenIn,
+            address tokenOut,
+            uint256 minAmountOut,
+            bool wrapEth,
+            bool unwrapEth,
+            address receiver,
+            PermitSingle permitSingle,
+            bytes signature,
+            bytes swaps
+        ) external payable returns (uint256);
+
+        function splitSwapPermit2(
+            uint256 amountIn,
+            address tokenIn,
+            address tokenOut,
+            uint256 minAmountOut,
+            bool wrapEth,
+            bool unwrapEth,
+            uint256 nTokens,
+            address receiver,
+            PermitSingle permitSingle,
+            bytes signature,
+            bytes swaps
+        ) external payable returns (uint256);
+    }
+}
+
+/// Router calldata for a Permit2 solution. The permit the encoder prepared is signed with
+/// `signer`, so the router pulls the sell token through Permit2 and no router approval is
+/// needed.
+pub fn build_permit2_calldata(
+    encoded: EncodedSolution,
+    solution: &Solution,
+    chain_id: u64,
+    signer: &dyn TradeSigner,
+) -> Result<Vec<u8>, EncodingError> {
+    let permit = encoded.permit.as_ref().ok_or_else(|| {
+        EncodingError::InvalidRoute("Permit2 solution was encoded without a permit".to_string())
+    })?;
+    let permit = convert_permit(permit)?;
+
+    let domain = eip712_domain! {
+        name: "Permit2",
+        chain_id: chain_id,
+        verifying_contract: PERMIT2_ADDRESS,
+    };
+    let signature = signer
+        .sign_hash(&permit.eip712_signing_hash(&domain))
+        .map_err(|e| EncodingError::AbiEncode(format!("can't sign Permit2 permit: {e:#}")))?;
+    let signature = AlloyBytes::from(signature.as_bytes().to_vec());
+
+    let amount_in = biguint_to_u256(&solution.given_amount);
+    let token_in = decode_address(&solution.given_token)?;
+    let token_out = decode_address(&solution.checked_token)?;
+    let min_amount_out = biguint_to_u256(&solution.checked_amount);
+    let receiver = decode_address(&solution.receiver)?;
+    let wrap_eth = solution.native_action == Some(NativeAction::Wrap);
+    let unwrap_eth = solution.native_action == Some(NativeAction::Unwrap);
+    let swaps = AlloyBytes::from(encoded.swaps);
+
+    let calldata = match encoded.function_signature.parse::<RouterFunction>()? {
+        RouterFunction::SingleSwapPermit2 => ITychoRouterPermit2::singleSwapPermit2Call {
+            amountIn: amount_in,
+            tokenIn: token_in,
+            tokenOut: token_out,
+            minAmountOut: min_amount_out,
+            wrapEth: wrap_eth,
+            unwrapEth: unwrap_eth,
+            receiver,
+            permitSingle: permit,
+            signature,
+            swaps,
+        }
+        .abi_encode(),
+        RouterFunction::SequentialSwapPermit2 => ITychoRouterPermit2::sequentialSwapPermit2Call {
+            amountIn: amount_in,
+            tokenIn: token_in,
+            tokenOut: token_out,
+            minAmountOut: min_amount_out,
+            wrapEth: wrap_eth,
+            unwrapEth: unwrap_eth,
+            receiver,
+            permitSingle: permit,
+            signature,
+            swaps,
+        }
+        .abi_encode(),
+        RouterFunction::SplitSwapPermit2 => ITychoRouterPermit2::splitSwapPermit2Call {
+            amountIn: amount_in,
+            tokenIn: token_in,
+            tokenOut: token_out,
+            minAmountOut: min_amount_out,
+            wrapEth: wrap_eth,
+            unwrapEth: unwrap_eth,
+            nTokens: U256::from(encoded.n_tokens),
+            receiver,
+            permitSingle: permit,
+            signature,
+            swaps,
+        }
+        .abi_encode(),
+        other => {
+            return Err(EncodingError::UnsupportedFunctionSignature(format!(
+                "{other:?} doesn't take a Permit2 permit"
+            )));
+        }
+    };
+
+    Ok(calldata)
+}
+
+fn convert_permit(permit: &TychoPermitSingle) -> Result<PermitSingle, EncodingError> {
+    let details = &permit.details;
+    Ok(PermitSingle {
+        details: PermitDetails {
+            token: decode_address(&details.token)?,
+            amount: U160::try_from_be_slice(&details.amount.to_bytes_be())
+                .ok_or_else(|| out_of_range("amount", &details.amount))?,
+            expiration: U48::try_from_be_slice(&details.expiration.to_bytes_be())
+                .ok_or_else(|| out_of_range("expiration", &details.expiration))?,
+            nonce: U48::try_from_be_slice(&details.nonce.to_bytes_be())
+                .ok_or_else(|| out_of_range("nonce", &details.nonce))?,
+        },
+        spender: decode_address(&permit.spender)?,
+        sigDeadline: biguint_to_u256(&permit.sig_deadline),
+    })
+}
+
+fn out_of_range(field: &str, value: &BigUint) -> EncodingError {
+    EncodingError::AbiEncode(format!("Permit2 {field} {value} is out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::Signature;
+    use tycho_execution::encoding::models::PermitDetails as TychoPermitDetails;
+    use tycho_simulation::tycho_common::hex_bytes::Bytes;
+
+    use super::*;
+    use crate::testing::{MockSigner, ROUTER, encoded};
+
+    const TOKEN_IN: [u8; 20] = [0x22; 20];
+    const TOKEN_OUT: [u8; 20] = [0xa0; 20];
+
+    fn permit() -> TychoPermitSingle {
+        TychoPermitSingle {
+            details: TychoPermitDetails {
+                token: Bytes::from(TOKEN_IN.as_slice()),
+                amount: BigUint::from(1_000u32),
+                expiration: BigUint::from(1_800_000_000u64),
+                nonce: BigUint::from(7u32),
+            },
+            spender: Bytes::from(ROUTER.as_slice()),
+            sig_deadline: BigUint::from(1_700_000_000u64),
+        }
+    }
+
+    fn solution(signer: &MockSigner) -> Solution {
+        Solution {
+            sender: Bytes::from(signer.address().as_slice()),
+            receiver: Bytes::from(signer.address().as_slice()),
+            given_token: Bytes::from(TOKEN_IN.as_slice()),
+            given_amount: BigUint::from(1_000u32),
+            checked_token: Bytes::from(TOKEN_OUT.as_slice()),
+            exact_out: false,
+            checked_amount: BigUint::from(1_990u32),
+            swaps: Vec::new(),
+            native_action: None,
+        }
+    }
+
+    fn permit2_solution(function: RouterFunction) -> EncodedSolution {
+        EncodedSolution {
+            permit: Some(permit()),
+            ..encoded(function)
+        }
+    }
+
+    #[test]
+    fn signs_the_permit_for_the_chain_and_encodes_it_into_the_call() {
+        let signer = MockSigner::new();
+        let calldata = build_permit2_calldata(
+            permit2_solution(RouterFunction::SingleSwapPermit2),
+            &solution(&signer),
+            1,
+            &signer,
+        )
+        .unwrap();
+
+        let call = ITychoRouterPermit2::singleSwapPermit2Call::abi_decode(&calldata).unwrap();
+        assert_eq!(call.amountIn, U256::from(1_000u32));
+        assert_eq!(call.tokenIn, Address::from(TOKEN_IN));
+        assert_eq!(call.tokenOut, Address::from(TOKEN_OUT));
+        assert_eq!(call.minAmountOut, U256::from(1_990u32));
+        assert_eq!(call.receiver, signer.address());
+        assert_eq!(call.permitSingle.spender, ROUTER);
+        assert_eq!(call.permitSingle.details.nonce, U48::from(7u32));
+        assert_eq!(call.swaps.as_ref(), [0xab; 4].as_slice());
+
+        let domain = eip712_domain! {
+            name: "Permit2",
+            chain_id: 1,
+            verifying_contract: PERMIT2_ADDRESS,
+        };
+        let hash = call.permitSingle.eip712_signing_hash(&domain);
+        assert_eq!(signer.signed(), vec![hash]);
+        let signature = Signature::try_from(call.signature.as_ref()).unwrap();
+        assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), signer.address());
+    }
+
+    #[test]
+    fn the_signing_domain_depends_on_the_chain() {
+        let signer = MockSigner::new();
+        for chain_id in [1, 8453] {
+            build_permit2_calldata(
+                permit2_solution(RouterFunction::SplitSwapPermit2),
+                &solution(&signer),
+                chain_id,
+                &signer,
+            )
+            .unwrap();
+        }
+
+        let signed = signer.signed();
+        assert_eq!(signed.len(), 2);
+        assert_ne!(signed[0], signed[1]);
+    }
+
+    #[test]
+    fn rejects_missing_permits_non_permit2_functions_and_signer_failures() {
+        let signer = MockSigner::new();
+
+        let unsigned = build_permit2_calldata(
+            encoded(RouterFunction::SingleSwapPermit2),
+            &solution(&signer),
+            1,
+            &signer,
+        );
+        assert!(matches!(unsigned, Err(EncodingError::InvalidRoute(_))));
+
+        let plain = build_permit2_calldata(
+            permit2_solution(RouterFunction::SingleSwap),
+            &solution(&signer),
+            1,
+            &signer,
+        );
+        assert!(matches!(plain, Err(EncodingError::UnsupportedFunctionSignature(_))));
+
+        let locked = MockSigner::failing();
+        let refused = build_permit2_calldata(
+            permit2_solution(RouterFunction::SingleSwapPermit2),
+            &solution(&locked),
+            1,
+            &locked,
+        );
+        assert!(matches!(refused, Err(EncodingError::AbiEncode(_))));
+    }
+}
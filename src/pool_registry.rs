@@ -0,0 +1,131 @@
+use std::collections::{BTreeSet, HashMap};
+
+use tracing::{info, trace, warn};
+use tycho_simulation::protocol::models::{ProtocolComponent, Update};
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_core::simulation::protocol_sim::ProtocolSim;
+
+use crate::exchanges::accepts_v4_hook_pool;
+
+/// Token pair with the lower address first, so both directions share one key.
+pub type PairKey = (Bytes, Bytes);
+
+pub fn pair_key(a: &Bytes, b: &Bytes) -> PairKey {
+    if a <= b {
+        (a.clone(), b.clone())
+    } else {
+        (b.clone(), a.clone())
+    }
+}
+
+/// Components seen on the stream so far. Tycho only sends a component once in
+/// `new_pairs`, later updates carry just its state, so the component has to be kept here.
+/// The latest state of every pool is kept too, so pools of the same pair can be compared
+/// when only one of them changed.
+#[derive(Debug, Default)]
+pub struct PoolRegistry {
+    components: HashMap<String, ProtocolComponent>,
+    states: HashMap<String, Box<dyn ProtocolSim>>,
+    pairs: HashMap<PairKey, BTreeSet<String>>,
+    /// `V4_HOOK_ADDRESSES`, which the stream can only filter on coarsely.
+    extra_v4_hooks: Vec<Bytes>,
+}
+
+impl PoolRegistry {
+    pub fn new(extra_v4_hooks: Vec<Bytes>) -> Self {
+        Self {
+            extra_v4_hooks,
+            ..Self::default()
+        }
+    }
+
+    pub fn insert(&mut self, id: String, component: ProtocolComponent) {
+        for key in component_pairs(&component) {
+            self.pairs.entry(key).or_default().insert(id.clone());
+        }
+        self.components.insert(id, component);
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<ProtocolComponent> {
+        let component = self.components.remove(id)?;
+        self.states.remove(id);
+        for key in component_pairs(&component) {
+            if let Some(pools) = self.pairs.get_mut(&key) {
+                pools.remove(id);
+                if pools.is_empty() {
+                    self.pairs.remove(&key);
+                }
+            }
+        }
+        Some(component)
+    }
+
+    pub fn state(&self, id: &str) -> Option<&dyn ProtocolSim> {
+        self.states.get(id).map(Box::as_ref)
+    }
+
+    /// Ids of tracked pools trading `key`, in either direction.
+    pub fn pools_for_pair(&self, key: &PairKey) -> impl Iterator<Item = &String> {
+        self.pairs.get(key).into_iter().flatten()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ProtocolComponent> {
+        self.components.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Registers new pairs and drops removed ones from a stream update, returning the ids
+    /// of the pools it stopped tracking.
+    pub fn apply(&mut self, update: &Update) -> Vec<String> {
+        let mut removed = Vec::new();
+        for (id, component) in &update.new_pairs {
+            // Nothing to quote without a pair, and such a component is likely malformed
+            if component.tokens.len() < 2 {
+                warn!(
+                    pool = %id,
+                    protocol = %component.protocol_system,
+                    token_count = component.tokens.len(),
+                    "Ignoring component with fewer than two tokens"
+                );
+                continue;
+            }
+            if !accepts_v4_hook_pool(component, &self.extra_v4_hooks) {
+                trace!(pool = %id, "Hook is neither Euler's nor in V4_HOOK_ADDRESSES, ignoring pool");
+                continue;
+            }
+            self.insert(id.clone(), component.clone());
+        }
+        for (id, component) in &update.removed_pairs {
+            if self.remove(id).is_some() {
+                // Tycho doesn't say why, usually the pool fell below the TVL remove threshold
+                info!(
+                    pool = %id,
+                    protocol = %component.protocol_system,
+                    reason = "reported in removed_pairs",
+                    tracked_pools = self.len(),
+                    "🗑️ Pool removed from tracking"
+                );
+                removed.push(id.clone());
+            }
+        }
+        for (id, state) in &update.states {
+            if self.components.contains_key(id) {
+                self.states.insert(id.clone(), state.clone_box());
+            }
+        }
+        removed
+    }
+}
+
+/// Every unordered token pair a component trades.
+pub fn component_pairs(component: &ProtocolComponent) -> BTreeSet<PairKey> {
+    let tokens = &component.tokens;
+    tokens
+        .iter()
+        .enumerate()
+        .flat_map(|(i, a)| tokens[i + 1..].iter().map(move |b| pair_key(&a.address, &b.address)))
+        .collect()
+}
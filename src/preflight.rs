@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use alloy::primitives::{Address, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::sol_types::{SolCall, SolValue};
+use anyhow::{Context, Result, bail};
+use num_bigint::BigUint;
+use tracing::{info, warn};
+use tycho_simulation::evm::protocol::u256_num::u256_to_biguint;
+use tycho_simulation::tycho_common::models::token::Token;
+
+use crate::amounts::{DecimalAmount, format_amount, format_token_amount};
+use crate::config::ConfigSource;
+use crate::consts::NATIVE_ETH_ADDRESS;
+use crate::encoding::IERC20;
+
+const DEFAULT_INTERVAL_BLOCKS: u64 = 100;
+const ETH_DECIMALS: u32 = 18;
+
+/// What a failed startup check does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreflightMode {
+    /// Refuse to start.
+    #[default]
+    Fail,
+    /// Log the shortfall and start anyway.
+    Warn,
+}
+
+impl std::str::FromStr for PreflightMode {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        match raw.trim().to_lowercase().as_str() {
+            "fail" => Ok(Self::Fail),
+            "warn" => Ok(Self::Warn),
+            other => bail!("Unknown PREFLIGHT_MODE {other:?}, expected fail or warn"),
+        }
+    }
+}
+
+/// Minimum balances checked at startup and, for gas funds, every `interval_blocks`.
+#[derive(Debug, Clone)]
+pub struct PreflightConfig {
+    pub mode: PreflightMode,
+    /// ETH the sending account needs for gas, `None` only requires a non-zero balance.
+    pub min_eth_balance: Option<DecimalAmount>,
+    /// `MIN_TOKEN_BALANCE` for every target token, overridden by `MIN_TOKEN_BALANCE_<SYMBOL>`.
+    pub min_token_balance: Option<DecimalAmount>,
+    pub token_overrides: HashMap<String, DecimalAmount>,
+    pub interval_blocks: u64,
+}
+
+impl PreflightConfig {
+    pub fn from_source(source: &ConfigSource) -> Result<Self> {
+        let mode = source
+            .get("PREFLIGHT_MODE")
+            .map(|raw| raw.parse())
+            .transpose()?
+            .unwrap_or_default();
+        let min_eth_balance = source
+            .get("MIN_ETH_BALANCE")
+            .map(|raw| raw.parse().context("Can't parse MIN_ETH_BALANCE"))
+            .transpose()?;
+        if let Some(amount) = &min_eth_balance {
+            amount.to_base_units(ETH_DECIMALS).context("Can't use MIN_ETH_BALANCE")?;
+        }
+        let min_token_balance = source
+            .get("MIN_TOKEN_BALANCE")
+            .map(|raw| raw.parse().context("Can't parse MIN_TOKEN_BALANCE"))
+            .transpose()?;
+
+        let mut token_overrides = HashMap::new();
+        for key in source.keys_with_prefix("MIN_TOKEN_BALANCE_") {
+            let Some(raw) = source.get(&key) else {
+                continue;
+            };
+            let amount = raw.parse().with_context(|| format!("Can't parse {key}"))?;
+            token_overrides.insert(key["MIN_TOKEN_BALANCE_".len()..].to_uppercase(), amount);
+        }
+
+        let interval_blocks = match source.get("PREFLIGHT_INTERVAL_BLOCKS") {
+            Some(raw) => raw
+                .trim()
+                .parse()
+                .context("Can't parse PREFLIGHT_INTERVAL_BLOCKS")?,
+            None => DEFAULT_INTERVAL_BLOCKS,
+        };
+
+        Ok(Self {
+            mode,
+            min_eth_balance,
+            min_token_balance,
+            token_overrides,
+            interval_blocks,
+        })
+    }
+
+    fn min_eth_wei(&self) -> BigUint {
+        self.min_eth_balance
+            .as_ref()
+            // Checked against ETH's decimals when the config was loaded
+            .and_then(|amount| amount.to_base_units(ETH_DECIMALS).ok())
+            .unwrap_or_else(|| BigUint::from(1u32))
+    }
+
+    fn min_for_token(&self, token: &Token) -> Result<Option<BigUint>> {
+        self.token_overrides
+            .get(&token.symbol.to_uppercase())
+            .or(self.min_token_balance.as_ref())
+            .map(|amount| amount.to_base_units(token.decimals))
+            .transpose()
+            .with_context(|| format!("Can't use the minimum {} balance", token.symbol))
+    }
+}
+
+/// Accounts and tokens the preflight check looks at.
+pub struct Accounts<'a> {
+    /// Sends the transactions and pays their gas.
+    pub gas_payer: Address,
+    /// Holds the sell tokens, the executor contract.
+    pub holder: Address,
+    /// Spender the holder must have approved ahead of trading, `None` when every trade
+    /// approves in its own batch.
+    pub spender: Option<Address>,
+    pub tokens: Vec<&'a Token>,
+}
+
+/// Logs the gas payer's ETH and the holder's target token balances and allowances as a
+/// table, then fails or warns as `PREFLIGHT_MODE` says when any is below its minimum.
+pub async fn run(
+    provider: &impl Provider,
+    config: &PreflightConfig,
+    accounts: &Accounts<'_>,
+) -> Result<()> {
+    let mut table = String::new();
+    let mut shortfalls = Vec::new();
+
+    let eth = provider
+        .get_balance(accounts.gas_payer)
+        .await
+        .context("Can't fetch the gas payer's ETH balance")?;
+    let eth = u256_to_biguint(eth);
+    let min_eth = config.min_eth_wei();
+    writeln!(
+        table,
+        "{:<10} {:<42} {:>28} {:>28}",
+        "token", "account", "balance", "allowance"
+    )?;
+    writeln!(
+        table,
+        "{:<10} {:<42} {:>28} {:>28}",
+        "ETH (gas)",
+        accounts.gas_payer.to_string(),
+        format_eth(&eth),
+        "-"
+    )?;
+    if eth < min_eth {
+        shortfalls.push(format!(
+            "gas payer holds {}, needs {}",
+            format_eth(&eth),
+            format_eth(&min_eth)
+        ));
+    }
+
+    for token in &accounts.tokens {
+        let address = Address::from_slice(&token.address);
+        let balance = u256_to_biguint(token_balance(provider, address, accounts.holder).await?);
+        let allowance = match accounts.spender {
+            Some(spender) if address != NATIVE_ETH_ADDRESS => {
+                let allowance = token_allowance(provider, address, accounts.holder, spender).await?;
+                format_token_amount(&u256_to_biguint(allowance), token)
+            }
+            _ => "-".to_string(),
+        };
+        writeln!(
+            table,
+            "{:<10} {:<42} {:>28} {:>28}",
+            token.symbol,
+            accounts.holder.to_string(),
+            format_token_amount(&balance, token),
+            allowance
+        )?;
+        if let Some(min) = config.min_for_token(token)?
+            && balance < min
+        {
+            shortfalls.push(format!(
+                "{} holds {}, needs {}",
+                accounts.holder,
+                format_token_amount(&balance, token),
+                format_token_amount(&min, token)
+            ));
+        }
+    }
+
+    info!("🩺 Preflight balances\n{table}");
+    if shortfalls.is_empty() {
+        info!("✅ Preflight check passed");
+        return Ok(());
+    }
+    match config.mode {
+        PreflightMode::Fail => bail!("Preflight check failed: {}", shortfalls.join("; ")),
+        PreflightMode::Warn => {
+            for shortfall in &shortfalls {
+                warn!("⚠️ Preflight: {shortfall}");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Warns when the gas payer's ETH fell below `MIN_ETH_BALANCE` since startup. Errors are
+/// only logged, the bot keeps running.
+pub async fn check_gas_funds(provider: &impl Provider, config: &PreflightConfig, gas_payer: Address) {
+    match provider.get_balance(gas_payer).await {
+        Ok(balance) => {
+            let balance = u256_to_biguint(balance);
+            let min = config.min_eth_wei();
+            if balance < min {
+                warn!(
+                    %gas_payer,
+                    balance = %format_eth(&balance),
+                    min = %format_eth(&min),
+                    "⛽ Gas funds below MIN_ETH_BALANCE"
+                );
+            }
+        }
+        Err(e) => warn!(%gas_payer, error = %e, "Can't fetch the gas payer's ETH balance"),
+    }
+}
+
+async fn token_balance(provider: &impl Provider, token: Address, owner: Address) -> Result<U256> {
+    if token == NATIVE_ETH_ADDRESS {
+        return provider
+            .get_balance(owner)
+            .await
+            .context("Can't fetch the ETH balance");
+    }
+    let tx = TransactionRequest::default()
+        .to(token)
+        .input(IERC20::balanceOfCall { owner }.abi_encode().into());
+    let output = provider
+        .call(tx)
+        .await
+        .with_context(|| format!("balanceOf call on {token} failed"))?;
+    U256::abi_decode(&output).with_context(|| format!("balanceOf on {token} returned no uint256"))
+}
+
+async fn token_allowance(
+    provider: &impl Provider,
+    token: Address,
+    owner: Address,
+    spender: Address,
+) -> Result<U256> {
+    let tx = TransactionRequest::default()
+        .to(token)
+        .input(IERC20::allowanceCall { owner, spender }.abi_encode().into());
+    let output = provider
+        .call(tx)
+        .await
+        .with_context(|| format!("allowance call on {token} failed"))?;
+    U256::abi_decode(&output).with_context(|| format!("allowance on {token} returned no uint256"))
+}
+
+fn format_eth(wei: &BigUint) -> String {
+    format_amount(wei, ETH_DECIMALS, "ETH")
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::node_bindings::Anvil;
+    use alloy::providers::ProviderBuilder;
+
+    use super::*;
+    use crate::testing::token;
+
+    fn preflight_config(pairs: &[(&str, &str)]) -> PreflightConfig {
+        PreflightConfig::from_source(&ConfigSource::from_pairs(pairs)).unwrap()
+    }
+
+    // Anvil funds its dev accounts with 10000 ETH each
+    #[tokio::test]
+    #[ignore = "needs anvil on PATH, run with --ignored"]
+    async fn funded_accounts_pass_and_empty_ones_fail_against_anvil() {
+        let anvil = Anvil::new().spawn();
+        let provider = ProviderBuilder::new().connect_http(anvil.endpoint_url());
+        let eth = token(0xee, "ETH", 18);
+        let funded = anvil.addresses()[0];
+        let empty = Address::repeat_byte(0x42);
+        let accounts = |gas_payer, holder| Accounts {
+            gas_payer,
+            holder,
+            spender: None,
+            tokens: vec![&eth],
+        };
+        let config = preflight_config(&[("MIN_ETH_BALANCE", "1"), ("MIN_TOKEN_BALANCE", "100")]);
+
+        run(&provider, &config, &accounts(funded, funded)).await.unwrap();
+
+        let error = run(&provider, &config, &accounts(empty, funded)).await.unwrap_err().to_string();
+        assert!(error.contains("gas payer holds 0 ETH, needs 1 ETH"), "{error}");
+
+        let error = run(&provider, &config, &accounts(funded, empty)).await.unwrap_err().to_string();
+        assert!(error.contains(&format!("{empty} holds")), "{error}");
+        assert!(!error.contains("gas payer"), "{error}");
+
+        let warn_only = preflight_config(&[("MIN_ETH_BALANCE", "1"), ("PREFLIGHT_MODE", "warn")]);
+        run(&provider, &warn_only, &accounts(empty, empty)).await.unwrap();
+    }
+}
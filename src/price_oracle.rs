@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use alloy::hex;
+use anyhow::{Context, Result, anyhow, bail};
+use num_bigint::{BigInt, BigUint};
+use num_traits::ToPrimitive;
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::Chain;
+use tycho_simulation::tycho_common::models::token::Token;
+
+use crate::config::ConfigSource;
+
+const COINGECKO_API_URL: &str = "https://api.coingecko.com/api/v3";
+/// Coingecko's free tier allows a few dozen calls a minute, far fewer than pool updates.
+const COINGECKO_CACHE_TTL: Duration = Duration::from_secs(60);
+/// Gives up on a Coingecko request, a hung one would stall the profit gate with it.
+const COINGECKO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// USD valuation of tokens, used for logging notionals and USD profit thresholds.
+pub trait PriceOracle: Send + Sync {
+    fn price_usd(&self, token: &Token) -> impl Future<Output = Result<f64>> + Send;
+}
+
+/// Where USD prices come from, see `PRICE_ORACLE`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OracleConfig {
+    /// Fixed prices from `PRICE_USD_<SYMBOL>` keys.
+    Static(HashMap<String, f64>),
+    /// Coingecko's token price endpoint, with an optional `COINGECKO_API_KEY`.
+    Coingecko { api_key: Option<String> },
+}
+
+impl OracleConfig {
+    /// `PRICE_ORACLE=static|coingecko`. Without it, static prices are used when any
+    /// `PRICE_USD_*` key is set, otherwise no oracle is configured.
+    pub fn from_source(source: &ConfigSource) -> Result<Option<Self>> {
+        let mut prices = HashMap::new();
+        for key in source.keys_with_prefix("PRICE_USD_") {
+            let Some(raw) = source.get(&key) else {
+                continue;
+            };
+            let price: f64 = raw
+                .trim()
+                .parse()
+                .with_context(|| format!("Can't parse {key} as a number"))?;
+            prices.insert(key["PRICE_USD_".len()..].to_uppercase(), price);
+        }
+
+        match source.get("PRICE_ORACLE").as_deref().map(str::trim) {
+            None if prices.is_empty() => Ok(None),
+            None | Some("static") => Ok(Some(Self::Static(prices))),
+            Some("coingecko") => Ok(Some(Self::Coingecko {
+                api_key: source.get("COINGECKO_API_KEY"),
+            })),
+            Some(other) => bail!("Unknown PRICE_ORACLE {other:?}, expected static or coingecko"),
+        }
+    }
+}
+
+/// Prices configured up front, looked up by token symbol.
+#[derive(Debug, Clone, Default)]
+pub struct StaticPriceOracle {
+    prices: HashMap<String, f64>,
+}
+
+impl StaticPriceOracle {
+    pub fn new(prices: HashMap<String, f64>) -> Self {
+        Self { prices }
+    }
+}
+
+impl PriceOracle for StaticPriceOracle {
+    async fn price_usd(&self, token: &Token) -> Result<f64> {
+        self.prices
+            .get(&token.symbol.to_uppercase())
+            .copied()
+            .ok_or_else(|| anyhow!("No PRICE_USD_{} configured", token.symbol.to_uppercase()))
+    }
+}
+
+/// Prices from Coingecko's `simple/token_price` endpoint, cached per token for a minute.
+#[derive(Debug)]
+pub struct CoingeckoOracle {
+    client: reqwest::Client,
+    base_url: String,
+    platform: &'static str,
+    api_key: Option<String>,
+    cache: Mutex<HashMap<Bytes, (f64, Instant)>>,
+}
+
+impl CoingeckoOracle {
+    pub fn new(chain: Chain, api_key: Option<String>) -> Result<Self> {
+        Self::with_endpoint(chain, api_key, COINGECKO_API_URL, COINGECKO_TIMEOUT)
+    }
+
+    fn with_endpoint(
+        chain: Chain,
+        api_key: Option<String>,
+        base_url: &str,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let platform = match chain {
+            Chain::Ethereum => "ethereum",
+            Chain::Base => "base",
+            Chain::Unichain => "unichain",
+            other => bail!("Coingecko prices are not supported for {other:?}"),
+        };
+
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .context("Can't build the Coingecko HTTP client")?,
+            base_url: base_url.to_string(),
+            platform,
+            api_key,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn fetch(&self, address: &str) -> Result<f64> {
+        let mut request = self
+            .client
+            .get(format!(
+                "{}/simple/token_price/{}",
+                self.base_url, self.platform
+            ))
+            .query(&[("contract_addresses", address), ("vs_currencies", "usd")]);
+        if let Some(api_key) = &self.api_key {
+            request = request.header("x-cg-demo-api-key", api_key);
+        }
+
+        let prices: HashMap<String, HashMap<String, f64>> = request
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Can't decode Coingecko response")?;
+
+        prices
+            .get(address)
+            .and_then(|quote| quote.get("usd"))
+            .copied()
+            .ok_or_else(|| anyhow!("Coingecko has no USD price for {address}"))
+    }
+}
+
+impl PriceOracle for CoingeckoOracle {
+    async fn price_usd(&self, token: &Token) -> Result<f64> {
+        let cached = self
+            .cache
+            .lock()
+            .expect("price cache lock poisoned")
+            .get(&token.address)
+            .copied();
+        if let Some((price, fetched_at)) = cached
+            && fetched_at.elapsed() < COINGECKO_CACHE_TTL
+        {
+            return Ok(price);
+        }
+
+        // Not holding the cache during the request, cached tokens stay instant meanwhile
+        let address = format!("0x{}", hex::encode(&token.address));
+        let price = self
+            .fetch(&address)
+            .await
+            .with_context(|| format!("Can't fetch USD price for {}", token.symbol))?;
+        self.cache
+            .lock()
+            .expect("price cache lock poisoned")
+            .insert(token.address.clone(), (price, Instant::now()));
+
+        Ok(price)
+    }
+}
+
+/// The oracle selected by `OracleConfig`.
+#[derive(Debug)]
+pub enum Oracle {
+    Static(StaticPriceOracle),
+    Coingecko(CoingeckoOracle),
+}
+
+impl Oracle {
+    pub fn from_config(config: &OracleConfig, chain: Chain) -> Result<Self> {
+        match config {
+            OracleConfig::Static(prices) => {
+                Ok(Self::Static(StaticPriceOracle::new(prices.clone())))
+            }
+            OracleConfig::Coingecko { api_key } => Ok(Self::Coingecko(CoingeckoOracle::new(
+                chain,
+                api_key.clone(),
+            )?)),
+        }
+    }
+}
+
+impl PriceOracle for Oracle {
+    async fn price_usd(&self, token: &Token) -> Result<f64> {
+        match self {
+            Self::Static(oracle) => oracle.price_usd(token).await,
+            Self::Coingecko(oracle) => oracle.price_usd(token).await,
+        }
+    }
+}
+
+/// USD value of `amount` base units of `token`.
+pub async fn value_usd(oracle: &impl PriceOracle, amount: &BigUint, token: &Token) -> Result<f64> {
+    signed_value_usd(oracle, &BigInt::from(amount.clone()), token).await
+}
+
+/// Like `value_usd`, for amounts that may be negative such as a losing round trip.
+pub async fn signed_value_usd(
+    oracle: &impl PriceOracle,
+    amount: &BigInt,
+    token: &Token,
+) -> Result<f64> {
+    let price = oracle.price_usd(token).await?;
+    let units = amount
+        .to_f64()
+        .ok_or_else(|| anyhow!("Amount {amount} doesn't fit in an f64"))?;
+
+    Ok(units / 10f64.powi(token.decimals as i32) * price)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::token;
+
+    #[tokio::test]
+    async fn a_hung_fetch_neither_blocks_cached_tokens_nor_hangs() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        // Accepts connections and never answers
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+        let oracle =
+            CoingeckoOracle::with_endpoint(Chain::Ethereum, None, &url, Duration::from_millis(300))
+                .unwrap();
+        let (cached, uncached) = (token(0x11, "AAA", 18), token(0x22, "BBB", 18));
+        oracle
+            .cache
+            .lock()
+            .unwrap()
+            .insert(cached.address.clone(), (2.5, Instant::now()));
+
+        let (slow, fast) = tokio::join!(oracle.price_usd(&uncached), async {
+            tokio::time::timeout(Duration::from_millis(100), oracle.price_usd(&cached)).await
+        });
+
+        assert_eq!(fast.expect("cached price waited on the fetch").unwrap(), 2.5);
+        let error = format!("{:#}", slow.unwrap_err());
+        assert!(error.contains("Can't fetch USD price for BBB"), "{error}");
+    }
+}
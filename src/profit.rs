@@ -0,0 +1,422 @@
+use anyhow::Result;
+use num_bigint::{BigInt, BigUint};
+use num_traits::ToPrimitive;
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::Chain;
+use tycho_simulation::tycho_common::models::token::Token;
+
+use crate::config::AppConfig;
+use crate::consts::{WETH_ETHEREUM, WETH_OP_STACK};
+use crate::pool_registry::{PoolRegistry, pair_key};
+use crate::price_oracle::{PriceOracle, signed_value_usd, value_usd};
+
+const WEI_PER_ETH: f64 = 1e18;
+
+pub fn wrapped_native_address(chain: Chain) -> alloy::primitives::Address {
+    match chain {
+        Chain::Base | Chain::Unichain => WETH_OP_STACK,
+        _ => WETH_ETHEREUM,
+    }
+}
+
+/// The wrapped native token as a `Token`, for pricing gas through an oracle.
+pub fn wrapped_native_token(chain: Chain) -> Token {
+    Token::new(
+        &Bytes::from(wrapped_native_address(chain).as_slice()),
+        "WETH",
+        18,
+        0,
+        &[],
+        chain,
+        100,
+    )
+}
+
+/// Gas cost expressed in `token` units. Only the wrapped native token has a known
+/// exchange rate to gas, anything else needs `gas_cost_via_oracle`.
+pub fn gas_cost_in_token(
+    token: &Token,
+    chain: Chain,
+    gas_units: &BigUint,
+    gas_price: u128,
+) -> Option<BigUint> {
+    (token.address.as_ref() == wrapped_native_address(chain).as_slice())
+        .then(|| gas_units * BigUint::from(gas_price))
+}
+
+/// Net profit in sell token units: what exiting the position hands back, minus what was
+/// put in and the gas spent doing it.
+pub fn compute_profit(amount_in: &BigUint, amount_back: &BigUint, gas_cost: &BigUint) -> BigInt {
+    BigInt::from(amount_back.clone()) - BigInt::from(amount_in.clone()) - BigInt::from(gas_cost.clone())
+}
+
+/// What selling `amount` of `buy_token` back for `sell_token` fetches away from the pool
+/// `entry_id` it was bought in: the best quote among the other tracked pools of the pair,
+/// otherwise the oracle's USD prices. Quoting the entry pool again would only measure its
+/// fee twice, so with neither available the trade can't be priced.
+pub async fn exit_quote(
+    registry: &PoolRegistry,
+    oracle: Option<&impl PriceOracle>,
+    entry_id: &str,
+    sell_token: &Token,
+    buy_token: &Token,
+    amount: &BigUint,
+) -> Result<BigUint, ProfitShortfall> {
+    let best_pool = registry
+        .pools_for_pair(&pair_key(&sell_token.address, &buy_token.address))
+        .filter(|id| id.as_str() != entry_id)
+        .filter_map(|id| registry.state(id))
+        .filter_map(|state| state.get_amount_out(amount.clone(), buy_token, sell_token).ok())
+        .map(|result| result.amount)
+        .max();
+    if let Some(amount_back) = best_pool {
+        return Ok(amount_back);
+    }
+
+    let Some(oracle) = oracle else {
+        return Err(ProfitShortfall {
+            threshold: "exit price",
+            shortfall: format!(
+                "no other pool for {}/{} and no PRICE_ORACLE",
+                buy_token.symbol, sell_token.symbol
+            ),
+        });
+    };
+    convert_via_oracle(oracle, amount, buy_token, sell_token)
+        .await
+        .map_err(|e| ProfitShortfall {
+            threshold: "exit price",
+            shortfall: format!("{e:#}"),
+        })
+}
+
+/// `amount` of `from` expressed in `to` units at the oracle's USD prices.
+pub async fn convert_via_oracle(
+    oracle: &impl PriceOracle,
+    amount: &BigUint,
+    from: &Token,
+    to: &Token,
+) -> Result<BigUint> {
+    let amount_usd = value_usd(oracle, amount, from).await?;
+    let to_price = oracle.price_usd(to).await?;
+    anyhow::ensure!(to_price > 0.0, "{} is priced at ${to_price}", to.symbol);
+
+    let units = amount_usd / to_price * 10f64.powi(to.decimals as i32);
+    Ok(BigUint::from(units.floor() as u128))
+}
+
+/// Converts a gas cost in wei to `token` units through USD prices, for sell tokens
+/// other than the wrapped native one.
+pub async fn gas_cost_via_oracle(
+    oracle: &impl PriceOracle,
+    token: &Token,
+    chain: Chain,
+    gas_units: &BigUint,
+    gas_price: u128,
+) -> Result<BigUint> {
+    let gas_wei = (gas_units * BigUint::from(gas_price)).to_f64().unwrap_or(f64::MAX);
+    let eth_price = oracle.price_usd(&wrapped_native_token(chain)).await?;
+    let token_price = oracle.price_usd(token).await?;
+
+    let gas_usd = gas_wei / WEI_PER_ETH * eth_price;
+    let units = gas_usd / token_price * 10f64.powi(token.decimals as i32);
+    Ok(BigUint::from(units.ceil() as u128))
+}
+
+/// The first profit threshold a round trip failed, with how far it fell short.
+#[derive(Debug)]
+pub struct ProfitShortfall {
+    pub threshold: &'static str,
+    pub shortfall: String,
+}
+
+/// Gas cost in `token` units, priced directly for WETH and through `oracle` otherwise.
+pub async fn gas_cost(
+    chain: Chain,
+    oracle: Option<&impl PriceOracle>,
+    token: &Token,
+    gas_units: &BigUint,
+    gas_price: u128,
+) -> Result<BigUint, ProfitShortfall> {
+    match (gas_cost_in_token(token, chain, gas_units, gas_price), oracle) {
+        (Some(gas_cost), _) => Ok(gas_cost),
+        (None, Some(oracle)) => gas_cost_via_oracle(oracle, token, chain, gas_units, gas_price)
+            .await
+            .map_err(|e| ProfitShortfall {
+                threshold: "gas pricing",
+                shortfall: format!("{e:#}"),
+            }),
+        // Counting gas as free would pass trades that lose it all to fees
+        (None, None) => Err(ProfitShortfall {
+            threshold: "gas pricing",
+            shortfall: format!("no PRICE_ORACLE to price gas in {}", token.symbol),
+        }),
+    }
+}
+
+/// Net profit in sell token units of turning `amount_in` of `sell_token` into
+/// `amount_back` of it. Gas is priced in the sell token directly for WETH and through
+/// `oracle` otherwise.
+pub async fn net_profit(
+    chain: Chain,
+    oracle: Option<&impl PriceOracle>,
+    sell_token: &Token,
+    amount_in: &BigUint,
+    amount_back: &BigUint,
+    gas_units: &BigUint,
+    gas_price: u128,
+) -> Result<BigInt, ProfitShortfall> {
+    let gas_cost = gas_cost(chain, oracle, sell_token, gas_units, gas_price).await?;
+    Ok(compute_profit(amount_in, amount_back, &gas_cost))
+}
+
+/// Checks a trade that turns `amount_in` of `sell_token` into `amount_back` of it against
+/// `MIN_PROFIT_WEI`, `MIN_PROFIT_BPS` and `MIN_PROFIT_USD`, returning the net profit in
+/// sell token units when every configured threshold is cleared.
+#[allow(clippy::too_many_arguments)]
+pub async fn check_profit(
+    config: &AppConfig,
+    oracle: Option<&impl PriceOracle>,
+    sell_token: &Token,
+    amount_in: &BigUint,
+    amount_back: &BigUint,
+    gas_units: &BigUint,
+    gas_price: u128,
+) -> Result<BigInt, ProfitShortfall> {
+    let profit = net_profit(
+        config.chain,
+        oracle,
+        sell_token,
+        amount_in,
+        amount_back,
+        gas_units,
+        gas_price,
+    )
+    .await?;
+    check_thresholds(config, oracle, sell_token, amount_in, profit).await
+}
+
+/// Checks an already computed net `profit` against the configured thresholds.
+pub async fn check_thresholds(
+    config: &AppConfig,
+    oracle: Option<&impl PriceOracle>,
+    sell_token: &Token,
+    amount_in: &BigUint,
+    profit: BigInt,
+) -> Result<BigInt, ProfitShortfall> {
+    if let Some(min_profit) = &config.min_profit_wei {
+        let min_profit = BigInt::from(min_profit.clone());
+        if profit <= min_profit {
+            return Err(ProfitShortfall {
+                threshold: "MIN_PROFIT_WEI",
+                shortfall: (min_profit - &profit).to_string(),
+            });
+        }
+    }
+
+    if let Some(min_profit_bps) = config.min_profit_bps {
+        let min_profit = BigInt::from(amount_in.clone()) * min_profit_bps / 10_000;
+        if profit <= min_profit {
+            return Err(ProfitShortfall {
+                threshold: "MIN_PROFIT_BPS",
+                shortfall: (min_profit - &profit).to_string(),
+            });
+        }
+    }
+
+    if let (Some(min_profit_usd), Some(oracle)) = (config.min_profit_usd, oracle) {
+        let profit_usd = signed_value_usd(oracle, &profit, sell_token)
+            .await
+            .map_err(|e| ProfitShortfall {
+                threshold: "MIN_PROFIT_USD",
+                shortfall: format!("{e:#}"),
+            })?;
+        if profit_usd <= min_profit_usd {
+            return Err(ProfitShortfall {
+                threshold: "MIN_PROFIT_USD",
+                shortfall: format!("${:.2}", min_profit_usd - profit_usd),
+            });
+        }
+    }
+
+    Ok(profit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tycho_simulation::tycho_core::simulation::protocol_sim::ProtocolSim;
+
+    use super::*;
+    use crate::price_oracle::StaticPriceOracle;
+    use crate::testing::{config, pool, registry, token};
+
+    const ONE: u128 = 1_000_000_000_000_000_000;
+
+    fn no_oracle() -> Option<&'static StaticPriceOracle> {
+        None
+    }
+
+    #[tokio::test]
+    async fn exit_quote_uses_the_best_other_pool_of_the_pair() {
+        let (aaa, bbb) = (token(0x11, "AAA", 18), token(0x22, "BBB", 18));
+        let pair = [aaa.clone(), bbb.clone()];
+        let registry = registry(&[
+            ("entry", &pair, pool(1_000 * ONE, 2_000 * ONE)),
+            ("deep", &pair, pool(1_000 * ONE, 1_900 * ONE)),
+            ("shallow", &pair, pool(10 * ONE, 19 * ONE)),
+        ]);
+        let amount = BigUint::from(10 * ONE);
+
+        let amount_back = exit_quote(&registry, no_oracle(), "entry", &aaa, &bbb, &amount)
+            .await
+            .unwrap();
+
+        let quote = |reserve0, reserve1| {
+            pool(reserve0, reserve1)
+                .get_amount_out(amount.clone(), &bbb, &aaa)
+                .unwrap()
+                .amount
+        };
+        assert_eq!(amount_back, quote(1_000 * ONE, 1_900 * ONE));
+        assert!(amount_back > quote(10 * ONE, 19 * ONE));
+        // The entry pool alone would have answered with its own, worse price
+        assert!(amount_back > quote(1_000 * ONE, 2_000 * ONE));
+    }
+
+    #[tokio::test]
+    async fn exit_quote_falls_back_to_the_oracle() {
+        let (aaa, bbb) = (token(0x11, "AAA", 18), token(0x22, "BBB", 18));
+        let registry = registry(&[("entry", &[aaa.clone(), bbb.clone()], pool(ONE, ONE))]);
+        let oracle = StaticPriceOracle::new(HashMap::from([
+            ("AAA".to_string(), 2.0),
+            ("BBB".to_string(), 1.0),
+        ]));
+
+        let amount_back = exit_quote(
+            &registry,
+            Some(&oracle),
+            "entry",
+            &aaa,
+            &bbb,
+            &BigUint::from(10 * ONE),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(amount_back, BigUint::from(5 * ONE));
+    }
+
+    #[tokio::test]
+    async fn exit_quote_fails_without_another_pool_or_an_oracle() {
+        let (aaa, bbb) = (token(0x11, "AAA", 18), token(0x22, "BBB", 18));
+        let registry = registry(&[("entry", &[aaa.clone(), bbb.clone()], pool(ONE, ONE))]);
+
+        let miss = exit_quote(&registry, no_oracle(), "entry", &aaa, &bbb, &BigUint::from(ONE))
+            .await
+            .unwrap_err();
+
+        assert_eq!(miss.threshold, "exit price");
+        assert!(miss.shortfall.contains("BBB/AAA"), "{}", miss.shortfall);
+    }
+
+    #[tokio::test]
+    async fn gas_is_priced_in_weth_directly_and_fails_elsewhere_without_an_oracle() {
+        let config = config(&[("MIN_PROFIT_WEI", "0")]);
+        let weth = wrapped_native_token(Chain::Ethereum);
+        let usdc = token(0xa0, "USDC", 6);
+        let (gas_units, gas_price) = (BigUint::from(100_000u32), 10_000_000_000u128);
+        let amount_in = BigUint::from(ONE);
+
+        let profit = check_profit(
+            &config,
+            no_oracle(),
+            &weth,
+            &amount_in,
+            &(&amount_in + BigUint::from(2 * 1_000_000_000_000_000u128)),
+            &gas_units,
+            gas_price,
+        )
+        .await
+        .unwrap();
+        // 0.002 WETH back on top, minus 0.001 WETH of gas
+        assert_eq!(profit, BigInt::from(1_000_000_000_000_000u128));
+
+        let miss = check_profit(
+            &config,
+            no_oracle(),
+            &usdc,
+            &BigUint::from(1_000_000u32),
+            &BigUint::from(2_000_000u32),
+            &gas_units,
+            gas_price,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(miss.threshold, "gas pricing");
+        assert!(miss.shortfall.contains("USDC"), "{}", miss.shortfall);
+    }
+
+    #[tokio::test]
+    async fn gas_is_priced_through_the_oracle_for_other_tokens() {
+        let config = config(&[("MIN_PROFIT_WEI", "0")]);
+        let usdc = token(0xa0, "USDC", 6);
+        let oracle = StaticPriceOracle::new(HashMap::from([
+            ("WETH".to_string(), 2_000.0),
+            ("USDC".to_string(), 1.0),
+        ]));
+
+        // 0.001 ETH of gas is $2, or 2 USDC
+        let profit = check_profit(
+            &config,
+            Some(&oracle),
+            &usdc,
+            &BigUint::from(1_000_000u32),
+            &BigUint::from(4_000_000u32),
+            &BigUint::from(100_000u32),
+            10_000_000_000,
+        )
+        .await
+        .unwrap()
+        .to_i64()
+        .unwrap();
+        // Gas is rounded up to whole units after a float conversion
+        assert!((999_999..=1_000_000).contains(&profit), "{profit}");
+    }
+
+    #[tokio::test]
+    async fn net_profit_goes_negative_once_gas_exceeds_the_spread() {
+        let weth = wrapped_native_token(Chain::Ethereum);
+        let amount_in = BigUint::from(ONE);
+        // A 0.001 WETH spread against 100k gas
+        let amount_back = &amount_in + BigUint::from(1_000_000_000_000_000u128);
+        let gas_units = BigUint::from(100_000u32);
+
+        let cheap_gas = net_profit(
+            Chain::Ethereum,
+            no_oracle(),
+            &weth,
+            &amount_in,
+            &amount_back,
+            &gas_units,
+            1_000_000_000,
+        )
+        .await
+        .unwrap();
+        assert_eq!(cheap_gas, BigInt::from(900_000_000_000_000u128));
+
+        let dear_gas = net_profit(
+            Chain::Ethereum,
+            no_oracle(),
+            &weth,
+            &amount_in,
+            &amount_back,
+            &gas_units,
+            20_000_000_000,
+        )
+        .await
+        .unwrap();
+        assert_eq!(dear_gas, BigInt::from(-1_000_000_000_000_000i128));
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use alloy::network::EthereumWallet;
+use alloy::providers::{DynProvider, Provider, ProviderBuilder, WsConnect};
+use alloy::rpc::client::RpcClient;
+use alloy::rpc::json_rpc::{RequestPacket, ResponsePacket};
+use alloy::transports::http::Http;
+use alloy::transports::http::reqwest::{Client, Url};
+use alloy::transports::{TransportError, TransportFut};
+use anyhow::{Context as _, Result, bail, ensure};
+use tower::Service;
+use tracing::{info, warn};
+
+/// How long one endpoint may take to answer before the request fails over to the next.
+const RPC_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends every JSON-RPC request to the first endpoint that answers. Only transport
+/// failures move on to the next endpoint, an RPC error response such as a revert is
+/// returned as is.
+#[derive(Debug, Clone)]
+pub struct FailoverTransport {
+    endpoints: Arc<Vec<(Url, Http<Client>)>>,
+}
+
+impl FailoverTransport {
+    pub fn new(urls: &[Url]) -> Result<Self> {
+        Self::with_timeout(urls, RPC_REQUEST_TIMEOUT)
+    }
+
+    /// Gives up on an endpoint after `timeout`, so a hung node fails over like a dead one.
+    pub fn with_timeout(urls: &[Url], timeout: Duration) -> Result<Self> {
+        ensure!(
+            !urls.is_empty(),
+            "Failover transport needs at least one RPC endpoint"
+        );
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Can't build the RPC HTTP client")?;
+        let endpoints = urls
+            .iter()
+            .map(|url| (url.clone(), Http::with_client(client.clone(), url.clone())))
+            .collect();
+
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+        })
+    }
+}
+
+impl Service<RequestPacket> for FailoverTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let endpoints = self.endpoints.clone();
+        Box::pin(async move {
+            let mut last_error = None;
+            for (index, (url, transport)) in endpoints.iter().enumerate() {
+                match transport.clone().call(request.clone()).await {
+                    Ok(response) => return Ok(response),
+                    Err(e) => {
+                        if index + 1 < endpoints.len() {
+                            warn!(rpc = %url, error = %e, "RPC transport error, failing over to the next endpoint");
+                        }
+                        last_error = Some(e);
+                    }
+                }
+            }
+
+            Err(last_error.expect("transport has at least one endpoint"))
+        })
+    }
+}
+
+/// Wallet-enabled provider over every URL in `urls`, in failover order, which the
+/// executor and gas estimation run on.
+pub fn connect_failover(urls: &[Url], wallet: EthereumWallet) -> Result<impl Provider + Clone> {
+    let transport = FailoverTransport::new(urls)?;
+    let client = RpcClient::new(transport, false);
+
+    Ok(ProviderBuilder::new().wallet(wallet).connect_client(client))
+}
+
+/// Wallet-enabled provider for the configured endpoints. A single `ws://` or `wss://`
+/// endpoint gets a WebSocket connection, which also allows block and pending transaction
+/// subscriptions, anything else goes through `connect_failover`.
+pub async fn build_provider(urls: &[Url], wallet: EthereumWallet) -> Result<DynProvider> {
+    let ws_urls: Vec<&Url> = urls.iter().filter(|url| is_ws(url)).collect();
+    match (ws_urls.as_slice(), urls.len()) {
+        ([], _) => Ok(connect_failover(urls, wallet)?.erased()),
+        ([url], 1) => {
+            info!(rpc = %url, "🔌 Connecting to RPC over WebSocket");
+            let provider = ProviderBuilder::new()
+                .wallet(wallet)
+                .connect_ws(WsConnect::new(url.as_str()))
+                .await
+                .with_context(|| format!("Can't open WebSocket connection to {url}"))?;
+            Ok(provider.erased())
+        }
+        _ => bail!("WebSocket RPC endpoints can't be combined with failover, configure a single ws:// or wss:// URL"),
+    }
+}
+
+fn is_ws(url: &Url) -> bool {
+    matches!(url.scheme(), "ws" | "wss")
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::U64;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    async fn endpoint(listener: &TcpListener) -> Url {
+        format!("http://{}", listener.local_addr().unwrap()).parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_hung_endpoint_times_out_and_fails_over() {
+        let hung = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let healthy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let urls = [endpoint(&hung).await, endpoint(&healthy).await];
+        // Accepts connections and never answers
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = hung.accept().await {
+                held.push(socket);
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = healthy.accept().await {
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                let body = r#"{"jsonrpc":"2.0","id":0,"result":"0x1"}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        let transport = FailoverTransport::with_timeout(&urls, Duration::from_millis(200)).unwrap();
+        let client = RpcClient::new(transport, false);
+
+        let chain_id = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.request_noparams::<U64>("eth_chainId"),
+        )
+        .await
+        .expect("the hung endpoint was never given up on")
+        .unwrap();
+
+        assert_eq!(chain_id, U64::from(1));
+    }
+}
@@ -0,0 +1,330 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use alloy::primitives::TxHash;
+use alloy::providers::{DynProvider, Provider};
+use alloy::rpc::types::TransactionRequest;
+use anyhow::{Context, Result, ensure};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use crate::config::ConfigSource;
+use crate::executor::join_until;
+
+const DEFAULT_CONFIRMATIONS: u64 = 1;
+const DEFAULT_STUCK_AFTER_BLOCKS: u64 = 2;
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchConfig {
+    /// Blocks, including the one it was mined in, before a transaction counts as landed.
+    pub confirmations: u64,
+    /// Blocks without a receipt after which a transaction is reported stuck.
+    pub stuck_after_blocks: u64,
+}
+
+impl WatchConfig {
+    pub fn from_source(source: &ConfigSource) -> Result<Self> {
+        let confirmations = match source.get("CONFIRMATIONS") {
+            Some(raw) => raw.trim().parse().context("Can't parse CONFIRMATIONS")?,
+            None => DEFAULT_CONFIRMATIONS,
+        };
+        let stuck_after_blocks = match source.get("STUCK_AFTER_BLOCKS") {
+            Some(raw) => raw.trim().parse().context("Can't parse STUCK_AFTER_BLOCKS")?,
+            None => DEFAULT_STUCK_AFTER_BLOCKS,
+        };
+        ensure!(confirmations >= 1, "CONFIRMATIONS must be at least 1");
+        ensure!(stuck_after_blocks >= 1, "STUCK_AFTER_BLOCKS must be at least 1");
+
+        Ok(Self {
+            confirmations,
+            stuck_after_blocks,
+        })
+    }
+}
+
+/// A submission the watcher follows.
+#[derive(Debug, Clone)]
+pub struct WatchedTx {
+    /// Pending tracker key, a component id or route label.
+    pub key: String,
+    pub tx_hash: TxHash,
+    /// Hashes sent earlier at the same nonce. Any of them can still be mined instead.
+    pub replaced: Vec<TxHash>,
+    pub nonce: u64,
+    /// The request as sent, with its nonce and fees, for a replacement to start from.
+    pub request: TransactionRequest,
+    /// Block of the update the trade was quoted on.
+    pub origin_block: u64,
+    /// Replacements sent so far at this nonce.
+    pub replacements: u32,
+}
+
+#[derive(Debug)]
+pub enum TxOutcome {
+    Mined {
+        tx: WatchedTx,
+        success: bool,
+        block: u64,
+        gas_used: u64,
+        effective_gas_price: u128,
+    },
+    /// No receipt after `stuck_after_blocks`, since `submitted_block`. `head` is the block
+    /// it was reported at.
+    Stuck {
+        tx: WatchedTx,
+        submitted_block: u64,
+        head: u64,
+    },
+}
+
+impl TxOutcome {
+    pub fn tx(&self) -> &WatchedTx {
+        match self {
+            Self::Mined { tx, .. } | Self::Stuck { tx, .. } => tx,
+        }
+    }
+}
+
+/// Follows submitted transactions from background tasks, one per transaction, and queues
+/// their outcomes for `drain`. RPC errors only delay a check, the task keeps polling.
+pub struct ReceiptWatcher {
+    provider: DynProvider,
+    config: WatchConfig,
+    sender: UnboundedSender<TxOutcome>,
+    outcomes: Mutex<UnboundedReceiver<TxOutcome>>,
+    tasks: Mutex<JoinSet<()>>,
+}
+
+impl ReceiptWatcher {
+    pub fn new(provider: DynProvider, config: WatchConfig) -> Self {
+        let (sender, outcomes) = unbounded_channel();
+        Self {
+            provider,
+            config,
+            sender,
+            outcomes: Mutex::new(outcomes),
+            tasks: Mutex::new(JoinSet::new()),
+        }
+    }
+
+    pub fn watch(&self, tx: WatchedTx) {
+        let provider = self.provider.clone();
+        let config = self.config;
+        let sender = self.sender.clone();
+        let mut tasks = self.tasks.lock().expect("receipt watcher lock poisoned");
+        // Finished watchers are only reaped here, their outcome already went to `drain`
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(async move {
+            let outcome = wait_for_receipt(&provider, config, tx).await;
+            // The receiver only goes away on shutdown
+            let _ = sender.send(outcome);
+        });
+    }
+
+    /// Lets the watchers still polling run until `deadline`, on shutdown. Their outcomes
+    /// are then ready for `drain`.
+    pub async fn wait(&self, deadline: tokio::time::Instant) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().expect("receipt watcher lock poisoned"));
+        join_until(tasks, deadline, "Receipt watcher").await;
+    }
+
+    /// Outcomes reported since the last call, without waiting.
+    pub fn drain(&self) -> Vec<TxOutcome> {
+        let mut outcomes = self.outcomes.lock().expect("receipt watcher lock poisoned");
+        std::iter::from_fn(|| outcomes.try_recv().ok()).collect()
+    }
+}
+
+/// Polls every hash sent at the nonce, the latest first. `tx_hash` of a `Mined` outcome is
+/// the one that landed.
+async fn wait_for_receipt(provider: &DynProvider, config: WatchConfig, mut tx: WatchedTx) -> TxOutcome {
+    let mut submitted_block = None;
+    'poll: loop {
+        tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        let head = match provider.get_block_number().await {
+            Ok(head) => head,
+            Err(e) => {
+                debug!(tx_hash = %tx.tx_hash, error = %e, "Can't fetch the block number, retrying");
+                continue;
+            }
+        };
+        let submitted = *submitted_block.get_or_insert(head);
+
+        let hashes: Vec<TxHash> =
+            std::iter::once(tx.tx_hash).chain(tx.replaced.iter().copied()).collect();
+        for tx_hash in hashes {
+            match provider.get_transaction_receipt(tx_hash).await {
+                Ok(Some(receipt)) => {
+                    let block = receipt.block_number.unwrap_or(head);
+                    if head + 1 < block + config.confirmations {
+                        continue 'poll;
+                    }
+                    if tx_hash != tx.tx_hash {
+                        info!(
+                            key = %tx.key,
+                            %tx_hash,
+                            replacement = %tx.tx_hash,
+                            "An earlier transaction at the nonce was mined"
+                        );
+                        tx.replaced.retain(|replaced| *replaced != tx_hash);
+                        tx.replaced.push(tx.tx_hash);
+                        tx.tx_hash = tx_hash;
+                    }
+                    return TxOutcome::Mined {
+                        success: receipt.status(),
+                        block,
+                        gas_used: receipt.gas_used,
+                        effective_gas_price: receipt.effective_gas_price,
+                        tx,
+                    };
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    debug!(%tx_hash, error = %e, "Can't fetch the receipt, retrying");
+                    continue 'poll;
+                }
+            }
+        }
+        if head >= submitted + config.stuck_after_blocks {
+            return TxOutcome::Stuck {
+                tx,
+                submitted_block: submitted,
+                head,
+            };
+        }
+    }
+}
+
+/// Running totals over landed transactions.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    pub succeeded: u64,
+    pub reverted: u64,
+    /// `gas_used * effective_gas_price` over every mined transaction, in wei.
+    pub gas_spent_wei: u128,
+}
+
+impl Ledger {
+    pub fn record(&mut self, outcome: &TxOutcome) {
+        match outcome {
+            TxOutcome::Mined {
+                tx,
+                success,
+                block,
+                gas_used,
+                effective_gas_price,
+            } => {
+                let fee = u128::from(*gas_used) * effective_gas_price;
+                self.gas_spent_wei += fee;
+                if *success {
+                    self.succeeded += 1;
+                    info!(
+                        key = %tx.key,
+                        tx_hash = %tx.tx_hash,
+                        block,
+                        gas_used,
+                        effective_gas_price,
+                        fee_wei = fee,
+                        "⛏️ Transaction confirmed"
+                    );
+                } else {
+                    self.reverted += 1;
+                    warn!(
+                        key = %tx.key,
+                        tx_hash = %tx.tx_hash,
+                        block,
+                        gas_used,
+                        effective_gas_price,
+                        fee_wei = fee,
+                        "💥 Transaction reverted on chain"
+                    );
+                }
+                debug!(
+                    succeeded = self.succeeded,
+                    reverted = self.reverted,
+                    gas_spent_wei = self.gas_spent_wei,
+                    "Ledger updated"
+                );
+            }
+            TxOutcome::Stuck {
+                tx,
+                submitted_block,
+                ..
+            } => warn!(
+                key = %tx.key,
+                tx_hash = %tx.tx_hash,
+                nonce = tx.nonce,
+                submitted_block,
+                "🐌 Transaction stuck without a receipt"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::U64;
+    use alloy::providers::ProviderBuilder;
+    use alloy::rpc::types::TransactionReceipt;
+    use alloy::transports::mock::Asserter;
+
+    use super::*;
+    use crate::testing::receipt;
+
+    const ORIGINAL: TxHash = TxHash::repeat_byte(0x01);
+    const BUMPED: TxHash = TxHash::repeat_byte(0x02);
+
+    fn watched() -> WatchedTx {
+        WatchedTx {
+            key: "pool".to_string(),
+            tx_hash: BUMPED,
+            replaced: vec![ORIGINAL],
+            nonce: 4,
+            request: TransactionRequest::default(),
+            origin_block: 100,
+            replacements: 1,
+        }
+    }
+
+    const CONFIG: WatchConfig = WatchConfig {
+        confirmations: 1,
+        stuck_after_blocks: 2,
+    };
+
+    #[tokio::test]
+    async fn a_replaced_transaction_mined_after_all_is_reported() {
+        let asserter = Asserter::new();
+        asserter.push_success(&U64::from(101));
+        asserter.push_success(&None::<TransactionReceipt>);
+        asserter.push_success(&receipt(ORIGINAL, 101));
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter).erased();
+
+        let outcome = wait_for_receipt(&provider, CONFIG, watched()).await;
+
+        let TxOutcome::Mined { tx, block, success, .. } = outcome else {
+            panic!("expected the original to be reported mined, got {outcome:?}");
+        };
+        assert_eq!((tx.tx_hash, block, success), (ORIGINAL, 101, true));
+        assert_eq!(tx.replaced, vec![BUMPED]);
+    }
+
+    #[tokio::test]
+    async fn a_transaction_without_receipt_is_stuck_after_the_configured_blocks() {
+        let asserter = Asserter::new();
+        for head in [100u64, 102] {
+            asserter.push_success(&U64::from(head));
+            asserter.push_success(&None::<TransactionReceipt>);
+            asserter.push_success(&None::<TransactionReceipt>);
+        }
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter).erased();
+
+        let outcome = wait_for_receipt(&provider, CONFIG, watched()).await;
+
+        assert!(
+            matches!(outcome, TxOutcome::Stuck { submitted_block: 100, head: 102, .. }),
+            "{outcome:?}"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use alloy::primitives::{Address, U256};
+use alloy::rpc::types::TransactionRequest;
+use anyhow::{Context, Result, ensure};
+
+use crate::config::ConfigSource;
+
+/// Nodes reject a same-nonce replacement unless both fees rise by at least 10%, 12.5%
+/// keeps clear of rounding on either side.
+const MIN_FEE_BUMP_PERCENT: f64 = 12.5;
+const DEFAULT_OPPORTUNITY_TTL_BLOCKS: u64 = 3;
+/// Gas of a plain ETH transfer, all a cancellation needs.
+const CANCEL_GAS_LIMIT: u64 = 21_000;
+
+/// What to do with a transaction reported stuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// Same calldata and nonce with bumped fees, the opportunity may still be there.
+    SpeedUp,
+    /// A 0 ETH self-transfer at the same nonce, the opportunity is gone.
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplacementPolicy {
+    /// Percent both EIP-1559 fees are raised by on each replacement.
+    pub fee_bump_percent: f64,
+    /// Blocks after the update a trade was quoted on during which it is still sped up
+    /// rather than cancelled.
+    pub opportunity_ttl_blocks: u64,
+}
+
+impl ReplacementPolicy {
+    pub fn from_source(source: &ConfigSource) -> Result<Self> {
+        let fee_bump_percent = match source.get("REPLACEMENT_FEE_BUMP_PERCENT") {
+            Some(raw) => raw
+                .trim()
+                .parse()
+                .context("Can't parse REPLACEMENT_FEE_BUMP_PERCENT as a number")?,
+            None => MIN_FEE_BUMP_PERCENT,
+        };
+        let opportunity_ttl_blocks = match source.get("OPPORTUNITY_TTL_BLOCKS") {
+            Some(raw) => raw
+                .trim()
+                .parse()
+                .context("Can't parse OPPORTUNITY_TTL_BLOCKS")?,
+            None => DEFAULT_OPPORTUNITY_TTL_BLOCKS,
+        };
+        ensure!(
+            fee_bump_percent >= MIN_FEE_BUMP_PERCENT,
+            "REPLACEMENT_FEE_BUMP_PERCENT must be at least {MIN_FEE_BUMP_PERCENT}, got {fee_bump_percent}"
+        );
+
+        Ok(Self {
+            fee_bump_percent,
+            opportunity_ttl_blocks,
+        })
+    }
+
+    pub fn decide(&self, origin_block: u64, head: u64) -> Replacement {
+        if head.saturating_sub(origin_block) > self.opportunity_ttl_blocks {
+            Replacement::Cancel
+        } else {
+            Replacement::SpeedUp
+        }
+    }
+
+    /// The replacement for `stuck`, which must carry its nonce and fees, sent from `from`.
+    pub fn replace(
+        &self,
+        replacement: Replacement,
+        stuck: &TransactionRequest,
+        from: Address,
+    ) -> TransactionRequest {
+        let tx = match replacement {
+            Replacement::SpeedUp => stuck.clone(),
+            Replacement::Cancel => TransactionRequest::default()
+                .from(from)
+                .to(from)
+                .value(U256::ZERO)
+                .gas_limit(CANCEL_GAS_LIMIT)
+                .nonce(stuck.nonce.unwrap_or_default()),
+        };
+        tx.max_fee_per_gas(self.bump(stuck.max_fee_per_gas.unwrap_or_default()))
+            .max_priority_fee_per_gas(self.bump(stuck.max_priority_fee_per_gas.unwrap_or_default()))
+    }
+
+    /// `fee` raised by `fee_bump_percent`, rounding up and by at least 1 wei.
+    pub fn bump(&self, fee: u128) -> u128 {
+        let bumped = (fee as f64 * (1.0 + self.fee_bump_percent / 100.0)).ceil() as u128;
+        bumped.max(fee + 1)
+    }
+}
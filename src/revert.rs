@@ -0,0 +1,49 @@
+use alloy::primitives::Bytes;
+use alloy::sol_types::{Panic, Revert, SolError};
+use alloy::transports::TransportError;
+
+use crate::encoding::function_selector;
+use crate::error::RevertReason;
+
+/// Custom errors of the Tycho router and Permit2 that show up in failed swaps.
+const KNOWN_ERRORS: &[&str] = &[
+    "TychoRouter__AddressZero()",
+    "TychoRouter__EmptySwaps()",
+    "TychoRouter__NegativeSlippage(uint256,uint256)",
+    "TychoRouter__MessageValueMismatch(uint256,uint256)",
+    "TychoRouter__UndefinedMinAmountOut()",
+    "InsufficientAllowance(uint256)",
+    "AllowanceExpired(uint256)",
+    "InvalidNonce()",
+    "SignatureExpired(uint256)",
+];
+
+/// Decodes `Error(string)` and `Panic(uint256)` payloads and names the custom errors in
+/// `KNOWN_ERRORS`, anything else is kept raw.
+pub fn decode_revert(data: &Bytes) -> RevertReason {
+    if data.is_empty() {
+        return RevertReason::Empty;
+    }
+    if let Ok(revert) = Revert::abi_decode(data) {
+        return RevertReason::Error(revert.reason);
+    }
+    if let Ok(panic) = Panic::abi_decode(data) {
+        return RevertReason::Panic(panic.code);
+    }
+    let known = KNOWN_ERRORS
+        .iter()
+        .find(|signature| data.starts_with(&function_selector(signature)));
+    match known {
+        Some(signature) => RevertReason::Named(signature, data.clone()),
+        None => RevertReason::Custom(data.clone()),
+    }
+}
+
+/// The revert behind a failed `eth_call` or `eth_estimateGas`, or the RPC error itself when
+/// the node returned no revert data.
+pub fn revert_reason(error: &TransportError) -> RevertReason {
+    match error.as_error_resp().and_then(|payload| payload.as_revert_data()) {
+        Some(data) => decode_revert(&data),
+        None => RevertReason::Rpc(error.to_string()),
+    }
+}
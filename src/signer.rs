@@ -0,0 +1,63 @@
+use alloy::primitives::{Address, B256, Signature};
+use alloy::signers::SignerSync;
+use alloy::signers::local::PrivateKeySigner;
+use anyhow::Result;
+
+/// What the bot needs from whoever holds the trading key. Keeping it this small lets a
+/// KMS or hardware wallet back the bot without touching the swap code.
+pub trait TradeSigner: Send + Sync {
+    fn address(&self) -> Address;
+
+    fn sign_hash(&self, hash: &B256) -> Result<Signature>;
+}
+
+impl TradeSigner for PrivateKeySigner {
+    fn address(&self) -> Address {
+        PrivateKeySigner::address(self)
+    }
+
+    fn sign_hash(&self, hash: &B256) -> Result<Signature> {
+        Ok(self.sign_hash_sync(hash)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::keccak256;
+
+    use super::*;
+    use crate::testing::MockSigner;
+
+    #[test]
+    fn private_key_signatures_recover_to_its_address() {
+        let signer = PrivateKeySigner::random();
+        let hash = keccak256(b"permit");
+
+        let signature = TradeSigner::sign_hash(&signer, &hash).unwrap();
+
+        assert_eq!(
+            signature.recover_address_from_prehash(&hash).unwrap(),
+            TradeSigner::address(&signer)
+        );
+    }
+
+    #[test]
+    fn mock_signer_is_deterministic_and_records_hashes() {
+        let signer = MockSigner::new();
+        let hash = keccak256(b"permit");
+
+        let first = signer.sign_hash(&hash).unwrap();
+        let second = MockSigner::new().sign_hash(&hash).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first.recover_address_from_prehash(&hash).unwrap(), signer.address());
+        assert_eq!(signer.signed(), vec![hash]);
+    }
+
+    #[test]
+    fn failing_mock_signer_errors_through_the_trait_object() {
+        let signer: &dyn TradeSigner = &MockSigner::failing();
+
+        assert!(signer.sign_hash(&B256::ZERO).is_err());
+    }
+}
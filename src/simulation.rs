@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use alloy::eips::BlockId;
+use alloy::primitives::{Address, B256, Bytes, U256, keccak256};
+use alloy::providers::Provider;
+use alloy::rpc::types::TransactionRequest;
+use alloy::rpc::types::state::{AccountOverride, StateOverride};
+use alloy::sol_types::{SolCall, SolValue};
+use anyhow::{Context, Result, ensure};
+use tracing::{debug, warn};
+use tycho_simulation::tycho_common::models::token::Token;
+
+use crate::config::ConfigSource;
+use crate::consts::NATIVE_ETH_ADDRESS;
+use crate::encoding::{IERC20, RouterFunction, decode_multitrade_calldata};
+use crate::error::RevertReason;
+use crate::revert::revert_reason;
+
+/// Mapping slots of OpenZeppelin's ERC20: `_balances` at 0 and `_allowances` at 1.
+const OZ_TOKEN_SLOTS: TokenSlots = TokenSlots {
+    balance: 0,
+    allowance: 1,
+};
+
+/// Storage slots of a token's `balanceOf` and `allowance` mappings, written as
+/// `<balance>,<allowance>` in config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenSlots {
+    pub balance: u64,
+    pub allowance: u64,
+}
+
+impl FromStr for TokenSlots {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        let (balance, allowance) = raw
+            .split_once(',')
+            .context("Token slots must look like <balance slot>,<allowance slot>")?;
+        Ok(Self {
+            balance: balance.trim().parse().context("Can't parse balance slot")?,
+            allowance: allowance.trim().parse().context("Can't parse allowance slot")?,
+        })
+    }
+}
+
+impl TokenSlots {
+    fn balance_slot(self, owner: Address) -> B256 {
+        keccak256((owner, U256::from(self.balance)).abi_encode())
+    }
+
+    fn allowance_slot(self, owner: Address, spender: Address) -> B256 {
+        let owner_allowances = keccak256((owner, U256::from(self.allowance)).abi_encode());
+        keccak256((spender, owner_allowances).abi_encode())
+    }
+
+    /// Storage writes giving `owner` a balance of `amount` and `spender` an unlimited
+    /// allowance over it.
+    fn state_diff(self, owner: Address, spender: Address, amount: U256) -> Vec<(B256, B256)> {
+        vec![
+            (self.balance_slot(owner), B256::from(amount)),
+            (self.allowance_slot(owner, spender), B256::from(U256::MAX)),
+        ]
+    }
+}
+
+/// Token storage layouts for state-overridden simulations, `TOKEN_SLOTS` (the OpenZeppelin
+/// layout when unset) with per-symbol overrides like `TOKEN_SLOTS_USDC=9,10`.
+#[derive(Debug, Clone)]
+pub struct SlotConfig {
+    pub default: TokenSlots,
+    pub overrides: HashMap<String, TokenSlots>,
+}
+
+impl SlotConfig {
+    pub fn from_source(source: &ConfigSource) -> Result<Self> {
+        let default = source
+            .get("TOKEN_SLOTS")
+            .map(|raw| raw.parse().context("Can't parse TOKEN_SLOTS"))
+            .transpose()?
+            .unwrap_or(OZ_TOKEN_SLOTS);
+
+        let mut overrides = HashMap::new();
+        for key in source.keys_with_prefix("TOKEN_SLOTS_") {
+            let Some(raw) = source.get(&key) else {
+                continue;
+            };
+            let slots = raw.parse().with_context(|| format!("Can't parse {key}"))?;
+            overrides.insert(key["TOKEN_SLOTS_".len()..].to_uppercase(), slots);
+        }
+
+        Ok(Self { default, overrides })
+    }
+
+    pub fn for_token(&self, token: &Token) -> TokenSlots {
+        self.overrides
+            .get(&token.symbol.to_uppercase())
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Result of `simulate_with_overrides`.
+#[derive(Debug)]
+pub struct SwapSimulation {
+    /// Router output from the simulated swap, `None` when it couldn't be read back.
+    pub amount_out: Option<U256>,
+    pub min_amount_out: Option<U256>,
+}
+
+/// `eth_call`s `tx` against the latest block and returns its output, or the decoded
+/// reason when it reverts. Catches failing swaps before gas estimation.
+pub async fn simulate_call(
+    provider: &impl Provider,
+    tx: TransactionRequest,
+) -> Result<Bytes, RevertReason> {
+    match provider.call(tx).block(BlockId::latest()).await {
+        Ok(output) => Ok(output),
+        Err(e) => {
+            let reason = revert_reason(&e);
+            warn!(%reason, "🧪 Simulated call failed");
+            Err(reason)
+        }
+    }
+}
+
+/// Simulates an `executeInteractions` transaction as if the wallet held `amount_in` of the
+/// sell token and had approved the executor contract, so a cold wallet can still be
+/// validated. The override is only used once `balanceOf` and `allowance` read back the
+/// faked values, tokens with another storage layout get the plain call instead.
+///
+/// The router call is then replayed from the executor contract to read the amount out the
+/// router returns, for comparison against the `minAmountOut` it was given.
+pub async fn simulate_with_overrides(
+    provider: &impl Provider,
+    tx: TransactionRequest,
+    sell_token: &Token,
+    amount_in: U256,
+    slots: &SlotConfig,
+) -> Result<SwapSimulation, RevertReason> {
+    let calldata = tx.input.input().cloned().unwrap_or_default();
+    let batch = decode_multitrade_calldata(&calldata)
+        .map_err(|e| RevertReason::Rpc(format!("can't decode the batch: {e}")))?;
+    let (Some(wallet), Some(executor)) = (tx.from, tx.to.and_then(|to| to.to().copied())) else {
+        return Err(RevertReason::Rpc("transaction has no from or to address".to_string()));
+    };
+    let token = batch.token_address;
+    let native = token == NATIVE_ETH_ADDRESS;
+    let slots = slots.for_token(sell_token);
+
+    // Native input has no token storage to fake, only the ETH sent along
+    let wallet_state = if native {
+        Some(balance_override(wallet, tx.value.unwrap_or_default()))
+    } else {
+        let state = token_override(token, slots.state_diff(wallet, executor, amount_in));
+        match check_override(provider, token, wallet, executor, amount_in, &state).await {
+            Ok(()) => Some(state),
+            Err(e) => {
+                warn!(
+                    token = %sell_token.symbol,
+                    error = %e,
+                    "🧪 Token storage layout not recognised, simulating without state overrides"
+                );
+                None
+            }
+        }
+    };
+    call_with(provider, tx, wallet_state).await?;
+
+    let Some(router_call) = batch
+        .interactions
+        .iter()
+        .find(|interaction| RouterFunction::from_calldata(&interaction.callData).is_ok())
+    else {
+        return Ok(SwapSimulation {
+            amount_out: None,
+            min_amount_out: None,
+        });
+    };
+    let router = router_call.target;
+    let min_amount_out = router_call
+        .callData
+        .get(4 + 3 * 32..4 + 4 * 32)
+        .map(U256::from_be_slice);
+
+    // The executor contract holds the sell token when the router runs, whether the router
+    // pulls it or expects it already transferred
+    let router_state = if native {
+        Some(balance_override(executor, router_call.value))
+    } else {
+        let mut diff = slots.state_diff(executor, router, amount_in);
+        diff.push((slots.balance_slot(router), B256::from(amount_in)));
+        Some(token_override(token, diff))
+    };
+    let router_tx = TransactionRequest::default()
+        .from(executor)
+        .to(router)
+        .value(router_call.value)
+        .input(router_call.callData.clone().into());
+    let output = call_with(provider, router_tx, router_state).await?;
+    let amount_out = U256::abi_decode(&output).ok();
+    debug!(?amount_out, ?min_amount_out, "🧪 Simulated router output");
+
+    Ok(SwapSimulation {
+        amount_out,
+        min_amount_out,
+    })
+}
+
+async fn call_with(
+    provider: &impl Provider,
+    tx: TransactionRequest,
+    state: Option<StateOverride>,
+) -> Result<Bytes, RevertReason> {
+    let call = provider.call(tx).block(BlockId::latest());
+    let result = match state {
+        Some(state) => call.overrides(state).await,
+        None => call.await,
+    };
+    result.map_err(|e| {
+        let reason = revert_reason(&e);
+        warn!(%reason, "🧪 Simulated call failed");
+        reason
+    })
+}
+
+/// Reads `balanceOf(owner)` and `allowance(owner, spender)` under `state` and errors unless
+/// both show the faked values.
+async fn check_override(
+    provider: &impl Provider,
+    token: Address,
+    owner: Address,
+    spender: Address,
+    amount: U256,
+    state: &StateOverride,
+) -> Result<()> {
+    let balance = read_u256(provider, token, IERC20::balanceOfCall { owner }.abi_encode(), state).await?;
+    let allowance = read_u256(
+        provider,
+        token,
+        IERC20::allowanceCall { owner, spender }.abi_encode(),
+        state,
+    )
+    .await?;
+    ensure!(balance == amount, "balanceOf returned {balance} instead of {amount}");
+    ensure!(allowance == U256::MAX, "allowance returned {allowance} instead of the maximum");
+    Ok(())
+}
+
+async fn read_u256(
+    provider: &impl Provider,
+    token: Address,
+    calldata: Vec<u8>,
+    state: &StateOverride,
+) -> Result<U256> {
+    let tx = TransactionRequest::default().to(token).input(calldata.into());
+    let output = provider
+        .call(tx)
+        .block(BlockId::latest())
+        .overrides(state.clone())
+        .await?;
+    Ok(U256::abi_decode(&output)?)
+}
+
+fn token_override(token: Address, diff: Vec<(B256, B256)>) -> StateOverride {
+    let mut state = StateOverride::default();
+    state.insert(
+        token,
+        AccountOverride {
+            state_diff: Some(diff.into_iter().collect()),
+            ..Default::default()
+        },
+    );
+    state
+}
+
+fn balance_override(account: Address, balance: U256) -> StateOverride {
+    let mut state = StateOverride::default();
+    state.insert(
+        account,
+        AccountOverride {
+            balance: Some(balance),
+            ..Default::default()
+        },
+    );
+    state
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeSet;
+
+use tracing::trace;
+use tycho_simulation::protocol::models::{ProtocolComponent, Update};
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::token::Token;
+
+use crate::pool_registry::{PairKey, PoolRegistry, component_pairs};
+
+/// Two pools quoting the same pair far enough apart to buy on one and sell on the other.
+#[derive(Debug, Clone, Copy)]
+pub struct SpreadOpportunity<'a> {
+    /// Token bought on `cheap` and sold on `rich`.
+    pub base: &'a Token,
+    /// Token the loop starts and ends with.
+    pub quote: &'a Token,
+    /// Pool with the lowest price of `base` in `quote`.
+    pub cheap: &'a ProtocolComponent,
+    pub cheap_id: &'a str,
+    /// Pool with the highest price of `base` in `quote`.
+    pub rich: &'a ProtocolComponent,
+    pub rich_id: &'a str,
+    pub spread_bps: f64,
+}
+
+impl<'a> SpreadOpportunity<'a> {
+    /// The same opportunity looping through `base` instead: a pool where `base` is cheap
+    /// in `quote` is one where `quote` is expensive in `base`.
+    pub fn flipped(self) -> Self {
+        Self {
+            base: self.quote,
+            quote: self.base,
+            cheap: self.rich,
+            cheap_id: self.rich_id,
+            rich: self.cheap,
+            rich_id: self.cheap_id,
+            spread_bps: self.spread_bps,
+        }
+    }
+}
+
+/// Recomputes spot prices of every tracked pool sharing a pair with a pool in `update`,
+/// and returns the pairs whose best and worst price are more than `min_spread_bps` apart.
+pub fn find_spreads<'a>(
+    registry: &'a PoolRegistry,
+    update: &Update,
+    min_spread_bps: u32,
+) -> Vec<SpreadOpportunity<'a>> {
+    let affected: BTreeSet<PairKey> = update
+        .states
+        .keys()
+        .filter_map(|id| registry.get(id))
+        .flat_map(component_pairs)
+        .collect();
+
+    affected
+        .iter()
+        .filter_map(|key| best_spread(registry, key))
+        .filter(|opportunity| opportunity.spread_bps > f64::from(min_spread_bps))
+        .collect()
+}
+
+fn best_spread<'a>(registry: &'a PoolRegistry, key: &PairKey) -> Option<SpreadOpportunity<'a>> {
+    let mut prices: Vec<(&'a str, &'a ProtocolComponent, &'a Token, &'a Token, f64)> = Vec::new();
+    for id in registry.pools_for_pair(key) {
+        let (Some(component), Some(state)) = (registry.get(id), registry.state(id)) else {
+            continue;
+        };
+        let find = |address: &Bytes| {
+            component
+                .tokens
+                .iter()
+                .find(|token| &token.address == address)
+        };
+        let (Some(base), Some(quote)) = (find(&key.0), find(&key.1)) else {
+            continue;
+        };
+
+        match state.spot_price(base, quote) {
+            Ok(price) if price.is_finite() && price > 0.0 => {
+                prices.push((id.as_str(), component, base, quote, price))
+            }
+            Ok(price) => trace!(component = %id, price, "Ignoring unusable spot price"),
+            Err(e) => trace!(component = %id, error = %e, "Spot price unavailable"),
+        }
+    }
+
+    let by_price = |a: &&(_, _, _, _, f64), b: &&(_, _, _, _, f64)| a.4.total_cmp(&b.4);
+    let cheap = prices.iter().min_by(by_price)?;
+    let rich = prices.iter().max_by(by_price)?;
+    if cheap.0 == rich.0 {
+        return None;
+    }
+
+    Some(SpreadOpportunity {
+        base: cheap.2,
+        quote: cheap.3,
+        cheap: cheap.1,
+        cheap_id: cheap.0,
+        rich: rich.1,
+        rich_id: rich.0,
+        spread_bps: (rich.4 / cheap.4 - 1.0) * 10_000.0,
+    })
+}
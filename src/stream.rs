@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, bail, ensure};
+use futures::{Stream, StreamExt};
+use tokio::signal;
+use tracing::{error, info, trace, warn};
+use tycho_simulation::evm::decoder::StreamDecodeError;
+use tycho_simulation::evm::stream::ProtocolStreamBuilder;
+use tycho_simulation::protocol::models::Update;
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::token::Token;
+use tycho_simulation::utils::load_all_tokens;
+
+use crate::config::AppConfig;
+use crate::error::StateErrors::Disconnect;
+use crate::exchanges::register_exchanges;
+use crate::health;
+
+/// Consecutive stream errors after which the connection is treated as dead and rebuilt.
+const MAX_CONSECUTIVE_ERRORS: u32 = 5;
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// A connection that stays up this long resets the backoff.
+const HEALTHY_PERIOD: Duration = Duration::from_secs(120);
+
+pub async fn load_tokens(config: &AppConfig) -> Result<HashMap<Bytes, Token>> {
+    info!(chain = ?config.chain, tycho_url = %config.tycho_url, "📡 Loading all tokens from Tycho API");
+    let all_tokens = load_all_tokens(
+        &config.tycho_url,
+        false,
+        Some(&config.tycho_api_key),
+        false,
+        config.chain,
+        None,
+        None,
+    )
+    .await
+    .map_err(Disconnect);
+
+    match all_tokens {
+        Ok(tokens) => {
+            ensure!(
+                tokens.len() >= config.min_tokens,
+                "Tycho returned {} tokens for {:?}, expected at least MIN_TOKENS={}. Check CHAIN and TYCHO_URL",
+                tokens.len(),
+                config.chain,
+                config.min_tokens
+            );
+            info!(token_count = tokens.len(), "✅ Successfully loaded tokens");
+            trace!(
+                "Token addresses: {:?}",
+                tokens.keys().take(5).collect::<Vec<_>>()
+            );
+            Ok(tokens)
+        }
+        Err(e) => {
+            error!(error = %e, "❌ Failed to load tokens from Tycho");
+            bail!("Details: {}", e);
+        }
+    }
+}
+
+pub async fn build_protocol_stream(
+    config: &AppConfig,
+    tokens: HashMap<Bytes, Token>,
+) -> Result<impl Stream<Item = Result<Update, StreamDecodeError>>> {
+    info!("🔧 Building protocol stream with exchanges");
+    let protocol_stream = register_exchanges(
+        ProtocolStreamBuilder::new(&config.tycho_url, config.chain),
+        &config.exchanges,
+        &config.v4_hook_addresses,
+    )?
+    .auth_key(Some(config.tycho_api_key.clone()))
+    .disable_compression()
+    .skip_state_decode_failures(true)
+    .set_tokens(tokens)
+    .await
+    .build()
+    .await;
+
+    match protocol_stream {
+        Ok(stream) => Ok(stream),
+        Err(e) => bail!("Failed to build ProtocolStreamBuilder: {:?}", e),
+    }
+}
+
+/// Consumes the protocol stream, rebuilding it with exponential backoff whenever it ends
+/// or keeps failing, so a dropped websocket doesn't take the whole process down.
+/// Tokens from the initial load are reused until they are older than `tokens_ttl`.
+/// Returns `Ok(())` on SIGINT/SIGTERM once the update being handled has finished, or after
+/// `shutdown_timeout` if it hasn't. A second signal exits the process immediately.
+pub async fn run_stream_with_reconnect<F>(
+    config: &AppConfig,
+    mut tokens: HashMap<Bytes, Token>,
+    mut on_update: F,
+) -> Result<()>
+where
+    F: AsyncFnMut(Update),
+{
+    let mut tokens_loaded_at = Instant::now();
+    let mut attempt = 0u32;
+    let shutdown = async {
+        shutdown_signal().await;
+        exit_on_second_signal();
+    };
+    tokio::pin!(shutdown);
+
+    loop {
+        if attempt > 0 && tokens_loaded_at.elapsed() > config.tokens_ttl {
+            match load_tokens(config).await {
+                Ok(fresh) => {
+                    tokens = fresh;
+                    tokens_loaded_at = Instant::now();
+                }
+                Err(e) => warn!("Keeping cached tokens, refresh failed: {:#}", e),
+            }
+        }
+
+        let connected_at = Instant::now();
+        match build_protocol_stream(config, tokens.clone()).await {
+            Ok(stream) => {
+                info!("✅ Protocol stream built successfully, starting message loop");
+                health::stream_connected(true);
+                let mut stream = Box::pin(stream);
+                let mut consecutive_errors = 0u32;
+
+                loop {
+                    // Only waiting for the next message races the signal, an update that is
+                    // already being handled runs to completion
+                    let msg = tokio::select! {
+                        msg = stream.next() => msg,
+                        _ = &mut shutdown => return Ok(()),
+                    };
+                    let Some(msg) = msg else {
+                        break;
+                    };
+                    trace!(message = ?msg, "Full message details");
+                    health::message_received();
+
+                    match msg {
+                        Ok(update) => {
+                            consecutive_errors = 0;
+                            let handled = on_update(update);
+                            tokio::pin!(handled);
+                            tokio::select! {
+                                _ = &mut handled => {}
+                                _ = &mut shutdown => {
+                                    finish_in_flight(handled, config.shutdown_timeout).await;
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!("❌ Stream error: {:?}", e);
+                            consecutive_errors += 1;
+                            if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                                warn!(consecutive_errors, "Too many stream errors, reconnecting");
+                                break;
+                            }
+                        }
+                    }
+                }
+                health::stream_connected(false);
+                warn!(uptime_secs = connected_at.elapsed().as_secs(), "Protocol stream ended");
+            }
+            Err(e) => {
+                error!("❌ {:#}", e);
+            }
+        }
+
+        if connected_at.elapsed() >= HEALTHY_PERIOD {
+            attempt = 0;
+        }
+        attempt += 1;
+        if let Some(max_retries) = config.stream_max_retries
+            && attempt > max_retries
+        {
+            bail!("Protocol stream failed after {} reconnect attempts", max_retries);
+        }
+
+        let delay = backoff_delay(config.stream_retry_base_delay, attempt);
+        warn!(attempt, delay_ms = delay.as_millis() as u64, "🔄 Reconnecting protocol stream");
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = &mut shutdown => return Ok(()),
+        }
+    }
+}
+
+/// Lets the update being handled when the signal came run for up to `timeout`. Returns
+/// whether it finished.
+async fn finish_in_flight(handled: impl Future<Output = ()>, timeout: Duration) -> bool {
+    info!(timeout_secs = timeout.as_secs(), "⏳ Waiting for the update in flight to finish");
+    let finished = tokio::time::timeout(timeout, handled).await.is_ok();
+    if !finished {
+        warn!("Update still running after SHUTDOWN_TIMEOUT_SECS, abandoning it");
+    }
+    finished
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            error!("❌ Can't listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!("❌ Can't listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("🛑 Received Ctrl-C, shutting down"),
+        _ = terminate => info!("🛑 Received SIGTERM, shutting down"),
+    }
+}
+
+/// Listens for another signal once shutdown started, for when draining takes too long.
+fn exit_on_second_signal() {
+    tokio::spawn(async {
+        shutdown_signal().await;
+        warn!("🛑 Second signal, exiting immediately");
+        std::process::exit(130);
+    });
+}
+
+/// `base * 2^(attempt - 1)` capped at a minute, plus up to 20% jitter so several
+/// instances don't hammer Tycho in lockstep.
+fn backoff_delay(base: Duration, attempt: u32) -> Duration {
+    let exponential = base
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RECONNECT_DELAY);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default();
+    let jitter = exponential.mul_f64(f64::from(nanos % 1_000) / 5_000.0);
+
+    exponential + jitter
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn an_update_in_flight_is_drained_or_abandoned_at_the_timeout() {
+        let done = Arc::new(AtomicBool::new(false));
+        let quick = {
+            let done = Arc::clone(&done);
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                done.store(true, Ordering::SeqCst);
+            }
+        };
+        assert!(finish_in_flight(quick, Duration::from_secs(5)).await);
+        assert!(done.load(Ordering::SeqCst));
+
+        let started = Instant::now();
+        let stuck = tokio::time::sleep(Duration::from_secs(3600));
+        assert!(!finish_in_flight(stuck, Duration::from_millis(200)).await);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,25 +1,173 @@
-use std::str::FromStr;
+use std::collections::HashMap;
 
 use alloy::hex;
-use alloy::primitives::{Address, B256, Bytes as AlloyBytes, U256};
+use alloy::primitives::{Address, Bytes as AlloyBytes, U256};
 use alloy::rpc::types::TransactionRequest;
-use alloy::signers::local::PrivateKeySigner;
-use alloy::sol_types::SolValue;
-use anyhow::Result;
 use num_bigint::BigUint;
-use tracing::info;
+use num_traits::ToPrimitive;
+use tracing::{debug, info, warn};
 
-use tycho_execution::encoding::models::{Solution, Swap};
+use tycho_execution::encoding::models::{
+    NativeAction, Solution, Swap, Transaction, UserTransferType,
+};
 use tycho_execution::encoding::tycho_encoder::TychoEncoder;
 use tycho_simulation::evm::protocol::u256_num::biguint_to_u256;
 use tycho_simulation::protocol::models::ProtocolComponent;
 use tycho_simulation::tycho_common::hex_bytes::Bytes;
 use tycho_simulation::tycho_common::models::token::Token;
+use tycho_simulation::tycho_core::simulation::errors::SimulationError;
+use tycho_simulation::tycho_core::simulation::protocol_sim::ProtocolSim;
 
-use crate::encoding::{create_multitrade_calldata, encode_input};
-use crate::consts::{OUR_CONTRACT, ARBITRAGE_WALLET_ADDRESS};
+use crate::amount_in_strategy::amount_in_for;
+use crate::amounts::format_token_amount;
+use crate::encoding::{
+    ApproveStrategy, ExecutionMode, InteractionBatch, RouterFunction, create_multitrade_calldata,
+    erc20_transfer,
+};
+use crate::config::{AppConfig, Slippage};
+use crate::consts::NATIVE_ETH_ADDRESS;
+use crate::error::EncodingError;
+use crate::metrics;
+use crate::permit2::build_permit2_calldata;
+use crate::profit::wrapped_native_address;
+use crate::signer::TradeSigner;
 
+const BPS_DENOMINATOR: u32 = 10_000;
+const SPLIT_EPSILON: f64 = 1e-6;
 
+/// Per-run settings shared by every swap, taken from `AppConfig` once at startup.
+#[derive(Debug, Clone)]
+pub struct SwapSettings {
+    pub slippage: Slippage,
+    pub execution_mode: ExecutionMode,
+    pub executor_contract: Address,
+    pub wallet_address: Option<Address>,
+    /// Recipient of the swap output, the signer when `None`.
+    pub receiver: Option<Address>,
+    pub user_transfer_type: UserTransferType,
+    pub approve_strategy: ApproveStrategy,
+    /// EIP-155 chain id, the Permit2 signing domain depends on it.
+    pub chain_id: u64,
+    /// Wrapped native token of the chain, what pools trade in place of native ETH.
+    pub wrapped_native: Address,
+}
+
+impl From<&AppConfig> for SwapSettings {
+    fn from(config: &AppConfig) -> Self {
+        Self {
+            slippage: config.slippage.clone(),
+            execution_mode: config.execution_mode,
+            executor_contract: config.executor_contract,
+            wallet_address: config.wallet_address,
+            receiver: config.receiver,
+            user_transfer_type: config.user_transfer_type.clone(),
+            approve_strategy: config.approve_strategy,
+            chain_id: config.chain.id(),
+            wrapped_native: wrapped_native_address(config.chain),
+        }
+    }
+}
+
+/// Which side of a swap is fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeMode {
+    /// Sell exactly `amount_in`, slippage lowers the accepted `amount_out`.
+    ExactIn,
+    /// Receive exactly `amount_out`, e.g. to repay a flash loan. Slippage raises the
+    /// most `amount_in` the swap may spend.
+    ExactOut,
+}
+
+/// Returns `amount * (10_000 - slippage_bps) / 10_000`, rounding down.
+/// `slippage_bps` above 10_000 is treated as 100%.
+pub fn apply_slippage(amount: &BigUint, slippage_bps: u32) -> BigUint {
+    let keep_bps = BPS_DENOMINATOR - slippage_bps.min(BPS_DENOMINATOR);
+    let min_amount = amount * BigUint::from(keep_bps) / BigUint::from(BPS_DENOMINATOR);
+
+    if min_amount == BigUint::ZERO && *amount != BigUint::ZERO {
+        warn!(
+            amount = %amount,
+            slippage_bps,
+            "Minimum amount rounded down to zero, swap is not protected by slippage"
+        );
+    }
+
+    min_amount
+}
+
+/// Returns `amount * (10_000 + slippage_bps) / 10_000`, rounding up, for capping the input
+/// of an exact-out swap.
+pub fn apply_input_slippage(amount: &BigUint, slippage_bps: u32) -> BigUint {
+    let denominator = BigUint::from(BPS_DENOMINATOR);
+    let scaled = amount * BigUint::from(BPS_DENOMINATOR + slippage_bps.min(BPS_DENOMINATOR));
+    (scaled + &denominator - 1u32) / denominator
+}
+
+pub fn is_native(token: &Token) -> bool {
+    token.address.as_ref() == NATIVE_ETH_ADDRESS.as_slice()
+}
+
+/// Zero decimals or an empty symbol usually mean the token metadata failed to load, and
+/// amounts derived from it would be off by orders of magnitude.
+pub fn has_suspicious_metadata(token: &Token) -> bool {
+    token.decimals == 0 || token.symbol.trim().is_empty()
+}
+
+/// Every `(sell, buy)` direction between a component's tokens, `n * (n - 1)` in total.
+pub fn token_pairs(tokens: &[Token]) -> impl Iterator<Item = (&Token, &Token)> {
+    tokens.iter().enumerate().flat_map(move |(i, a)| {
+        tokens
+            .iter()
+            .enumerate()
+            .filter(move |(j, _)| *j != i)
+            .map(move |(_, b)| (a, b))
+    })
+}
+
+/// Picks the direction of a pair that buys on `state` and sells back on `exit` at a positive
+/// spread, as `(sell, buy, amount_in)`. Selling `token_b` starts from what `amount` of
+/// `token_a` buys on `state`. When both sides gain, the one keeping the larger fraction of
+/// its input wins. `None` when neither side gets back more than it put in.
+pub fn best_direction(
+    state: &dyn ProtocolSim,
+    exit: &dyn ProtocolSim,
+    token_a: &Token,
+    token_b: &Token,
+    amount: BigUint,
+) -> Option<(Token, Token, BigUint)> {
+    let round_trip = |sell: &Token, buy: &Token, amount: &BigUint| -> Option<BigUint> {
+        let out = state.get_amount_out(amount.clone(), sell, buy).ok()?.amount;
+        Some(exit.get_amount_out(out, buy, sell).ok()?.amount)
+    };
+
+    let forward = round_trip(token_a, token_b, &amount)
+        .filter(|back| *back > amount)
+        .map(|back| (back, amount.clone()));
+    let backward = state
+        .get_amount_out(amount.clone(), token_a, token_b)
+        .ok()
+        .map(|result| result.amount)
+        .filter(|amount_b| *amount_b != BigUint::ZERO)
+        .and_then(|amount_b| Some((round_trip(token_b, token_a, &amount_b)?, amount_b)))
+        .filter(|(back, amount_b)| back > amount_b);
+
+    match (forward, backward) {
+        // Compare the kept fractions back_b / amount_b and back_a / amount
+        (Some((forward, amount)), Some((backward, amount_b)))
+            if &backward * &amount > &forward * &amount_b =>
+        {
+            Some((token_b.clone(), token_a.clone(), amount_b))
+        }
+        (Some((_, amount)), _) => Some((token_a.clone(), token_b.clone(), amount)),
+        (None, Some((_, amount_b))) => Some((token_b.clone(), token_a.clone(), amount_b)),
+        (None, None) => None,
+    }
+}
+
+/// Encodes a swap on one pool.
+///
+/// With `TradeMode::ExactOut` the quoted `amount_out` is required exactly and slippage
+/// widens the input instead, so `amount_in` plus slippage becomes the most the swap may spend.
 #[allow(clippy::too_many_arguments)]
 pub fn process_swap(
     component: &ProtocolComponent,
@@ -27,99 +175,1516 @@ pub fn process_swap(
     buy_token: &Token,
     amount_in: BigUint,
     amount_out: BigUint,
-    private_key: &str,
+    mode: TradeMode,
+    signer: &dyn TradeSigner,
+    settings: &SwapSettings,
     encoder: &dyn TychoEncoder
-) -> Result<TransactionRequest> {
+) -> Result<TransactionRequest, EncodingError> {
     info!(
         "Processing swap: {} -> {}",
-        sell_token.symbol, buy_token.symbol
+        format_token_amount(&amount_in, sell_token),
+        format_token_amount(&amount_out, buy_token)
     );
 
-    // let encoder = TychoRouterEncoderBuilder::new()
-    //     .user_transfer_type(tycho_execution::encoding::models::UserTransferType::TransferFrom)
-    //     .chain(Chain::Ethereum)
-    //     .build()?;
-
-    let pk = B256::from_str(private_key)?;
-    let signer = PrivateKeySigner::from_bytes(&pk)?;
-    let slippage_tolerance = 5; // 5%
-    let min_amount_out =
-        amount_out.clone() * BigUint::from((100 - slippage_tolerance) as u32) / BigUint::from(100u32);
-
-    let component_clone = component.clone().into();
-    let swap = Swap {
-        component: component_clone,
-        token_in: Bytes::from(sell_token.address.as_ref()),
-        token_out: Bytes::from(buy_token.address.as_ref()),
-        split: 0.0,
-        user_data: None,
-        protocol_state: None,
-        estimated_amount_in: Some(amount_in.clone()),
+    encode_hops(
+        vec![(component.clone(), sell_token.clone(), buy_token.clone(), 1.0)],
+        vec![amount_in.clone()],
+        amount_in,
+        amount_out,
+        mode,
+        signer,
+        settings,
+        encoder,
+    )
+}
+
+/// Encodes a swap on one pool buying exactly `amount_out` of `buy_token`, spending at
+/// most the input `state` needs for it plus slippage.
+#[allow(clippy::too_many_arguments)]
+pub fn process_exact_out_swap(
+    component: &ProtocolComponent,
+    state: &dyn ProtocolSim,
+    sell_token: &Token,
+    buy_token: &Token,
+    amount_out: BigUint,
+    signer: &dyn TradeSigner,
+    settings: &SwapSettings,
+    encoder: &dyn TychoEncoder,
+) -> Result<TransactionRequest, EncodingError> {
+    let amount_in = amount_in_for(state, sell_token, buy_token, &amount_out).ok_or_else(|| {
+        EncodingError::InvalidRoute(format!(
+            "pool {} can't deliver {}",
+            component.id,
+            format_token_amount(&amount_out, buy_token)
+        ))
+    })?;
+
+    process_swap(
+        component,
+        sell_token,
+        buy_token,
+        amount_in,
+        amount_out,
+        TradeMode::ExactOut,
+        signer,
+        settings,
+        encoder,
+    )
+}
+
+/// Builds a single `Solution` out of several hops, see `validate_hops` for the rules on
+/// splits and token order. `states` holds the protocol state of every hop, the estimated
+/// input of each hop and the output come from chaining quotes through them, see
+/// `quote_hops`.
+pub fn process_multi_hop_swap(
+    hops: Vec<(ProtocolComponent, Token, Token, f64)>,
+    states: &[&dyn ProtocolSim],
+    amount_in: BigUint,
+    signer: &dyn TradeSigner,
+    settings: &SwapSettings,
+    encoder: &dyn TychoEncoder,
+) -> Result<TransactionRequest, EncodingError> {
+    let quote = quote_hops(&hops, states, amount_in.clone())?;
+    encode_hops(
+        hops,
+        quote.hop_amounts_in,
+        amount_in,
+        quote.amount_out,
+        TradeMode::ExactIn,
+        signer,
+        settings,
+        encoder,
+    )
+}
+
+/// Quotes a route of split and sequential hops in order. Each hop sells its split of
+/// what the route input, or the hops before it, provided of its token, and the last hop
+/// selling a token takes whatever is left of it.
+pub fn quote_hops(
+    hops: &[(ProtocolComponent, Token, Token, f64)],
+    states: &[&dyn ProtocolSim],
+    amount_in: BigUint,
+) -> Result<RouteQuote, EncodingError> {
+    validate_hops(hops)?;
+    if hops.len() != states.len() {
+        return Err(EncodingError::InvalidRoute(format!(
+            "route has {} hops but {} pool states",
+            hops.len(),
+            states.len()
+        )));
+    }
+
+    let remainder_hops = last_hop_selling(hops);
+    let sell_token = &hops[0].1.address;
+    let buy_token = &hops[hops.len() - 1].2.address;
+    // Bought and sold so far of every token the route touches
+    let mut received: HashMap<&Bytes, BigUint> = HashMap::from([(sell_token, amount_in)]);
+    let mut spent: HashMap<&Bytes, BigUint> = HashMap::new();
+    let mut hop_amounts_in = Vec::with_capacity(hops.len());
+    let mut gas = BigUint::ZERO;
+
+    for (index, ((_, token_in, token_out, split), state)) in hops.iter().zip(states).enumerate() {
+        let available = received.get(&token_in.address).cloned().unwrap_or_default();
+        let spent_so_far = spent.entry(&token_in.address).or_default();
+        let share = if remainder_hops.get(&token_in.address) == Some(&index) {
+            if available > *spent_so_far {
+                &available - &*spent_so_far
+            } else {
+                BigUint::ZERO
+            }
+        } else {
+            split_share(&available, *split)
+        };
+        *spent_so_far += &share;
+
+        let result = state.get_amount_out(share.clone(), token_in, token_out)?;
+        *received.entry(&token_out.address).or_default() += result.amount;
+        gas += result.gas;
+        hop_amounts_in.push(share);
+    }
+
+    // A cyclic route gets its input back, only the part not sold again counts
+    let bought = received.remove(buy_token).unwrap_or_default();
+    let sold = spent.remove(buy_token).unwrap_or_default();
+    let amount_out = if bought > sold { bought - sold } else { BigUint::ZERO };
+
+    Ok(RouteQuote {
+        hop_amounts_in,
+        amount_out,
+        gas,
+    })
+}
+
+/// `fraction` of `amount`, rounding down, to a millionth.
+fn split_share(amount: &BigUint, fraction: f64) -> BigUint {
+    let scale = 1_000_000u64;
+    amount * BigUint::from((fraction * scale as f64) as u64) / BigUint::from(scale)
+}
+
+/// Amounts flowing through a sequential route, from `quote_route`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteQuote {
+    /// What each hop sells, the first entry being the route input.
+    pub hop_amounts_in: Vec<BigUint>,
+    pub amount_out: BigUint,
+    /// Protocol gas summed over all hops.
+    pub gas: BigUint,
+}
+
+/// Chains `get_amount_out` through `hops`, feeding each hop's output into the next.
+/// `states` holds the protocol state of every hop, in the same order.
+pub fn quote_route(
+    hops: &[(ProtocolComponent, Token, Token)],
+    states: &[&dyn ProtocolSim],
+    amount_in: BigUint,
+) -> Result<RouteQuote, SimulationError> {
+    let mut hop_amounts_in = Vec::with_capacity(hops.len());
+    let mut amount = amount_in;
+    let mut gas = BigUint::ZERO;
+    for ((_, token_in, token_out), state) in hops.iter().zip(states) {
+        let result = state.get_amount_out(amount.clone(), token_in, token_out)?;
+        hop_amounts_in.push(amount);
+        amount = result.amount;
+        gas += result.gas;
+    }
+
+    Ok(RouteQuote {
+        hop_amounts_in,
+        amount_out: amount,
+        gas,
+    })
+}
+
+/// Encodes a sequential route such as WBTC -> WETH -> USDC, each hop selling everything
+/// the previous one bought. `hop_amounts_in` are the per-hop inputs from `quote_route`.
+pub fn process_route(
+    hops: Vec<(ProtocolComponent, Token, Token)>,
+    hop_amounts_in: Vec<BigUint>,
+    amount_out: BigUint,
+    signer: &dyn TradeSigner,
+    settings: &SwapSettings,
+    encoder: &dyn TychoEncoder,
+) -> Result<TransactionRequest, EncodingError> {
+    if hops.len() != hop_amounts_in.len() {
+        return Err(EncodingError::InvalidRoute(format!(
+            "route has {} hops but {} hop amounts",
+            hops.len(),
+            hop_amounts_in.len()
+        )));
+    }
+    let amount_in = hop_amounts_in
+        .first()
+        .cloned()
+        .ok_or_else(|| EncodingError::InvalidRoute("route needs at least one hop".to_string()))?;
+
+    let hops = hops
+        .into_iter()
+        .map(|(component, token_in, token_out)| (component, token_in, token_out, 1.0))
+        .collect();
+    encode_hops(
+        hops,
+        hop_amounts_in,
+        amount_in,
+        amount_out,
+        TradeMode::ExactIn,
+        signer,
+        settings,
+        encoder,
+    )
+}
+
+/// A trade spread over several pools of the same pair, from `quote_split`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitQuote {
+    /// Share of `amount_in` sent to each pool, summing to 1.0.
+    pub fractions: Vec<f64>,
+    /// What each pool is expected to sell.
+    pub amounts_in: Vec<BigUint>,
+    pub amount_out: BigUint,
+    pub gas: BigUint,
+}
+
+/// Splits `amount_in` over `pools` in proportion to how much each can take for this
+/// direction, then quotes every share. Pools that can't report limits or quote are left
+/// out, and `None` is returned when fewer than two remain.
+pub fn quote_split(
+    pools: &[(&ProtocolComponent, &dyn ProtocolSim)],
+    sell_token: &Token,
+    buy_token: &Token,
+    amount_in: &BigUint,
+) -> Option<(Vec<ProtocolComponent>, SplitQuote)> {
+    let limits: Vec<(&ProtocolComponent, &dyn ProtocolSim, f64)> = pools
+        .iter()
+        .filter_map(|(component, state)| {
+            let (max_sell, _) = state
+                .get_limits(sell_token.address.clone(), buy_token.address.clone())
+                .ok()?;
+            let limit = max_sell.to_f64()?;
+            (limit > 0.0).then_some((*component, *state, limit))
+        })
+        .collect();
+    if limits.len() < 2 {
+        return None;
+    }
+
+    let total: f64 = limits.iter().map(|(_, _, limit)| limit).sum();
+    let mut components = Vec::with_capacity(limits.len());
+    let mut quote = SplitQuote {
+        fractions: Vec::with_capacity(limits.len()),
+        amounts_in: Vec::with_capacity(limits.len()),
+        amount_out: BigUint::ZERO,
+        gas: BigUint::ZERO,
+    };
+    let mut remaining = amount_in.clone();
+    for (index, (component, state, limit)) in limits.iter().enumerate() {
+        let fraction = limit / total;
+        // The last pool takes the rounding remainder, mirroring Tycho's split 0.0
+        let share = if index + 1 == limits.len() {
+            remaining.clone()
+        } else {
+            split_share(amount_in, fraction)
+        };
+        remaining -= &share;
+
+        let result = match state.get_amount_out(share.clone(), sell_token, buy_token) {
+            Ok(result) => result,
+            Err(e) => {
+                metrics::quote_failed();
+                debug!(
+                    component = %component.id,
+                    sell_token = %sell_token.symbol,
+                    buy_token = %buy_token.symbol,
+                    error = %e,
+                    "Split leg quote failed, skipping the split"
+                );
+                return None;
+            }
+        };
+        components.push((*component).clone());
+        quote.fractions.push(fraction);
+        quote.amounts_in.push(share);
+        quote.amount_out += result.amount;
+        quote.gas += result.gas;
+    }
+
+    Some((components, quote))
+}
+
+/// Encodes one trade split over several pools of the same pair, see `quote_split`.
+#[allow(clippy::too_many_arguments)]
+pub fn process_split_swap(
+    components: Vec<ProtocolComponent>,
+    sell_token: &Token,
+    buy_token: &Token,
+    split: SplitQuote,
+    amount_in: BigUint,
+    signer: &dyn TradeSigner,
+    settings: &SwapSettings,
+    encoder: &dyn TychoEncoder,
+) -> Result<TransactionRequest, EncodingError> {
+    let hops = components
+        .into_iter()
+        .zip(&split.fractions)
+        .map(|(component, fraction)| (component, sell_token.clone(), buy_token.clone(), *fraction))
+        .collect();
+
+    encode_hops(
+        hops,
+        split.amounts_in,
+        amount_in,
+        split.amount_out,
+        TradeMode::ExactIn,
+        signer,
+        settings,
+        encoder,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encode_hops(
+    hops: Vec<(ProtocolComponent, Token, Token, f64)>,
+    estimated_amounts_in: Vec<BigUint>,
+    amount_in: BigUint,
+    amount_out: BigUint,
+    mode: TradeMode,
+    signer: &dyn TradeSigner,
+    settings: &SwapSettings,
+    encoder: &dyn TychoEncoder,
+) -> Result<TransactionRequest, EncodingError> {
+    validate_hops(&hops)?;
+
+    let sell_token = hops[0].1.clone();
+    let buy_token = hops[hops.len() - 1].2.clone();
+
+    let native_action = if is_native(&sell_token) {
+        Some(NativeAction::Wrap)
+    } else if is_native(&buy_token) {
+        Some(NativeAction::Unwrap)
+    } else {
+        None
     };
 
+    // The router encoder rejects exact-out solutions its swap functions can't express,
+    // which surfaces as `EncodingError::Router`
+    let slippage_bps = settings.slippage.for_pair(&sell_token, &buy_token);
+    let (given_amount, checked_amount) = match mode {
+        TradeMode::ExactIn => (amount_in.clone(), apply_slippage(&amount_out, slippage_bps)),
+        TradeMode::ExactOut => (apply_input_slippage(&amount_in, slippage_bps), amount_out),
+    };
+
+    // Tycho gives the last swap out of each token split 0.0, meaning "whatever is left"
+    let remainder_hops: HashMap<Bytes, usize> = last_hop_selling(&hops)
+        .into_iter()
+        .map(|(token, index)| (token.clone(), index))
+        .collect();
+
+    let swaps = hops
+        .into_iter()
+        .zip(estimated_amounts_in)
+        .enumerate()
+        .map(|(index, ((component, token_in, token_out, split), estimated_amount_in))| {
+            let is_remainder = remainder_hops.get(&token_in.address) == Some(&index);
+            Swap {
+                component: component.into(),
+                token_in: pool_token_address(&token_in, settings),
+                token_out: pool_token_address(&token_out, settings),
+                split: if is_remainder { 0.0 } else { split },
+                user_data: None,
+                protocol_state: None,
+                estimated_amount_in: Some(estimated_amount_in),
+            }
+        })
+        .collect();
+
     let solution = Solution {
         sender: Bytes::from(signer.address().as_slice()),
-        receiver: Bytes::from(signer.address().as_slice()),
+        receiver: Bytes::from(settings.receiver.unwrap_or(signer.address()).as_slice()),
         given_token: Bytes::from(sell_token.address.as_ref()),
-        given_amount: amount_in.clone(),
+        given_amount: given_amount.clone(),
         checked_token: Bytes::from(buy_token.address.as_ref()),
-        exact_out: false,
-        checked_amount: min_amount_out.clone(),
-        swaps: vec![swap],
-        native_action: None,
+        exact_out: mode == TradeMode::ExactOut,
+        checked_amount,
+        swaps,
+        native_action: native_action.clone(),
     };
 
-    // let encoded_solutions = encoder.encode_full_calldata(vec![solution])?;
-    // let encoded = &encoded_solutions[0];
-
-    // let executor_address = Address::from_slice(&encoded.interacting_with);
-    // let approve_function_signature = "approve(address,uint256)";
-    // let args = (executor_address, biguint_to_u256(&amount_in));
-    // let approve_calldata = encode_input(approve_function_signature, args.abi_encode());
-    //
-    // let encoded_data = create_multitrade_calldata(
-    //     Address::from_slice(sell_token.address.as_ref()),
-    //     executor_address,
-    //     approve_calldata,
-    //     encoded.swaps.clone(),
-    // )?;
-    //
-    // info!("Encoded data: 0x{}", hex::encode(&encoded_data));
-    //
-    // Ok(encoded_data)
-    let transactions = encoder.encode_full_calldata(vec![solution])?;
-    let transaction = &transactions[0];
+    // Wrapping spends ETH sent along with the call rather than an ERC20 balance
+    let value = match native_action {
+        Some(NativeAction::Wrap) => biguint_to_u256(&given_amount),
+        _ => U256::ZERO,
+    };
+
+    let from = settings.wallet_address.unwrap_or(signer.address());
+
+    build_transaction(solution, &sell_token, &given_amount, value, from, signer, settings, encoder)
+}
+
+/// Pools trade WETH, the router wraps or unwraps native ETH around them as told by
+/// the solution's `native_action`.
+fn pool_token_address(token: &Token, settings: &SwapSettings) -> Bytes {
+    if is_native(token) {
+        Bytes::from(settings.wrapped_native.as_slice())
+    } else {
+        token.address.clone()
+    }
+}
+
+/// Index of the last hop selling each token, the one taking the remainder.
+fn last_hop_selling(hops: &[(ProtocolComponent, Token, Token, f64)]) -> HashMap<&Bytes, usize> {
+    hops.iter()
+        .enumerate()
+        .map(|(index, (_, token_in, _, _))| (&token_in.address, index))
+        .collect()
+}
+
+/// Hops selling the same token split it between them: their splits sum to 1.0, or,
+/// following Tycho, the last one is 0.0 and takes whatever the others leave. Every hop
+/// has to sell the route input or a token an earlier hop bought.
+fn validate_hops(hops: &[(ProtocolComponent, Token, Token, f64)]) -> Result<(), EncodingError> {
+    let Some((_, sell_token, _, _)) = hops.first() else {
+        return Err(EncodingError::InvalidRoute(
+            "multi-hop swap needs at least one hop".to_string(),
+        ));
+    };
+
+    let remainder_hops = last_hop_selling(hops);
+    // Per sold token: its symbol, the splits summed and whether the remainder hop says 0.0
+    let mut splits: HashMap<&Bytes, (&str, f64, bool)> = HashMap::new();
+    for (index, (_, token_in, _, split)) in hops.iter().enumerate() {
+        if !(0.0..=1.0).contains(split) {
+            return Err(EncodingError::InvalidRoute(format!(
+                "hop {index} has split {split}, outside 0.0 to 1.0"
+            )));
+        }
+        let is_remainder = remainder_hops.get(&token_in.address) == Some(&index);
+        if *split == 0.0 && !is_remainder {
+            return Err(EncodingError::InvalidRoute(format!(
+                "hop {index} has split 0.0 but isn't the last hop selling {}",
+                token_in.symbol
+            )));
+        }
+        let entry = splits
+            .entry(&token_in.address)
+            .or_insert((&token_in.symbol, 0.0, false));
+        entry.1 += split;
+        entry.2 |= is_remainder && *split == 0.0;
+    }
+    for (symbol, split_sum, open_remainder) in splits.values() {
+        let valid = if *open_remainder {
+            *split_sum < 1.0 - SPLIT_EPSILON
+        } else {
+            (split_sum - 1.0).abs() <= SPLIT_EPSILON
+        };
+        if !valid {
+            return Err(EncodingError::InvalidRoute(format!(
+                "splits of hops selling {symbol} must sum to 1.0, or stay below it with a last split of 0.0, got {split_sum}"
+            )));
+        }
+    }
+
+    let mut reachable = vec![&sell_token.address];
+    for (index, (_, token_in, token_out, _)) in hops.iter().enumerate() {
+        if !reachable.contains(&&token_in.address) {
+            return Err(EncodingError::InvalidRoute(format!(
+                "hop {} sells {} which is neither the route input nor bought by an earlier hop",
+                index, token_in.symbol
+            )));
+        }
+        reachable.push(&token_out.address);
+    }
+
+    Ok(())
+}
+
+/// Router address and calldata for `solution`, built entirely by tycho-execution so the
+/// wrap/unwrap, transfer and token packing always match the router ABI.
+///
+/// Permit2 solutions are the exception: the library leaves the permit unsigned, so
+/// they go through `build_permit2_calldata` with `signer`. That calldata is only valid
+/// sent by `signer` itself, `process_swap` rejects it since the executor contract makes
+/// the router call.
+pub fn build_router_calldata(
+    encoder: &dyn TychoEncoder,
+    solution: Solution,
+    signer: &dyn TradeSigner,
+    settings: &SwapSettings,
+) -> Result<(Address, Vec<u8>), EncodingError> {
+    let transaction = if settings.user_transfer_type == UserTransferType::TransferFromPermit2 {
+        let encoded = encoder
+            .encode_solutions(vec![solution.clone()])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| EncodingError::AbiEncode("router returned no solution".to_string()))?;
+        Transaction {
+            to: encoded.interacting_with.clone(),
+            value: BigUint::ZERO,
+            data: build_permit2_calldata(encoded, &solution, settings.chain_id, signer)?,
+        }
+    } else {
+        encoder
+            .encode_full_calldata(vec![solution])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| EncodingError::AbiEncode("router returned no transaction".to_string()))?
+    };
 
     info!("=== Transaction Debug ===");
     info!("To: 0x{}", hex::encode(&transaction.to));
     info!("Data length: {} bytes", transaction.data.len());
-    info!(
-        "Function selector: 0x{}",
-        hex::encode(&transaction.data[..4])
-    );
+    match RouterFunction::from_calldata(&transaction.data) {
+        Ok(router_function) => info!("Router function: {:?}", router_function),
+        Err(e) => warn!("Router function: {}", e),
+    }
     info!("========================");
 
-    let router_address = Address::from_slice(&transaction.to);
+    Ok((decode_address(&transaction.to)?, transaction.data))
+}
 
-    let swap_calldata = transaction.data.clone();
-    let amount_u256 = biguint_to_u256(&amount_in);
-    let approve_function_signature = "approve(address,uint256)";
-    let args = (router_address, amount_u256);
-    let approve_calldata = encode_input(approve_function_signature, args.abi_encode());
-    let encoded_data = create_multitrade_calldata(
-        Address::from_slice(sell_token.address.as_ref()),
-        router_address,
-        approve_calldata,
-        swap_calldata,
-    )?;
+#[allow(clippy::too_many_arguments)]
+fn build_transaction(
+    solution: Solution,
+    sell_token: &Token,
+    amount_in: &BigUint,
+    value: U256,
+    from: Address,
+    signer: &dyn TradeSigner,
+    settings: &SwapSettings,
+    encoder: &dyn TychoEncoder,
+) -> Result<TransactionRequest, EncodingError> {
+    if settings.user_transfer_type == UserTransferType::TransferFromPermit2 {
+        return Err(EncodingError::InvalidRoute(
+            "Permit2 can't be used through the executor contract, it would be the permit owner"
+                .to_string(),
+        ));
+    }
+    let (router_address, swap_calldata) = build_router_calldata(encoder, solution, signer, settings)?;
+    // Wrapping ETH funds the router from the call value, there is nothing to pull
+    if !is_native(sell_token) {
+        check_transfer_type(&swap_calldata, &settings.user_transfer_type)?;
+    }
 
-    info!("Final calldata: 0x{}", hex::encode(&encoded_data));
+    // Native ETH has no allowance, it is sent along with the router call instead
+    let token_address = decode_address(&sell_token.address)?;
+    let amount_u256 = biguint_to_u256(amount_in);
+    let encoded_data = match &settings.user_transfer_type {
+        // The router expects the tokens already sent to it
+        UserTransferType::None if !is_native(sell_token) => InteractionBatch::new()
+            .add_call(token_address, U256::ZERO, erc20_transfer(router_address, amount_u256))
+            .add_call(router_address, value, swap_calldata)
+            .build(token_address, settings.execution_mode),
+        _ => {
+            let approval = (!is_native(sell_token)).then_some((router_address, amount_u256));
+            create_multitrade_calldata(
+                token_address,
+                router_address,
+                approval,
+                settings.approve_strategy,
+                swap_calldata,
+                value,
+                settings.execution_mode,
+            )
+        }
+    };
+
+    info!(mode = ?settings.execution_mode, "Final calldata: 0x{}", hex::encode(&encoded_data));
 
     let tx_request = TransactionRequest::default()
-        .to(OUR_CONTRACT)
-        .from(ARBITRAGE_WALLET_ADDRESS)
+        .to(settings.executor_contract)
+        .from(from)
         .input(AlloyBytes::from(encoded_data).into())
-        .value(U256::ZERO);
+        .value(value);
 
     Ok(tx_request)
 }
+
+/// Errors when the router calldata pulls tokens differently from `USER_TRANSFER_TYPE`, the
+/// interactions sent before it would then leave the router without funds.
+fn check_transfer_type(
+    calldata: &[u8],
+    transfer_type: &UserTransferType,
+) -> Result<(), EncodingError> {
+    let function = RouterFunction::from_calldata(calldata)?;
+    let in_sync = match transfer_type {
+        UserTransferType::TransferFromPermit2 => function.is_permit2(),
+        UserTransferType::TransferFrom => function.transfer_from_flag(calldata) == Some(true),
+        UserTransferType::None => function.transfer_from_flag(calldata) == Some(false),
+    };
+    if !in_sync {
+        return Err(EncodingError::InvalidRoute(format!(
+            "router calldata uses {function:?} which doesn't match USER_TRANSFER_TYPE {transfer_type:?}"
+        )));
+    }
+    Ok(())
+}
+
+pub fn decode_address(bytes: &[u8]) -> Result<Address, EncodingError> {
+    Address::try_from(bytes)
+        .map_err(|_| EncodingError::AddressDecode(format!("0x{}", hex::encode(bytes))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::sol_types::SolCall;
+    use tycho_execution::encoding::errors::EncodingError as TychoEncodingError;
+    use tycho_execution::encoding::evm::encoder_builders::TychoRouterEncoderBuilder;
+    use tycho_simulation::tycho_common::models::Chain;
+
+    use crate::encoding::{ITychoRouter, decode_multitrade_calldata};
+    use crate::testing::{
+        MockEncoder, MockSigner, component, encoded, pool, pool_component, swap_settings, token,
+    };
+
+    fn hop(sell: &Token, buy: &Token, split: f64) -> (ProtocolComponent, Token, Token, f64) {
+        let id = format!("{}-{}", sell.symbol, buy.symbol);
+        (component(&id, &[sell.clone(), buy.clone()]), sell.clone(), buy.clone(), split)
+    }
+
+    fn route_error(hops: &[(ProtocolComponent, Token, Token, f64)]) -> String {
+        match validate_hops(hops) {
+            Err(EncodingError::InvalidRoute(reason)) => reason,
+            other => panic!("expected an invalid route, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn validate_hops_accepts_full_and_remainder_splits() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let weth = token(0xc0, "WETH", 18);
+
+        // Sequential, with explicit 1.0 splits or Tycho's 0.0
+        validate_hops(&[hop(&wbtc, &weth, 1.0), hop(&weth, &usdc, 1.0)]).unwrap();
+        validate_hops(&[hop(&wbtc, &weth, 0.0), hop(&weth, &usdc, 0.0)]).unwrap();
+        // Split over two pools, summing to 1.0 or leaving the remainder to the last
+        validate_hops(&[hop(&wbtc, &weth, 0.6), hop(&wbtc, &weth, 0.4)]).unwrap();
+        validate_hops(&[hop(&wbtc, &weth, 0.6), hop(&wbtc, &weth, 0.0)]).unwrap();
+        // Split then merged into a second hop
+        validate_hops(&[
+            hop(&wbtc, &weth, 0.5),
+            hop(&wbtc, &usdc, 0.0),
+            hop(&usdc, &weth, 0.0),
+        ])
+        .unwrap();
+    }
+
+    #[test]
+    fn validate_hops_rejects_bad_splits_and_broken_chains() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let weth = token(0xc0, "WETH", 18);
+
+        assert!(route_error(&[]).contains("at least one hop"));
+        assert!(route_error(&[hop(&wbtc, &weth, 0.6), hop(&wbtc, &weth, 0.3)]).contains("WBTC"));
+        // The remainder hop would get nothing
+        assert!(route_error(&[hop(&wbtc, &weth, 1.0), hop(&wbtc, &weth, 0.0)]).contains("WBTC"));
+        assert!(route_error(&[hop(&wbtc, &weth, 0.0), hop(&wbtc, &weth, 1.0)]).contains("hop 0"));
+        assert!(route_error(&[hop(&wbtc, &weth, 1.5)]).contains("outside"));
+        // USDC is neither the input nor bought before it is sold
+        assert!(
+            route_error(&[hop(&wbtc, &weth, 1.0), hop(&usdc, &weth, 1.0)])
+                .contains("hop 1 sells USDC")
+        );
+    }
+
+    #[test]
+    fn a_two_hop_route_is_encoded_as_a_sequential_swap_by_the_router_encoder() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let weth = token(0xc0, "WETH", 18);
+        let hops = vec![
+            (pool_component(0xb1, &[wbtc.clone(), weth.clone()]), wbtc.clone(), weth.clone()),
+            (pool_component(0xb2, &[weth.clone(), usdc.clone()]), weth.clone(), usdc.clone()),
+        ];
+        let amount_in = BigUint::from(10u64.pow(8));
+        let weth_out = BigUint::from(29u128 * 10u128.pow(18));
+        let usdc_out = BigUint::from(100_000u64 * 10u64.pow(6));
+        let encoder = TychoRouterEncoderBuilder::new()
+            .chain(Chain::Ethereum)
+            .user_transfer_type(UserTransferType::TransferFrom)
+            .build()
+            .unwrap();
+        let signer = MockSigner::new();
+        let settings = swap_settings();
+
+        let tx = process_route(
+            hops,
+            vec![amount_in.clone(), weth_out],
+            usdc_out.clone(),
+            &signer,
+            &settings,
+            encoder.as_ref(),
+        )
+        .unwrap();
+
+        let call = decode_multitrade_calldata(tx.input.input().unwrap()).unwrap();
+        let router_call = &call.interactions.last().unwrap().callData;
+        assert_eq!(
+            RouterFunction::from_calldata(router_call).unwrap(),
+            RouterFunction::SequentialSwap
+        );
+        let swap = ITychoRouter::sequentialSwapCall::abi_decode(router_call).unwrap();
+        assert_eq!(swap.amountIn, biguint_to_u256(&amount_in));
+        assert_eq!(swap.tokenIn.as_slice(), wbtc.address.as_ref());
+        assert_eq!(swap.tokenOut.as_slice(), usdc.address.as_ref());
+        assert_eq!(swap.minAmountOut, biguint_to_u256(&apply_slippage(&usdc_out, 50)));
+        assert_eq!(swap.receiver, signer.address());
+        assert!(swap.transferFrom);
+        assert!(!swap.wrapEth && !swap.unwrapEth);
+        // Each hop is a length-prefixed swap naming its pool
+        for pool_address in [[0xb1; 20], [0xb2; 20]] {
+            assert!(swap.swaps.windows(20).any(|window| window == pool_address));
+        }
+    }
+
+    #[test]
+    fn multi_hop_estimates_chain_each_hops_output_into_the_next() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let weth = token(0xc0, "WETH", 18);
+        let wbtc_weth = pool(1_000 * 10u128.pow(8), 30_000 * 10u128.pow(18));
+        let weth_usdc = pool(40_000_000 * 10u128.pow(6), 10_000 * 10u128.pow(18));
+        let hops = vec![hop(&wbtc, &weth, 1.0), hop(&weth, &usdc, 1.0)];
+        let amount_in = BigUint::from(10u64.pow(8));
+
+        let weth_out = wbtc_weth.get_amount_out(amount_in.clone(), &wbtc, &weth).unwrap().amount;
+        let usdc_out = weth_usdc.get_amount_out(weth_out.clone(), &weth, &usdc).unwrap().amount;
+        let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SequentialSwap));
+        process_multi_hop_swap(
+            hops,
+            &[&wbtc_weth, &weth_usdc],
+            amount_in.clone(),
+            &MockSigner::new(),
+            &swap_settings(),
+            &encoder,
+        )
+        .unwrap();
+
+        let solution = &encoder.calls()[0];
+        let estimates: Vec<_> = solution
+            .swaps
+            .iter()
+            .map(|swap| swap.estimated_amount_in.clone().unwrap())
+            .collect();
+        assert_eq!(estimates, vec![amount_in, weth_out]);
+        assert_eq!(solution.checked_amount, apply_slippage(&usdc_out, 50));
+        assert!(solution.swaps.iter().all(|swap| swap.split == 0.0));
+    }
+
+    #[test]
+    fn split_hops_take_their_share_and_the_last_takes_the_rest() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let weth = token(0xc0, "WETH", 18);
+        let first = pool(1_000 * 10u128.pow(8), 30_000 * 10u128.pow(18));
+        let second = pool(500 * 10u128.pow(8), 15_100 * 10u128.pow(18));
+        let hops = [hop(&wbtc, &weth, 0.25), hop(&wbtc, &weth, 0.0)];
+
+        let quote = quote_hops(&hops, &[&first, &second], BigUint::from(1_000u32)).unwrap();
+
+        assert_eq!(quote.hop_amounts_in, vec![BigUint::from(250u32), BigUint::from(750u32)]);
+        let expected = first.get_amount_out(BigUint::from(250u32), &wbtc, &weth).unwrap().amount
+            + second.get_amount_out(BigUint::from(750u32), &wbtc, &weth).unwrap().amount;
+        assert_eq!(quote.amount_out, expected);
+    }
+
+    #[test]
+    fn process_swap_hands_the_encoder_amounts_and_tokens_in_both_directions() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let pool = component("pool", &[wbtc.clone(), usdc.clone()]);
+        let settings = swap_settings();
+
+        for (sell, buy) in [(&wbtc, &usdc), (&usdc, &wbtc)] {
+            let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwap));
+            process_swap(
+                &pool,
+                sell,
+                buy,
+                BigUint::from(1_000_000u32),
+                BigUint::from(2_000_000u32),
+                TradeMode::ExactIn,
+                &MockSigner::new(),
+                &settings,
+                &encoder,
+            )
+            .unwrap();
+
+            let calls = encoder.calls();
+            assert_eq!(calls.len(), 1);
+            let solution = &calls[0];
+            assert_eq!(solution.given_token, sell.address);
+            assert_eq!(solution.given_amount, BigUint::from(1_000_000u32));
+            assert_eq!(solution.checked_token, buy.address);
+            // 50 bps off the quoted output
+            assert_eq!(solution.checked_amount, BigUint::from(1_990_000u32));
+            assert!(!solution.exact_out);
+            assert_eq!(solution.swaps.len(), 1);
+            assert_eq!(solution.swaps[0].token_in, sell.address);
+            assert_eq!(solution.swaps[0].token_out, buy.address);
+        }
+    }
+
+    #[test]
+    fn native_eth_is_wrapped_on_the_way_in_and_unwrapped_on_the_way_out() {
+        let eth = Token::new(
+            &Bytes::from(NATIVE_ETH_ADDRESS.as_slice()),
+            "ETH",
+            18,
+            0,
+            &[],
+            Chain::Ethereum,
+            100,
+        );
+        let wbtc = token(0x22, "WBTC", 8);
+        let pool = component("pool", &[wbtc.clone(), eth.clone()]);
+        let settings = swap_settings();
+        let weth = Bytes::from(settings.wrapped_native.as_slice());
+        let amount_in = BigUint::from(10u64.pow(18));
+        let swap = |sell: &Token, buy: &Token| {
+            let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwap));
+            let tx = process_swap(
+                &pool,
+                sell,
+                buy,
+                amount_in.clone(),
+                BigUint::from(3_000_000u32),
+                TradeMode::ExactIn,
+                &MockSigner::new(),
+                &settings,
+                &encoder,
+            )
+            .unwrap();
+            let call = decode_multitrade_calldata(tx.input.input().unwrap()).unwrap();
+            let router = call.interactions.last().unwrap().clone();
+            let router_call = ITychoRouter::singleSwapCall::abi_decode(&router.callData).unwrap();
+            (encoder.calls().remove(0), tx, call.interactions.len(), router, router_call)
+        };
+
+        // ETH -> WBTC: the ETH rides along with the call and the pool sees WETH
+        let (solution, tx, interactions, router, router_call) = swap(&eth, &wbtc);
+        assert_eq!(solution.native_action, Some(NativeAction::Wrap));
+        assert_eq!(solution.swaps[0].token_in, weth);
+        assert_eq!(solution.swaps[0].token_out, wbtc.address);
+        assert_eq!(tx.value, Some(biguint_to_u256(&amount_in)));
+        // No approval for ETH, only the router call, which forwards the value
+        assert_eq!(interactions, 1);
+        assert_eq!(router.value, biguint_to_u256(&amount_in));
+        assert!(router_call.wrapEth && !router_call.unwrapEth);
+
+        // WBTC -> ETH: nothing is sent along, the router unwraps the WETH it gets
+        let (solution, tx, interactions, router, router_call) = swap(&wbtc, &eth);
+        assert_eq!(solution.native_action, Some(NativeAction::Unwrap));
+        assert_eq!(solution.swaps[0].token_in, wbtc.address);
+        assert_eq!(solution.swaps[0].token_out, weth);
+        assert_eq!(tx.value, Some(U256::ZERO));
+        assert!(interactions > 1, "the WBTC approval comes first");
+        assert_eq!(router.value, U256::ZERO);
+        assert!(!router_call.wrapEth && router_call.unwrapEth);
+    }
+
+    #[test]
+    fn value_is_sent_exactly_when_selling_native_eth() {
+        let eth = Token::new(
+            &Bytes::from(NATIVE_ETH_ADDRESS.as_slice()),
+            "ETH",
+            18,
+            0,
+            &[],
+            Chain::Ethereum,
+            100,
+        );
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let amount_in = BigUint::from(10u64.pow(8));
+
+        for (sell, buy) in [(&eth, &wbtc), (&wbtc, &eth), (&wbtc, &usdc), (&usdc, &wbtc)] {
+            let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwap));
+            let tx = process_swap(
+                &component("pool", &[sell.clone(), buy.clone()]),
+                sell,
+                buy,
+                amount_in.clone(),
+                BigUint::from(1_000u32),
+                TradeMode::ExactIn,
+                &MockSigner::new(),
+                &swap_settings(),
+                &encoder,
+            )
+            .unwrap();
+
+            let value = tx.value.unwrap_or_default();
+            if is_native(sell) {
+                assert_eq!(value, biguint_to_u256(&amount_in), "{} -> {}", sell.symbol, buy.symbol);
+            } else {
+                assert_eq!(value, U256::ZERO, "{} -> {}", sell.symbol, buy.symbol);
+            }
+        }
+    }
+
+    #[test]
+    fn exact_out_fixes_the_output_and_puts_slippage_on_the_input() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let weth = token(0xc0, "WETH", 18);
+        let state = pool(1_000 * 10u128.pow(8), 30_000 * 10u128.pow(18));
+        let pool = component("pool", &[wbtc.clone(), weth.clone()]);
+        let wanted = BigUint::from(3u128 * 10u128.pow(18));
+        let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwap));
+
+        process_exact_out_swap(
+            &pool,
+            &state,
+            &wbtc,
+            &weth,
+            wanted.clone(),
+            &MockSigner::new(),
+            &swap_settings(),
+            &encoder,
+        )
+        .unwrap();
+
+        let solution = &encoder.calls()[0];
+        let needed = amount_in_for(&state, &wbtc, &weth, &wanted).unwrap();
+        assert!(solution.exact_out);
+        assert_eq!(solution.checked_token, weth.address);
+        assert_eq!(solution.checked_amount, wanted);
+        // 50 bps on top of the input the pool needs, rounded up
+        assert_eq!(solution.given_amount, apply_input_slippage(&needed, 50));
+        assert!(solution.given_amount > needed);
+
+        let too_much = BigUint::from(30_000u128 * 10u128.pow(18));
+        let result = process_exact_out_swap(
+            &pool,
+            &state,
+            &wbtc,
+            &weth,
+            too_much,
+            &MockSigner::new(),
+            &swap_settings(),
+            &MockEncoder::new(),
+        );
+        assert!(matches!(result, Err(EncodingError::InvalidRoute(_))));
+    }
+
+    #[test]
+    fn process_swap_surfaces_encoder_failures() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let pool = component("pool", &[wbtc.clone(), usdc.clone()]);
+        let encoder = MockEncoder::new().fail_with("no route");
+
+        let result = process_swap(
+            &pool,
+            &wbtc,
+            &usdc,
+            BigUint::from(1_000u32),
+            BigUint::from(2_000u32),
+            TradeMode::ExactIn,
+            &MockSigner::new(),
+            &swap_settings(),
+            &encoder,
+        );
+
+        assert!(matches!(result, Err(EncodingError::Router(_))));
+    }
+
+    /// Settings for the golden fixtures, with a fixed receiver instead of the signer.
+    fn fixture_settings() -> SwapSettings {
+        SwapSettings {
+            receiver: Some(Address::repeat_byte(0x0b)),
+            ..swap_settings()
+        }
+    }
+
+    fn assert_matches_fixture(tx: &TransactionRequest, fixture: &str) {
+        assert_eq!(tx.to, Some(crate::testing::EXECUTOR.into()));
+        assert_eq!(tx.value, Some(U256::ZERO));
+        assert_eq!(hex::encode(tx.input.input().unwrap()), fixture.trim());
+    }
+
+    #[test]
+    fn single_swap_calldata_matches_its_fixture() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwap));
+
+        let tx = process_swap(
+            &component("pool", &[wbtc.clone(), usdc.clone()]),
+            &wbtc,
+            &usdc,
+            BigUint::from(1_000_000u32),
+            BigUint::from(2_000_000u32),
+            TradeMode::ExactIn,
+            &MockSigner::new(),
+            &fixture_settings(),
+            &encoder,
+        )
+        .unwrap();
+
+        assert_matches_fixture(&tx, include_str!("../tests/fixtures/single_swap.hex"));
+    }
+
+    #[test]
+    fn sequential_swap_calldata_matches_its_fixture() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let weth = token(0xc0, "WETH", 18);
+        let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SequentialSwap));
+        let hops = vec![
+            (component("WBTC-WETH", &[wbtc.clone(), weth.clone()]), wbtc.clone(), weth.clone()),
+            (component("WETH-USDC", &[weth.clone(), usdc.clone()]), weth.clone(), usdc.clone()),
+        ];
+
+        let tx = process_route(
+            hops,
+            vec![BigUint::from(1_000_000u32), BigUint::from(10u64.pow(17))],
+            BigUint::from(2_000_000u32),
+            &MockSigner::new(),
+            &fixture_settings(),
+            &encoder,
+        )
+        .unwrap();
+
+        assert_matches_fixture(&tx, include_str!("../tests/fixtures/sequential_swap.hex"));
+    }
+
+    #[test]
+    fn split_swap_calldata_matches_its_fixture() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let encoder = MockEncoder::new().respond_with(encoded(RouterFunction::SplitSwap));
+        let split = SplitQuote {
+            fractions: vec![0.6, 0.4],
+            amounts_in: vec![BigUint::from(600_000u32), BigUint::from(400_000u32)],
+            amount_out: BigUint::from(2_000_000u32),
+            gas: BigUint::ZERO,
+        };
+
+        let tx = process_split_swap(
+            vec![
+                component("first", &[wbtc.clone(), usdc.clone()]),
+                component("second", &[wbtc.clone(), usdc.clone()]),
+            ],
+            &wbtc,
+            &usdc,
+            split,
+            BigUint::from(1_000_000u32),
+            &MockSigner::new(),
+            &fixture_settings(),
+            &encoder,
+        )
+        .unwrap();
+
+        assert_matches_fixture(&tx, include_str!("../tests/fixtures/split_swap.hex"));
+    }
+
+    #[test]
+    fn split_calldata_is_byte_for_byte_what_the_router_encoder_builds() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let pools = [
+            pool_component(0xb1, &[wbtc.clone(), usdc.clone()]),
+            pool_component(0xb2, &[wbtc.clone(), usdc.clone()]),
+        ];
+        let encoder = TychoRouterEncoderBuilder::new()
+            .chain(Chain::Ethereum)
+            .user_transfer_type(UserTransferType::TransferFrom)
+            .build()
+            .unwrap();
+        let signer = MockSigner::new();
+        let settings = swap_settings();
+        let split = SplitQuote {
+            fractions: vec![0.6, 0.4],
+            amounts_in: vec![BigUint::from(600_000u32), BigUint::from(400_000u32)],
+            amount_out: BigUint::from(2_000_000u32),
+            gas: BigUint::ZERO,
+        };
+
+        let tx = process_split_swap(
+            pools.to_vec(),
+            &wbtc,
+            &usdc,
+            split,
+            BigUint::from(1_000_000u32),
+            &signer,
+            &settings,
+            encoder.as_ref(),
+        )
+        .unwrap();
+
+        // The same trade written out by hand, the last pool taking the remainder
+        let swap = |pool: &ProtocolComponent, split: f64, estimated_amount_in: u32| Swap {
+            component: pool.clone().into(),
+            token_in: wbtc.address.clone(),
+            token_out: usdc.address.clone(),
+            split,
+            user_data: None,
+            protocol_state: None,
+            estimated_amount_in: Some(BigUint::from(estimated_amount_in)),
+        };
+        let solution = Solution {
+            sender: Bytes::from(signer.address().as_slice()),
+            receiver: Bytes::from(signer.address().as_slice()),
+            given_token: wbtc.address.clone(),
+            given_amount: BigUint::from(1_000_000u32),
+            checked_token: usdc.address.clone(),
+            exact_out: false,
+            checked_amount: BigUint::from(1_990_000u32),
+            swaps: vec![swap(&pools[0], 0.6, 600_000), swap(&pools[1], 0.0, 400_000)],
+            native_action: None,
+        };
+        let expected = encoder.encode_full_calldata(vec![solution.clone()]).unwrap().remove(0);
+        let expected_router = decode_address(&expected.to).unwrap();
+
+        let (router, calldata) =
+            build_router_calldata(encoder.as_ref(), solution, &signer, &settings).unwrap();
+        assert_eq!(router, expected_router);
+        assert_eq!(hex::encode(&calldata), hex::encode(&expected.data));
+
+        let call = decode_multitrade_calldata(tx.input.input().unwrap()).unwrap();
+        let router_call = call.interactions.last().unwrap();
+        assert_eq!(router_call.target, expected_router);
+        assert_eq!(hex::encode(&router_call.callData), hex::encode(&expected.data));
+        assert_eq!(
+            RouterFunction::from_calldata(&router_call.callData).unwrap(),
+            RouterFunction::SplitSwap
+        );
+    }
+
+    #[test]
+    fn every_branch_sends_its_router_call_from_the_wallet_through_the_executor() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let weth = token(0xc0, "WETH", 18);
+        let signer = MockSigner::new();
+        let wallet = Address::repeat_byte(0x0a);
+        let encode = |function: RouterFunction, settings: &SwapSettings| {
+            let encoder = MockEncoder::new().respond_with(encoded(function));
+            let amount_in = BigUint::from(1_000_000u32);
+            let amount_out = BigUint::from(2_000_000u32);
+            let tx = match function {
+                RouterFunction::SingleSwap => process_swap(
+                    &component("pool", &[wbtc.clone(), usdc.clone()]),
+                    &wbtc,
+                    &usdc,
+                    amount_in,
+                    amount_out,
+                    TradeMode::ExactIn,
+                    &signer,
+                    settings,
+                    &encoder,
+                ),
+                RouterFunction::SequentialSwap => process_route(
+                    vec![
+                        (component("WBTC-WETH", &[wbtc.clone(), weth.clone()]), wbtc.clone(), weth.clone()),
+                        (component("WETH-USDC", &[weth.clone(), usdc.clone()]), weth.clone(), usdc.clone()),
+                    ],
+                    vec![amount_in, BigUint::from(10u64.pow(17))],
+                    amount_out,
+                    &signer,
+                    settings,
+                    &encoder,
+                ),
+                _ => process_split_swap(
+                    vec![
+                        component("first", &[wbtc.clone(), usdc.clone()]),
+                        component("second", &[wbtc.clone(), usdc.clone()]),
+                    ],
+                    &wbtc,
+                    &usdc,
+                    SplitQuote {
+                        fractions: vec![0.5, 0.5],
+                        amounts_in: vec![BigUint::from(500_000u32), BigUint::from(500_000u32)],
+                        amount_out,
+                        gas: BigUint::ZERO,
+                    },
+                    amount_in,
+                    &signer,
+                    settings,
+                    &encoder,
+                ),
+            };
+            tx.unwrap()
+        };
+
+        for function in [
+            RouterFunction::SingleSwap,
+            RouterFunction::SequentialSwap,
+            RouterFunction::SplitSwap,
+        ] {
+            let own_wallet = encode(function, &swap_settings());
+            assert_eq!(own_wallet.from, Some(signer.address()));
+
+            let settings = SwapSettings {
+                wallet_address: Some(wallet),
+                ..swap_settings()
+            };
+            let tx = encode(function, &settings);
+            assert_eq!(tx.from, Some(wallet));
+            assert_eq!(tx.to, Some(crate::testing::EXECUTOR.into()));
+
+            // Approve the router for the WBTC sold, then call it
+            let call = decode_multitrade_calldata(tx.input.input().unwrap()).unwrap();
+            assert_eq!(call.token_address.as_slice(), wbtc.address.as_ref());
+            assert_eq!(call.mode, ExecutionMode::Test);
+            let [approval, router] = &call.interactions[..] else {
+                panic!("expected an approval and the router call, got {:?}", call.interactions);
+            };
+            assert_eq!(approval.target.as_slice(), wbtc.address.as_ref());
+            let approved = crate::encoding::IERC20::approveCall::abi_decode(&approval.callData).unwrap();
+            assert_eq!(approved.spender, crate::testing::ROUTER);
+            assert_eq!(approved.amount, U256::from(1_000_000u32));
+            assert_eq!(router.target, crate::testing::ROUTER);
+            assert_eq!(router.value, U256::ZERO);
+            assert_eq!(RouterFunction::from_calldata(&router.callData).unwrap(), function);
+        }
+    }
+
+    #[test]
+    fn router_calldata_for_an_unknown_function_is_rejected() {
+        /// Answers every solution with fixed router calldata.
+        struct RawEncoder(Vec<u8>);
+
+        impl TychoEncoder for RawEncoder {
+            fn encode_solutions(
+                &self,
+                _solutions: Vec<Solution>,
+            ) -> Result<Vec<tycho_execution::encoding::models::EncodedSolution>, TychoEncodingError>
+            {
+                Err(TychoEncodingError::FatalError("not used".to_string()))
+            }
+
+            fn encode_full_calldata(
+                &self,
+                _solutions: Vec<Solution>,
+            ) -> Result<Vec<Transaction>, TychoEncodingError> {
+                Ok(vec![Transaction {
+                    to: Bytes::from(crate::testing::ROUTER.as_slice()),
+                    value: BigUint::ZERO,
+                    data: self.0.clone(),
+                }])
+            }
+
+            fn validate_solution(&self, _solution: &Solution) -> Result<(), TychoEncodingError> {
+                Ok(())
+            }
+        }
+
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let mut calldata = crate::encoding::function_selector("swap(uint256,bytes)").to_vec();
+        calldata.extend([0u8; 64]);
+
+        let result = process_swap(
+            &component("pool", &[wbtc.clone(), usdc.clone()]),
+            &wbtc,
+            &usdc,
+            BigUint::from(1_000_000u32),
+            BigUint::from(2_000_000u32),
+            TradeMode::ExactIn,
+            &MockSigner::new(),
+            &fixture_settings(),
+            &RawEncoder(calldata),
+        );
+
+        // Its transferFrom flag can't be checked against USER_TRANSFER_TYPE
+        assert!(matches!(
+            result,
+            Err(EncodingError::UnsupportedRouterFunction(
+                crate::error::StateErrors::UnsupportedRouterFunction(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn mock_router_calldata_carries_the_transfer_from_flag() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let pool = component("pool", &[wbtc.clone(), usdc.clone()]);
+        let mut settings = swap_settings();
+        settings.user_transfer_type = UserTransferType::None;
+        let swap = |encoder: &MockEncoder| {
+            process_swap(
+                &pool,
+                &wbtc,
+                &usdc,
+                BigUint::from(1_000u32),
+                BigUint::from(2_000u32),
+                TradeMode::ExactIn,
+                &MockSigner::new(),
+                &settings,
+                encoder,
+            )
+        };
+
+        // A transferFrom router call behind a transfer would leave the router unfunded
+        let mismatched = MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwap));
+        assert!(matches!(swap(&mismatched), Err(EncodingError::InvalidRoute(_))));
+
+        let matching = MockEncoder::new()
+            .for_transfer_type(&UserTransferType::None)
+            .respond_with(encoded(RouterFunction::SingleSwap));
+        assert!(swap(&matching).is_ok());
+    }
+
+    #[test]
+    fn permit2_is_rejected_through_the_executor_contract() {
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let pool = component("pool", &[wbtc.clone(), usdc.clone()]);
+        let mut settings = swap_settings();
+        settings.user_transfer_type = UserTransferType::TransferFromPermit2;
+        let signer = MockSigner::new();
+
+        let result = process_swap(
+            &pool,
+            &wbtc,
+            &usdc,
+            BigUint::from(1_000u32),
+            BigUint::from(2_000u32),
+            TradeMode::ExactIn,
+            &signer,
+            &settings,
+            &MockEncoder::new().respond_with(encoded(RouterFunction::SingleSwapPermit2)),
+        );
+
+        assert!(matches!(result, Err(EncodingError::InvalidRoute(_))));
+        assert!(signer.signed().is_empty());
+    }
+
+    /// Collects what `run` logs at `WARN` and above.
+    fn warnings(run: impl FnOnce()) -> String {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Captured(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::WARN)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, run);
+        String::from_utf8(captured.0.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn slippage_keeps_the_quote_at_zero_bps_and_nothing_at_full() {
+        let quote = BigUint::from(1_234_567u32);
+        assert_eq!(apply_slippage(&quote, 0), quote);
+        assert_eq!(apply_input_slippage(&quote, 0), quote);
+
+        assert_eq!(apply_slippage(&quote, 10_000), BigUint::ZERO);
+        // Anything above 100% is capped, on the input side at twice the amount
+        assert_eq!(apply_slippage(&quote, 25_000), BigUint::ZERO);
+        assert_eq!(apply_input_slippage(&quote, 10_000), &quote * 2u32);
+        assert_eq!(apply_input_slippage(&quote, 25_000), &quote * 2u32);
+    }
+
+    #[test]
+    fn fifty_bps_rounds_the_output_down_and_the_input_up() {
+        // 1_000_001 * 9_950 / 10_000 = 995_000.995
+        assert_eq!(apply_slippage(&BigUint::from(1_000_001u32), 50), BigUint::from(995_000u32));
+        // 1_000_001 * 10_050 / 10_000 = 1_005_001.005
+        assert_eq!(
+            apply_input_slippage(&BigUint::from(1_000_001u32), 50),
+            BigUint::from(1_005_002u32)
+        );
+        let one_eth = BigUint::from(1_000_000_000_000_000_000u64);
+        assert_eq!(apply_slippage(&one_eth, 50), BigUint::from(995_000_000_000_000_000u64));
+        assert_eq!(
+            apply_input_slippage(&one_eth, 50),
+            BigUint::from(1_005_000_000_000_000_000u64)
+        );
+    }
+
+    #[test]
+    fn dust_amounts_whose_minimum_rounds_to_zero_warn() {
+        // 201 * 9_950 / 10_000 = 199.99, still protected
+        let logged = warnings(|| {
+            assert_eq!(apply_slippage(&BigUint::from(201u32), 50), BigUint::from(199u32));
+        });
+        assert!(logged.is_empty(), "{logged}");
+
+        // Under 10_000 wei the kept share can round down to nothing
+        for (amount, bps) in [(1u32, 50), (2, 5_001), (9_999, 9_999)] {
+            let logged = warnings(|| {
+                assert_eq!(apply_slippage(&BigUint::from(amount), bps), BigUint::ZERO);
+            });
+            assert!(logged.contains("Minimum amount rounded down to zero"), "{logged}");
+            assert!(logged.contains(&format!("amount={amount}")), "{logged}");
+        }
+
+        // Zero in is zero out, nothing to warn about
+        let logged = warnings(|| assert_eq!(apply_slippage(&BigUint::ZERO, 50), BigUint::ZERO));
+        assert!(logged.is_empty(), "{logged}");
+        // The input side rounds up, so dust never falls to zero
+        assert_eq!(apply_input_slippage(&BigUint::from(1u32), 50), BigUint::from(2u32));
+    }
+
+    #[test]
+    fn token_pairs_yields_every_direction_once() {
+        let tokens = [token(0x11, "AAA", 18), token(0x22, "BBB", 18), token(0x33, "CCC", 18)];
+
+        let pairs: Vec<(&str, &str)> = token_pairs(&tokens)
+            .map(|(sell, buy)| (sell.symbol.as_str(), buy.symbol.as_str()))
+            .collect();
+
+        assert_eq!(
+            pairs,
+            [
+                ("AAA", "BBB"),
+                ("AAA", "CCC"),
+                ("BBB", "AAA"),
+                ("BBB", "CCC"),
+                ("CCC", "AAA"),
+                ("CCC", "BBB"),
+            ]
+        );
+        assert_eq!(token_pairs(&tokens[..1]).count(), 0);
+    }
+
+    #[test]
+    fn best_direction_buys_where_the_token_is_cheap() {
+        const ONE: u128 = 1_000_000_000_000_000_000;
+        let aaa = token(0x11, "AAA", 18);
+        let bbb = token(0x22, "BBB", 18);
+        let rich_in_bbb = pool(1_000 * ONE, 2_000 * ONE);
+        let balanced = pool(1_000 * ONE, 1_000 * ONE);
+        let amount = BigUint::from(ONE);
+
+        // BBB is cheap in the first pool, so sell AAA there and sell BBB back elsewhere
+        let (sell, buy, amount_in) =
+            best_direction(&rich_in_bbb, &balanced, &aaa, &bbb, amount.clone()).unwrap();
+        assert_eq!((sell.symbol.as_str(), buy.symbol.as_str()), ("AAA", "BBB"));
+        assert_eq!(amount_in, amount);
+
+        // Swapped around, AAA is the cheap side and BBB starts from what 1 AAA buys
+        let (sell, buy, amount_in) =
+            best_direction(&balanced, &rich_in_bbb, &aaa, &bbb, amount.clone()).unwrap();
+        assert_eq!((sell.symbol.as_str(), buy.symbol.as_str()), ("BBB", "AAA"));
+        let quoted = balanced.get_amount_out(amount, &aaa, &bbb).unwrap().amount;
+        assert_eq!(amount_in, quoted);
+    }
+
+    #[test]
+    fn best_direction_is_none_when_neither_side_gains() {
+        const ONE: u128 = 1_000_000_000_000_000_000;
+        let aaa = token(0x11, "AAA", 18);
+        let bbb = token(0x22, "BBB", 18);
+        let state = pool(1_000 * ONE, 1_000 * ONE);
+
+        // Same price on both pools, the fees eat either round trip
+        let same_price = pool(5_000 * ONE, 5_000 * ONE);
+        assert!(best_direction(&state, &same_price, &aaa, &bbb, BigUint::from(ONE)).is_none());
+        // Nothing to sell back when the first leg quotes zero
+        assert!(best_direction(&state, &state, &aaa, &bbb, BigUint::ZERO).is_none());
+    }
+
+    #[test]
+    fn swaps_reuse_the_signer_loaded_with_the_config() {
+        let config = crate::testing::config(&[]);
+        // The config's PRIVATE_KEY is the mock signer's key
+        assert_eq!(config.signer.address(), TradeSigner::address(&MockSigner::new()));
+        let wbtc = token(0x22, "WBTC", 8);
+        let usdc = token(0xa0, "USDC", 6);
+        let pool = component("pool", &[wbtc.clone(), usdc.clone()]);
+        let encoder = MockEncoder::new()
+            .respond_with(encoded(RouterFunction::SingleSwap))
+            .respond_with(encoded(RouterFunction::SingleSwap));
+
+        for _ in 0..2 {
+            let tx = process_swap(
+                &pool,
+                &wbtc,
+                &usdc,
+                BigUint::from(1_000u32),
+                BigUint::from(2_000u32),
+                TradeMode::ExactIn,
+                &config.signer,
+                &swap_settings(),
+                &encoder,
+            )
+            .unwrap();
+            assert_eq!(tx.from, Some(config.signer.address()));
+        }
+
+        let signer = Bytes::from(config.signer.address().as_slice());
+        for solution in encoder.calls() {
+            assert_eq!(solution.sender, signer);
+            assert_eq!(solution.receiver, signer);
+        }
+    }
+}
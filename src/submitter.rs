@@ -0,0 +1,356 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use alloy::eips::eip2718::Encodable2718;
+use alloy::hex;
+use alloy::network::{EthereumWallet, TransactionBuilder};
+use alloy::primitives::{TxHash, keccak256};
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use alloy::rpc::json_rpc::{Response, ResponsePayload};
+use alloy::rpc::types::TransactionRequest;
+use alloy::signers::SignerSync;
+use alloy::signers::local::PrivateKeySigner;
+use alloy::transports::http::reqwest::Url;
+use alloy::transports::{TransportError, TransportErrorKind};
+use anyhow::{Context, Result, bail, ensure};
+use futures::future::BoxFuture;
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use crate::config::ConfigSource;
+use crate::executor::{TxSubmitter, join_until};
+
+const DEFAULT_BUNDLE_RELAY_URL: &str = "https://relay.flashbots.net";
+const DEFAULT_BUNDLE_BLOCKS: u64 = 3;
+/// How often the bundle resubmission task checks for a new block.
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// A bundle is only useful for its target block, a slower relay answer is as good as none.
+const RELAY_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where signed transactions are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SubmissionMode {
+    /// The configured RPC, and from there the public mempool.
+    #[default]
+    Public,
+    /// `PRIVATE_RPC_URL`, a protected endpoint such as Flashbots Protect or MEV Blocker.
+    Private,
+    /// `eth_sendBundle` to `BUNDLE_RELAY_URL` for the next `BUNDLE_BLOCKS` blocks.
+    Bundle,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubmissionConfig {
+    pub mode: SubmissionMode,
+    pub private_rpc_url: Option<Url>,
+    pub relay_url: Url,
+    /// Signs the `X-Flashbots-Signature` header. It only builds relay reputation and never
+    /// holds funds, a random key is used when unset.
+    pub bundle_signer: Option<PrivateKeySigner>,
+    /// Consecutive blocks a bundle is submitted for before it expires.
+    pub bundle_blocks: u64,
+}
+
+impl SubmissionConfig {
+    pub fn from_source(source: &ConfigSource) -> Result<Self> {
+        let mode = match source.get("SUBMISSION_MODE").as_deref().map(str::trim) {
+            None | Some("public") => SubmissionMode::Public,
+            Some("private") => SubmissionMode::Private,
+            Some("bundle") => SubmissionMode::Bundle,
+            Some(other) => bail!("Unknown SUBMISSION_MODE {other:?}, expected public, private or bundle"),
+        };
+
+        let private_rpc_url = source
+            .get("PRIVATE_RPC_URL")
+            .map(|raw| raw.trim().parse().context("Can't parse PRIVATE_RPC_URL"))
+            .transpose()?;
+        let relay_url = source
+            .get("BUNDLE_RELAY_URL")
+            .unwrap_or_else(|| DEFAULT_BUNDLE_RELAY_URL.to_string())
+            .trim()
+            .parse()
+            .context("Can't parse BUNDLE_RELAY_URL")?;
+        let bundle_signer = source
+            .get("FLASHBOTS_SIGNING_KEY")
+            .map(|raw| {
+                raw.trim()
+                    .parse::<PrivateKeySigner>()
+                    .map_err(|_| anyhow::anyhow!("FLASHBOTS_SIGNING_KEY is not a valid hex encoded key"))
+            })
+            .transpose()?;
+        let bundle_blocks = match source.get("BUNDLE_BLOCKS") {
+            Some(raw) => raw.trim().parse().context("Can't parse BUNDLE_BLOCKS")?,
+            None => DEFAULT_BUNDLE_BLOCKS,
+        };
+
+        ensure!(
+            mode != SubmissionMode::Private || private_rpc_url.is_some(),
+            "SUBMISSION_MODE=private requires PRIVATE_RPC_URL"
+        );
+        ensure!(bundle_blocks >= 1, "BUNDLE_BLOCKS must be at least 1");
+
+        Ok(Self {
+            mode,
+            private_rpc_url,
+            relay_url,
+            bundle_signer,
+            bundle_blocks,
+        })
+    }
+
+    /// The submitter for `mode`. `provider` is the main RPC, used as is in public mode and
+    /// for block numbers and receipts in bundle mode.
+    pub fn build(
+        &self,
+        provider: DynProvider,
+        wallet: EthereumWallet,
+        chain_id: u64,
+    ) -> Result<Box<dyn TxSubmitter>> {
+        match self.mode {
+            SubmissionMode::Public => Ok(Box::new(RpcSubmitter { provider })),
+            SubmissionMode::Private => {
+                let url = self
+                    .private_rpc_url
+                    .clone()
+                    .context("SUBMISSION_MODE=private requires PRIVATE_RPC_URL")?;
+                info!(rpc = %url, "🕶️ Sending transactions through a private RPC");
+                let provider = ProviderBuilder::new().wallet(wallet).connect_http(url).erased();
+                Ok(Box::new(RpcSubmitter { provider }))
+            }
+            SubmissionMode::Bundle => {
+                let auth = match &self.bundle_signer {
+                    Some(signer) => signer.clone(),
+                    None => {
+                        warn!("FLASHBOTS_SIGNING_KEY not set, signing bundles with a random key");
+                        PrivateKeySigner::random()
+                    }
+                };
+                info!(
+                    relay = %self.relay_url,
+                    blocks = self.bundle_blocks,
+                    "📦 Sending transactions as bundles"
+                );
+                let client = reqwest::Client::builder()
+                    .timeout(RELAY_REQUEST_TIMEOUT)
+                    .build()
+                    .context("Can't build the bundle relay client")?;
+                Ok(Box::new(BundleSubmitter {
+                    relay: Arc::new(BundleRelay {
+                        client,
+                        url: self.relay_url.clone(),
+                        auth,
+                    }),
+                    provider,
+                    wallet,
+                    chain_id,
+                    blocks: self.bundle_blocks,
+                    expired: Arc::default(),
+                    resubmissions: Mutex::new(JoinSet::new()),
+                }))
+            }
+        }
+    }
+}
+
+/// Sends through an RPC whose provider signs with the wallet: the main one in public mode,
+/// the protected one in private mode.
+pub struct RpcSubmitter {
+    provider: DynProvider,
+}
+
+impl TxSubmitter for RpcSubmitter {
+    fn submit(&self, tx: TransactionRequest) -> BoxFuture<'_, Result<TxHash, TransportError>> {
+        Box::pin(async move {
+            let pending = self.provider.send_transaction(tx).await?;
+            Ok(*pending.tx_hash())
+        })
+    }
+}
+
+/// Signs locally and sends a one-transaction bundle targeting the next block, then keeps
+/// resubmitting it for each following block until it is mined or `blocks` have passed.
+pub struct BundleSubmitter {
+    relay: Arc<BundleRelay>,
+    provider: DynProvider,
+    wallet: EthereumWallet,
+    chain_id: u64,
+    blocks: u64,
+    /// Filled by the resubmission tasks, drained by `take_expired`.
+    expired: Arc<Mutex<Vec<TxHash>>>,
+    resubmissions: Mutex<JoinSet<()>>,
+}
+
+impl TxSubmitter for BundleSubmitter {
+    fn submit(&self, tx: TransactionRequest) -> BoxFuture<'_, Result<TxHash, TransportError>> {
+        Box::pin(async move {
+            let envelope = tx
+                .with_chain_id(self.chain_id)
+                .build(&self.wallet)
+                .await
+                .map_err(TransportErrorKind::custom)?;
+            let tx_hash = *envelope.tx_hash();
+            let raw = format!("0x{}", hex::encode(envelope.encoded_2718()));
+
+            let head = self.provider.get_block_number().await?;
+            self.relay.send_bundle(&raw, head + 1).await?;
+            info!(%tx_hash, target_block = head + 1, "📦 Bundle submitted");
+
+            let relay = self.relay.clone();
+            let provider = self.provider.clone();
+            let last_block = head + self.blocks;
+            let expired = self.expired.clone();
+            let mut resubmissions = self.resubmissions.lock().expect("resubmissions lock poisoned");
+            while resubmissions.try_join_next().is_some() {}
+            resubmissions.spawn(async move {
+                if !resubmit_until_mined(relay, provider, raw, tx_hash, head + 2, last_block).await {
+                    expired.lock().expect("expired bundles lock poisoned").push(tx_hash);
+                }
+            });
+
+            Ok(tx_hash)
+        })
+    }
+
+    fn take_expired(&self) -> Vec<TxHash> {
+        std::mem::take(&mut *self.expired.lock().expect("expired bundles lock poisoned"))
+    }
+
+    fn drain(&self, deadline: tokio::time::Instant) -> BoxFuture<'_, ()> {
+        let resubmissions =
+            std::mem::take(&mut *self.resubmissions.lock().expect("resubmissions lock poisoned"));
+        Box::pin(async move {
+            join_until(resubmissions, deadline, "Bundle resubmission").await;
+        })
+    }
+}
+
+/// Sends the bundle again for each new block until the transaction shows up in a receipt
+/// or `last_block` has passed. Returns whether it was included.
+async fn resubmit_until_mined(
+    relay: Arc<BundleRelay>,
+    provider: DynProvider,
+    raw: String,
+    tx_hash: TxHash,
+    mut next_target: u64,
+    last_block: u64,
+) -> bool {
+    loop {
+        tokio::time::sleep(BLOCK_POLL_INTERVAL).await;
+        let head = match provider.get_block_number().await {
+            Ok(head) => head,
+            Err(e) => {
+                debug!(%tx_hash, error = %e, "Can't fetch the block number for bundle resubmission");
+                continue;
+            }
+        };
+        if let Ok(Some(receipt)) = provider.get_transaction_receipt(tx_hash).await {
+            info!(%tx_hash, block = ?receipt.block_number, "📦 Bundle included");
+            return true;
+        }
+        if head >= last_block {
+            warn!(%tx_hash, last_block, "📦 Bundle expired without being included");
+            return false;
+        }
+        if head + 1 < next_target {
+            continue;
+        }
+
+        match relay.send_bundle(&raw, head + 1).await {
+            Ok(()) => debug!(%tx_hash, target_block = head + 1, "📦 Bundle resubmitted"),
+            Err(e) => warn!(%tx_hash, target_block = head + 1, error = %e, "Bundle resubmission failed"),
+        }
+        next_target = head + 2;
+    }
+}
+
+/// `eth_sendBundle` client for a Flashbots-compatible relay.
+struct BundleRelay {
+    client: reqwest::Client,
+    url: Url,
+    auth: PrivateKeySigner,
+}
+
+impl BundleRelay {
+    async fn send_bundle(&self, raw_tx: &str, block: u64) -> Result<(), TransportError> {
+        let body = bundle_request(raw_tx, block);
+        // The relay authenticates the EIP-191 signature of the hex encoded body hash
+        let hash = format!("{}", keccak256(body.as_bytes()));
+        let signature = self
+            .auth
+            .sign_message_sync(hash.as_bytes())
+            .map_err(TransportErrorKind::custom)?;
+        let header = format!(
+            "{}:0x{}",
+            self.auth.address(),
+            hex::encode(signature.as_bytes())
+        );
+
+        let response: Response = self
+            .client
+            .post(self.url.as_str())
+            .header("Content-Type", "application/json")
+            .header("X-Flashbots-Signature", header)
+            .body(body)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(TransportErrorKind::custom)?
+            .json()
+            .await
+            .map_err(TransportErrorKind::custom)?;
+
+        match response.payload {
+            ResponsePayload::Success(_) => Ok(()),
+            ResponsePayload::Failure(error) => Err(TransportError::ErrorResp(error)),
+        }
+    }
+}
+
+/// JSON-RPC body of an `eth_sendBundle` call with one signed transaction.
+fn bundle_request(raw_tx: &str, block: u64) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","id":1,"method":"eth_sendBundle","params":[{{"txs":["{raw_tx}"],"blockNumber":"{block:#x}"}}]}}"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::U64;
+    use alloy::rpc::types::TransactionReceipt;
+    use alloy::transports::mock::Asserter;
+
+    use super::*;
+
+    #[test]
+    fn bundle_request_targets_one_block_with_the_raw_transaction() {
+        assert_eq!(
+            bundle_request("0x02f8", 26),
+            r#"{"jsonrpc":"2.0","id":1,"method":"eth_sendBundle","params":[{"txs":["0x02f8"],"blockNumber":"0x1a"}]}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn a_bundle_not_included_by_its_last_block_expires() {
+        let asserter = Asserter::new();
+        asserter.push_success(&U64::from(12));
+        asserter.push_success(&None::<TransactionReceipt>);
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter).erased();
+        let relay = Arc::new(BundleRelay {
+            client: reqwest::Client::new(),
+            url: "http://127.0.0.1:9".parse().unwrap(),
+            auth: PrivateKeySigner::random(),
+        });
+
+        let included = resubmit_until_mined(
+            relay,
+            provider,
+            "0x02f8".to_string(),
+            TxHash::repeat_byte(0x42),
+            11,
+            12,
+        )
+        .await;
+
+        assert!(!included);
+    }
+}
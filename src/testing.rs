@@ -0,0 +1,404 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use alloy::consensus::{Receipt, ReceiptEnvelope, ReceiptWithBloom};
+use alloy::hex;
+use alloy::network::EthereumWallet;
+use alloy::primitives::{Address, B256, Bloom, Signature, TxHash, U256, address};
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use alloy::rpc::types::TransactionReceipt;
+use alloy::signers::SignerSync;
+use alloy::signers::local::PrivateKeySigner;
+use alloy::sol_types::SolCall;
+use alloy::transports::mock::Asserter;
+use num_bigint::BigUint;
+use tycho_execution::encoding::errors::EncodingError;
+use tycho_execution::encoding::models::{
+    EncodedSolution, NativeAction, Solution, Transaction, UserTransferType,
+};
+use tycho_execution::encoding::tycho_encoder::TychoEncoder;
+use tycho_simulation::evm::protocol::u256_num::biguint_to_u256;
+use tycho_simulation::evm::protocol::uniswap_v2::state::UniswapV2State;
+use tycho_simulation::protocol::models::{ProtocolComponent, Update};
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::Chain;
+use tycho_simulation::tycho_common::models::token::Token;
+use tycho_simulation::tycho_core::simulation::protocol_sim::ProtocolSim;
+
+use crate::config::{AppConfig, ConfigSource, Slippage};
+use crate::encoding::{ApproveStrategy, ExecutionMode, ITychoRouter, RouterFunction};
+use crate::executor::Executor;
+use crate::pool_registry::PoolRegistry;
+use crate::receipts::ReceiptWatcher;
+use crate::signer::TradeSigner;
+use crate::stream_handler::SwapSettings;
+
+/// Executor contract of `swap_settings`.
+pub const EXECUTOR: Address = address!("0x00000000000000000000000000000000000e0e0e");
+/// Router every `encoded` solution interacts with.
+pub const ROUTER: Address = address!("0xfD0b31d2E955fA55e3fa641Fe90e08b677188d35");
+/// WETH on Ethereum, the wrapped native token of `swap_settings`.
+pub const WETH: Address = address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+
+/// `TychoEncoder` double for exercising the swap code without a router encoder. Every
+/// call pops the next programmed response and records the solutions it was given.
+#[derive(Debug)]
+pub struct MockEncoder {
+    responses: Mutex<VecDeque<Result<EncodedSolution, String>>>,
+    calls: Mutex<Vec<Solution>>,
+    transfer_from: bool,
+}
+
+impl Default for MockEncoder {
+    fn default() -> Self {
+        Self {
+            responses: Mutex::new(VecDeque::new()),
+            calls: Mutex::new(Vec::new()),
+            transfer_from: true,
+        }
+    }
+}
+
+impl MockEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `encoded` as the answer to the next call.
+    pub fn respond_with(self, encoded: EncodedSolution) -> Self {
+        self.responses.lock().unwrap().push_back(Ok(encoded));
+        self
+    }
+
+    /// Makes the next call fail with `message`.
+    pub fn fail_with(self, message: &str) -> Self {
+        self.responses.lock().unwrap().push_back(Err(message.to_string()));
+        self
+    }
+
+    /// Encodes router calls for `transfer_type`, `TransferFrom` by default. Only the
+    /// `transferFrom` flag depends on it.
+    pub fn for_transfer_type(self, transfer_type: &UserTransferType) -> Self {
+        Self {
+            transfer_from: *transfer_type == UserTransferType::TransferFrom,
+            ..self
+        }
+    }
+
+    /// Solutions passed to the encoder so far, oldest first.
+    pub fn calls(&self) -> Vec<Solution> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn next_response(&self, solutions: &[Solution]) -> Result<Vec<EncodedSolution>, EncodingError> {
+        self.calls.lock().unwrap().extend(solutions.iter().cloned());
+        let response = self.responses.lock().unwrap().pop_front().ok_or_else(|| {
+            EncodingError::FatalError("MockEncoder has no response programmed".to_string())
+        })?;
+        response
+            .map(|encoded| vec![encoded])
+            .map_err(EncodingError::FatalError)
+    }
+
+    /// Router calldata for `solution` the way the real encoder lays it out, with the
+    /// programmed `swaps` bytes in place of the packed swaps.
+    fn router_calldata(
+        &self,
+        encoded: &EncodedSolution,
+        solution: &Solution,
+    ) -> Result<Vec<u8>, EncodingError> {
+        let function: RouterFunction = encoded
+            .function_signature
+            .parse()
+            .map_err(|e| EncodingError::FatalError(format!("{e}")))?;
+        let amount_in = biguint_to_u256(&solution.given_amount);
+        let token_in = address_of(&solution.given_token)?;
+        let token_out = address_of(&solution.checked_token)?;
+        let min_amount_out = biguint_to_u256(&solution.checked_amount);
+        let wrap_eth = solution.native_action == Some(NativeAction::Wrap);
+        let unwrap_eth = solution.native_action == Some(NativeAction::Unwrap);
+        let receiver = address_of(&solution.receiver)?;
+        let swaps = encoded.swaps.clone().into();
+
+        let calldata = match function {
+            RouterFunction::SingleSwap => ITychoRouter::singleSwapCall {
+                amountIn: amount_in,
+                tokenIn: token_in,
+                tokenOut: token_out,
+                minAmountOut: min_amount_out,
+                wrapEth: wrap_eth,
+                unwrapEth: unwrap_eth,
+                receiver,
+                transferFrom: self.transfer_from,
+                swaps,
+            }
+            .abi_encode(),
+            RouterFunction::SequentialSwap => ITychoRouter::sequentialSwapCall {
+                amountIn: amount_in,
+                tokenIn: token_in,
+                tokenOut: token_out,
+                minAmountOut: min_amount_out,
+                wrapEth: wrap_eth,
+                unwrapEth: unwrap_eth,
+                receiver,
+                transferFrom: self.transfer_from,
+                swaps,
+            }
+            .abi_encode(),
+            RouterFunction::SplitSwap => ITychoRouter::splitSwapCall {
+                amountIn: amount_in,
+                tokenIn: token_in,
+                tokenOut: token_out,
+                minAmountOut: min_amount_out,
+                wrapEth: wrap_eth,
+                unwrapEth: unwrap_eth,
+                nTokens: U256::from(encoded.n_tokens),
+                receiver,
+                transferFrom: self.transfer_from,
+                swaps,
+            }
+            .abi_encode(),
+            permit2 => {
+                return Err(EncodingError::FatalError(format!(
+                    "{permit2:?} needs a signed permit, the real encoder can't produce it either"
+                )));
+            }
+        };
+        Ok(calldata)
+    }
+}
+
+impl TychoEncoder for MockEncoder {
+    fn encode_solutions(
+        &self,
+        solutions: Vec<Solution>,
+    ) -> Result<Vec<EncodedSolution>, EncodingError> {
+        self.next_response(&solutions)
+    }
+
+    fn encode_full_calldata(
+        &self,
+        solutions: Vec<Solution>,
+    ) -> Result<Vec<Transaction>, EncodingError> {
+        self.next_response(&solutions)?
+            .into_iter()
+            .zip(&solutions)
+            .map(|(encoded, solution)| {
+                Ok(Transaction {
+                    data: self.router_calldata(&encoded, solution)?,
+                    to: encoded.interacting_with,
+                    value: BigUint::ZERO,
+                })
+            })
+            .collect()
+    }
+
+    fn validate_solution(&self, _solution: &Solution) -> Result<(), EncodingError> {
+        Ok(())
+    }
+}
+
+fn address_of(bytes: &Bytes) -> Result<Address, EncodingError> {
+    Address::try_from(bytes.as_ref())
+        .map_err(|_| EncodingError::FatalError(format!("0x{} is not an address", hex::encode(bytes))))
+}
+
+/// An `EncodedSolution` for `function` on `ROUTER`, with recognisable `swaps` bytes.
+pub fn encoded(function: RouterFunction) -> EncodedSolution {
+    EncodedSolution {
+        swaps: vec![0xab; 4],
+        interacting_with: Bytes::from(ROUTER.as_slice()),
+        function_signature: function.signature(),
+        n_tokens: match function {
+            RouterFunction::SplitSwap | RouterFunction::SplitSwapPermit2 => 2,
+            _ => 0,
+        },
+        permit: None,
+    }
+}
+
+/// A token at address `0x{byte}{byte}..`, on Ethereum.
+pub fn token(byte: u8, symbol: &str, decimals: u32) -> Token {
+    Token::new(
+        &Bytes::from([byte; 20].as_slice()),
+        symbol,
+        decimals,
+        0,
+        &[],
+        Chain::Ethereum,
+        100,
+    )
+}
+
+pub fn component(id: &str, tokens: &[Token]) -> ProtocolComponent {
+    component_with_id(Bytes::from(id.as_bytes()), tokens)
+}
+
+/// A Uniswap V2 pool at address `0x{byte}{byte}..`, the id the router encoder's swap
+/// encoders expect.
+pub fn pool_component(byte: u8, tokens: &[Token]) -> ProtocolComponent {
+    component_with_id(Bytes::from([byte; 20].as_slice()), tokens)
+}
+
+fn component_with_id(id: Bytes, tokens: &[Token]) -> ProtocolComponent {
+    ProtocolComponent::new(
+        id,
+        "uniswap_v2".to_string(),
+        "uniswap_v2_pool".to_string(),
+        Chain::Ethereum,
+        tokens.to_vec(),
+        Vec::new(),
+        HashMap::new(),
+        Bytes::default(),
+        Default::default(),
+    )
+}
+
+/// Constant-product pool with Uniswap V2's 0.3% fee. `reserve0` belongs to whichever of
+/// the two tokens has the lower address.
+pub fn pool(reserve0: u128, reserve1: u128) -> UniswapV2State {
+    UniswapV2State::new(U256::from(reserve0), U256::from(reserve1))
+}
+
+/// A stream update announcing `pools` with their states at `block`.
+pub fn update(block: u64, pools: &[(&str, &[Token], UniswapV2State)]) -> Update {
+    let mut states: HashMap<String, Box<dyn ProtocolSim>> = HashMap::new();
+    let mut new_pairs = HashMap::new();
+    for (id, tokens, state) in pools {
+        states.insert(id.to_string(), Box::new(state.clone()));
+        new_pairs.insert(id.to_string(), component(id, tokens));
+    }
+    Update::new(block, states, new_pairs)
+}
+
+/// A registry tracking `pools`, as the stream would leave it after announcing them.
+pub(crate) fn registry(pools: &[(&str, &[Token], UniswapV2State)]) -> PoolRegistry {
+    let mut registry = PoolRegistry::default();
+    registry.apply(&update(1, pools));
+    registry
+}
+
+/// Config with throwaway values for the required keys, plus `extra`, later entries winning.
+pub fn config(extra: &[(&str, &str)]) -> AppConfig {
+    let mut pairs = vec![
+        ("TYCHO_API_KEY", "key"),
+        ("RPC_URL", "http://localhost:8545"),
+        ("EXECUTOR_CONTRACT", "0x00000000000000000000000000000000000e0e0e"),
+        (
+            "PRIVATE_KEY",
+            "0x1111111111111111111111111111111111111111111111111111111111111111",
+        ),
+    ];
+    pairs.extend_from_slice(extra);
+    AppConfig::from_source(&ConfigSource::from_pairs(&pairs)).expect("test config is valid")
+}
+
+/// An executor for `config` whose every RPC call is answered, in order, by `asserter`.
+pub fn executor(config: &AppConfig, asserter: Asserter) -> Executor<DynProvider> {
+    let provider = ProviderBuilder::new().connect_mocked_client(asserter).erased();
+    let submitter = config
+        .submission
+        .build(provider.clone(), EthereumWallet::from(config.signer.clone()), config.chain.id())
+        .expect("test submitter builds");
+    Executor::new(
+        provider.clone(),
+        submitter,
+        ReceiptWatcher::new(provider, config.watch),
+        config.replacement,
+        config.wallet_address.unwrap_or(config.signer.address()),
+        config.gas.clone(),
+        config.pending_tx_ttl,
+        config.nonce_resync_after,
+    )
+}
+
+/// A successful receipt for `tx_hash`, mined in `block` using 90k gas at 2 gwei.
+pub fn receipt(tx_hash: TxHash, block: u64) -> TransactionReceipt {
+    TransactionReceipt {
+        inner: ReceiptEnvelope::Eip1559(ReceiptWithBloom {
+            receipt: Receipt {
+                status: true.into(),
+                cumulative_gas_used: 90_000,
+                logs: Vec::new(),
+            },
+            logs_bloom: Bloom::default(),
+        }),
+        transaction_hash: tx_hash,
+        transaction_index: Some(0),
+        block_hash: Some(B256::repeat_byte(0xbb)),
+        block_number: Some(block),
+        gas_used: 90_000,
+        effective_gas_price: 2_000_000_000,
+        blob_gas_used: None,
+        blob_gas_price: None,
+        from: Address::repeat_byte(0x01),
+        to: Some(EXECUTOR),
+        contract_address: None,
+    }
+}
+
+/// Test mode, `TransferFrom`, exact approvals and 50 bps slippage, on Ethereum.
+pub fn swap_settings() -> SwapSettings {
+    SwapSettings {
+        slippage: Slippage::flat(50),
+        execution_mode: ExecutionMode::Test,
+        executor_contract: EXECUTOR,
+        wallet_address: None,
+        receiver: None,
+        user_transfer_type: UserTransferType::TransferFrom,
+        approve_strategy: ApproveStrategy::Exact,
+        chain_id: 1,
+        wrapped_native: WETH,
+    }
+}
+
+/// `TradeSigner` double backed by a fixed, publicly known key, so signatures are
+/// deterministic and recoverable. Records every hash it is asked to sign.
+#[derive(Debug)]
+pub struct MockSigner {
+    key: PrivateKeySigner,
+    fail: bool,
+    signed: Mutex<Vec<B256>>,
+}
+
+impl Default for MockSigner {
+    fn default() -> Self {
+        Self {
+            key: PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11))
+                .expect("0x11.. is a valid secp256k1 key"),
+            fail: false,
+            signed: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl MockSigner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A signer whose every `sign_hash` fails, as a locked hardware wallet would.
+    pub fn failing() -> Self {
+        Self {
+            fail: true,
+            ..Self::default()
+        }
+    }
+
+    /// Hashes passed to `sign_hash` so far, oldest first.
+    pub fn signed(&self) -> Vec<B256> {
+        self.signed.lock().unwrap().clone()
+    }
+}
+
+impl TradeSigner for MockSigner {
+    fn address(&self) -> Address {
+        self.key.address()
+    }
+
+    fn sign_hash(&self, hash: &B256) -> anyhow::Result<Signature> {
+        self.signed.lock().unwrap().push(*hash);
+        anyhow::ensure!(!self.fail, "MockSigner refuses to sign");
+        Ok(self.key.sign_hash_sync(hash)?)
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, B256, U256, address};
+use alloy::signers::local::PrivateKeySigner;
+use alloy::sol_types::SolCall;
+use num_bigint::BigUint;
+use tycho_execution::encoding::evm::encoder_builders::TychoRouterEncoderBuilder;
+use tycho_execution::encoding::models::UserTransferType;
+use tycho_simulation::evm::protocol::uniswap_v2::state::UniswapV2State;
+use tycho_simulation::protocol::models::ProtocolComponent;
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::Chain;
+use tycho_simulation::tycho_common::models::token::Token;
+
+use eulerswap::config::Slippage;
+use eulerswap::encoding::{
+    ApproveStrategy, ExecutionMode, ITychoRouter, RouterFunction, decode_multitrade_calldata,
+};
+use eulerswap::stream_handler::{SwapSettings, apply_slippage, process_multi_hop_swap, quote_hops};
+
+const EXECUTOR: Address = address!("0x00000000000000000000000000000000000e0e0e");
+const WETH: Address = address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+
+/// tests/fixtures/curve_3pool.toml
+fn fixture() -> toml::Table {
+    include_str!("fixtures/curve_3pool.toml").parse().unwrap()
+}
+
+fn strings<'a>(fixture: &'a toml::Table, key: &str) -> Vec<&'a str> {
+    fixture[key].as_array().unwrap().iter().map(|value| value.as_str().unwrap()).collect()
+}
+
+fn index(fixture: &toml::Table, key: &str) -> usize {
+    fixture[key].as_integer().unwrap() as usize
+}
+
+/// The pool's coins in their on-chain order.
+fn coins(fixture: &toml::Table) -> Vec<Token> {
+    let decimals = fixture["decimals"].as_array().unwrap();
+    strings(fixture, "coins")
+        .into_iter()
+        .zip(strings(fixture, "symbols"))
+        .zip(decimals)
+        .map(|((address, symbol), decimals)| {
+            let address: Bytes = address.parse().unwrap();
+            let decimals = decimals.as_integer().unwrap() as u32;
+            Token::new(&address, symbol, decimals, 0, &[], Chain::Ethereum, 100)
+        })
+        .collect()
+}
+
+fn curve_pool(fixture: &toml::Table, coins: &[Token]) -> ProtocolComponent {
+    let coins_json = format!("[\"{}\"]", strings(fixture, "coins").join("\",\""));
+    let factory: Bytes = fixture["factory"].as_str().unwrap().parse().unwrap();
+    ProtocolComponent::new(
+        fixture["pool"].as_str().unwrap().parse().unwrap(),
+        "vm:curve".to_string(),
+        "curve_pool".to_string(),
+        Chain::Ethereum,
+        coins.to_vec(),
+        Vec::new(),
+        HashMap::from([
+            ("coins".to_string(), Bytes::from(coins_json.into_bytes())),
+            ("factory".to_string(), factory),
+        ]),
+        Bytes::default(),
+        Default::default(),
+    )
+}
+
+#[test]
+fn a_curve_swap_between_non_adjacent_coins_names_their_indices() {
+    let fixture = fixture();
+    let coins = coins(&fixture);
+    let pool = curve_pool(&fixture, &coins);
+    let (sell, buy) = (&coins[index(&fixture, "sell")], &coins[index(&fixture, "buy")]);
+    let amount_in: BigUint = fixture["amount_in"].as_str().unwrap().parse().unwrap();
+    // Curve pools are simulated in an EVM, a 1:1 constant-product pool priced in the
+    // same tokens stands in for the quote. DAI sorts first, so it's reserve0.
+    let state = UniswapV2State::new(
+        U256::from(10u64).pow(U256::from(24)),
+        U256::from(10u64).pow(U256::from(12)),
+    );
+    let hops = vec![(pool.clone(), sell.clone(), buy.clone(), 1.0)];
+
+    let quote = quote_hops(&hops, &[&state], amount_in.clone()).unwrap();
+    assert_eq!(quote.hop_amounts_in, [amount_in.clone()]);
+    // About 1000 USDT in its 6 decimals
+    assert!(quote.amount_out > BigUint::from(990_000_000u32), "{}", quote.amount_out);
+    assert!(quote.amount_out < BigUint::from(1_000_000_000u32), "{}", quote.amount_out);
+
+    let signer = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11)).unwrap();
+    let settings = SwapSettings {
+        slippage: Slippage::flat(50),
+        execution_mode: ExecutionMode::Test,
+        executor_contract: EXECUTOR,
+        wallet_address: None,
+        receiver: None,
+        user_transfer_type: UserTransferType::TransferFrom,
+        approve_strategy: ApproveStrategy::Exact,
+        chain_id: 1,
+        wrapped_native: WETH,
+    };
+    let encoder = TychoRouterEncoderBuilder::new()
+        .chain(Chain::Ethereum)
+        .user_transfer_type(UserTransferType::TransferFrom)
+        .build()
+        .unwrap();
+
+    let tx = process_multi_hop_swap(
+        hops,
+        &[&state],
+        amount_in.clone(),
+        &signer,
+        &settings,
+        encoder.as_ref(),
+    )
+    .unwrap();
+
+    let call = decode_multitrade_calldata(tx.input.input().unwrap()).unwrap();
+    assert_eq!(call.token_address.as_slice(), sell.address.as_ref());
+    let router_call = &call.interactions.last().unwrap().callData;
+    assert_eq!(RouterFunction::from_calldata(router_call).unwrap(), RouterFunction::SingleSwap);
+    let swap = ITychoRouter::singleSwapCall::abi_decode(router_call).unwrap();
+    assert_eq!(swap.amountIn.to_string(), amount_in.to_string());
+    assert_eq!(swap.tokenOut.as_slice(), buy.address.as_ref());
+    assert_eq!(swap.minAmountOut.to_string(), apply_slippage(&quote.amount_out, 50).to_string());
+
+    // The curve executor data packs token in, token out and the pool, then the pool type
+    // and the coin indices i and j
+    let pool_address: Bytes = fixture["pool"].as_str().unwrap().parse().unwrap();
+    let parts: [&[u8]; 3] = [sell.address.as_ref(), buy.address.as_ref(), pool_address.as_ref()];
+    let header = parts.concat();
+    let start = swap
+        .swaps
+        .windows(header.len())
+        .position(|window| window == header.as_slice())
+        .unwrap_or_else(|| panic!("no curve swap for DAI -> USDT in {}", swap.swaps));
+    let indices = &swap.swaps[start + header.len() + 1..start + header.len() + 3];
+    assert_eq!(indices, [index(&fixture, "sell") as u8, index(&fixture, "buy") as u8]);
+    assert_eq!(indices, [0, 2]);
+}
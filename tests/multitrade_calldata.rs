@@ -0,0 +1,48 @@
+use alloy::primitives::{Address, U256, address};
+use alloy::sol_types::SolCall;
+
+use eulerswap::encoding::{
+    ApproveStrategy, ExecutionMode, IERC20, InteractionBatch, create_multitrade_calldata,
+    decode_multitrade_calldata,
+};
+
+const TOKEN: Address = address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+const ROUTER: Address = address!("0xfD0b31d2E955fA55e3fa641Fe90e08b677188d35");
+
+#[test]
+fn multitrade_calldata_round_trips_through_the_library() {
+    let amount = U256::from(1_000_000u64);
+    let swap_calldata = vec![0xde, 0xad, 0xbe, 0xef];
+    let calldata = create_multitrade_calldata(
+        TOKEN,
+        ROUTER,
+        Some((ROUTER, amount)),
+        ApproveStrategy::Reset,
+        swap_calldata.clone(),
+        U256::ZERO,
+        ExecutionMode::Test,
+    );
+
+    let call = decode_multitrade_calldata(&calldata).unwrap();
+    assert_eq!(call.token_address, TOKEN);
+    assert_eq!(call.mode, ExecutionMode::Test);
+    assert_eq!(call.interactions.len(), 3);
+    let approved: Vec<U256> = call.interactions[..2]
+        .iter()
+        .map(|interaction| {
+            assert_eq!(interaction.target, TOKEN);
+            IERC20::approveCall::abi_decode(&interaction.callData).unwrap().amount
+        })
+        .collect();
+    assert_eq!(approved, [U256::ZERO, amount]);
+    assert_eq!(call.interactions[2].target, ROUTER);
+    assert_eq!(call.interactions[2].callData.as_ref(), swap_calldata.as_slice());
+
+    // Dropping covered approvals leaves only the router call
+    let trimmed = InteractionBatch::from(call)
+        .without_approvals(TOKEN, ROUTER)
+        .build(TOKEN, ExecutionMode::Test);
+    let trimmed = decode_multitrade_calldata(&trimmed).unwrap();
+    assert_eq!(trimmed.interactions.len(), 1);
+    assert_eq!(trimmed.interactions[0].target, ROUTER);
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, B256, U256, address};
+use alloy::signers::local::PrivateKeySigner;
+use alloy::sol_types::SolCall;
+use num_bigint::BigUint;
+use tycho_execution::encoding::evm::encoder_builders::TychoRouterEncoderBuilder;
+use tycho_execution::encoding::models::UserTransferType;
+use tycho_simulation::protocol::models::ProtocolComponent;
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::Chain;
+use tycho_simulation::tycho_common::models::token::Token;
+
+use eulerswap::config::Slippage;
+use eulerswap::encoding::{
+    ApproveStrategy, ExecutionMode, ITychoRouter, decode_multitrade_calldata, encode_input,
+};
+use eulerswap::stream_handler::{SwapSettings, TradeMode, process_swap};
+
+const EXECUTOR: Address = address!("0x00000000000000000000000000000000000e0e0e");
+const WETH: Address = address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+
+fn token(byte: u8, symbol: &str, decimals: u32) -> Token {
+    Token::new(
+        &Bytes::from([byte; 20].as_slice()),
+        symbol,
+        decimals,
+        0,
+        &[],
+        Chain::Ethereum,
+        100,
+    )
+}
+
+fn uniswap_v2_pool(byte: u8, tokens: &[Token]) -> ProtocolComponent {
+    ProtocolComponent::new(
+        Bytes::from([byte; 20].as_slice()),
+        "uniswap_v2".to_string(),
+        "uniswap_v2_pool".to_string(),
+        Chain::Ethereum,
+        tokens.to_vec(),
+        Vec::new(),
+        HashMap::new(),
+        Bytes::default(),
+        Default::default(),
+    )
+}
+
+#[test]
+fn process_swap_approves_what_encode_input_builds_and_calls_the_router() {
+    let wbtc = token(0x22, "WBTC", 8);
+    let usdc = token(0xa0, "USDC", 6);
+    let signer = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11)).unwrap();
+    let settings = SwapSettings {
+        slippage: Slippage::flat(50),
+        execution_mode: ExecutionMode::Test,
+        executor_contract: EXECUTOR,
+        wallet_address: None,
+        receiver: None,
+        user_transfer_type: UserTransferType::TransferFrom,
+        approve_strategy: ApproveStrategy::Exact,
+        chain_id: 1,
+        wrapped_native: WETH,
+    };
+    let encoder = TychoRouterEncoderBuilder::new()
+        .chain(Chain::Ethereum)
+        .user_transfer_type(UserTransferType::TransferFrom)
+        .build()
+        .unwrap();
+
+    let tx = process_swap(
+        &uniswap_v2_pool(0xb1, &[wbtc.clone(), usdc.clone()]),
+        &wbtc,
+        &usdc,
+        BigUint::from(1_000_000u32),
+        BigUint::from(2_000_000u32),
+        TradeMode::ExactIn,
+        &signer,
+        &settings,
+        encoder.as_ref(),
+    )
+    .unwrap();
+
+    assert_eq!(tx.to, Some(EXECUTOR.into()));
+    let call = decode_multitrade_calldata(tx.input.input().unwrap()).unwrap();
+    assert_eq!(call.token_address.as_slice(), wbtc.address.as_ref());
+    assert_eq!(call.mode, ExecutionMode::Test);
+    let [approve, swap] = &call.interactions[..] else {
+        panic!("expected an approval and the router call, got {:?}", call.interactions);
+    };
+
+    // The router is the spender, approved exactly the amount sold
+    let router = swap.target;
+    let expected = encode_input("approve(address,uint256)", &(router, U256::from(1_000_000u32)))
+        .unwrap();
+    assert_eq!(approve.target, call.token_address);
+    assert_eq!(approve.callData.as_ref(), expected.as_slice());
+
+    let swap = ITychoRouter::singleSwapCall::abi_decode(&swap.callData).unwrap();
+    assert_eq!(swap.amountIn, U256::from(1_000_000u32));
+    assert_eq!(swap.tokenOut.as_slice(), usdc.address.as_ref());
+    // 50 bps below the quote
+    assert_eq!(swap.minAmountOut, U256::from(1_990_000u32));
+    assert_eq!(swap.receiver, signer.address());
+    assert!(swap.transferFrom);
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, B256, U256, address};
+use alloy::signers::local::PrivateKeySigner;
+use alloy::sol_types::SolCall;
+use num_bigint::BigUint;
+use tycho_execution::encoding::evm::encoder_builders::TychoRouterEncoderBuilder;
+use tycho_execution::encoding::models::UserTransferType;
+use tycho_simulation::evm::protocol::uniswap_v2::state::UniswapV2State;
+use tycho_simulation::protocol::models::ProtocolComponent;
+use tycho_simulation::tycho_common::hex_bytes::Bytes;
+use tycho_simulation::tycho_common::models::Chain;
+use tycho_simulation::tycho_common::models::token::Token;
+use tycho_simulation::tycho_core::simulation::protocol_sim::ProtocolSim;
+
+use eulerswap::config::Slippage;
+use eulerswap::encoding::{
+    ApproveStrategy, ExecutionMode, ITychoRouter, RouterFunction, decode_multitrade_calldata,
+};
+use eulerswap::stream_handler::{SwapSettings, TradeMode, process_swap};
+
+const EXECUTOR: Address = address!("0x00000000000000000000000000000000000e0e0e");
+const WETH: Address = address!("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+
+/// tests/fixtures/uniswap_v2_usdc_weth.toml
+struct Fixture(toml::Table);
+
+impl Fixture {
+    fn load() -> Self {
+        Self(include_str!("fixtures/uniswap_v2_usdc_weth.toml").parse().unwrap())
+    }
+
+    fn str(&self, key: &str) -> &str {
+        self.0[key].as_str().unwrap()
+    }
+
+    fn bytes(&self, key: &str) -> Bytes {
+        self.str(key).parse().unwrap()
+    }
+
+    fn amount(&self, key: &str) -> BigUint {
+        self.str(key).parse().unwrap()
+    }
+}
+
+fn token(address: Bytes, symbol: &str, decimals: u32) -> Token {
+    Token::new(&address, symbol, decimals, 0, &[], Chain::Ethereum, 100)
+}
+
+#[test]
+fn a_uniswap_v2_swap_calls_single_swap_with_the_slipped_quote() {
+    let fixture = Fixture::load();
+    let usdc = token(fixture.bytes("token0"), "USDC", 6);
+    let weth = token(fixture.bytes("token1"), "WETH", 18);
+    let pool = ProtocolComponent::new(
+        fixture.bytes("pool"),
+        "uniswap_v2".to_string(),
+        "uniswap_v2_pool".to_string(),
+        Chain::Ethereum,
+        vec![usdc.clone(), weth.clone()],
+        Vec::new(),
+        HashMap::new(),
+        Bytes::default(),
+        Default::default(),
+    );
+    let state = UniswapV2State::new(
+        U256::from_str_radix(fixture.str("reserve0"), 10).unwrap(),
+        U256::from_str_radix(fixture.str("reserve1"), 10).unwrap(),
+    );
+    let amount_in = fixture.amount("amount_in");
+
+    let quote = state.get_amount_out(amount_in.clone(), &usdc, &weth).unwrap();
+    assert_eq!(quote.amount, fixture.amount("amount_out"));
+
+    let signer = PrivateKeySigner::from_bytes(&B256::repeat_byte(0x11)).unwrap();
+    let slippage_bps = fixture.0["slippage_bps"].as_integer().unwrap() as u32;
+    let settings = SwapSettings {
+        slippage: Slippage::flat(slippage_bps),
+        execution_mode: ExecutionMode::Test,
+        executor_contract: EXECUTOR,
+        wallet_address: None,
+        receiver: None,
+        user_transfer_type: UserTransferType::TransferFrom,
+        approve_strategy: ApproveStrategy::Exact,
+        chain_id: 1,
+        wrapped_native: WETH,
+    };
+    let encoder = TychoRouterEncoderBuilder::new()
+        .chain(Chain::Ethereum)
+        .user_transfer_type(UserTransferType::TransferFrom)
+        .build()
+        .unwrap();
+
+    let tx = process_swap(
+        &pool,
+        &usdc,
+        &weth,
+        amount_in.clone(),
+        quote.amount,
+        TradeMode::ExactIn,
+        &signer,
+        &settings,
+        encoder.as_ref(),
+    )
+    .unwrap();
+
+    let call = decode_multitrade_calldata(tx.input.input().unwrap()).unwrap();
+    assert_eq!(call.token_address.as_slice(), usdc.address.as_ref());
+    let router_call = &call.interactions.last().unwrap().callData;
+    assert_eq!(RouterFunction::from_calldata(router_call).unwrap(), RouterFunction::SingleSwap);
+
+    let swap = ITychoRouter::singleSwapCall::abi_decode(router_call).unwrap();
+    assert_eq!(swap.amountIn.to_string(), amount_in.to_string());
+    assert_eq!(swap.tokenIn.as_slice(), usdc.address.as_ref());
+    assert_eq!(swap.tokenOut.as_slice(), weth.address.as_ref());
+    assert_eq!(swap.minAmountOut.to_string(), fixture.str("min_amount_out"));
+    // The encoded hop targets the fixture's pair
+    let pair = fixture.bytes("pool");
+    let pair: &[u8] = pair.as_ref();
+    assert!(
+        swap.swaps.windows(pair.len()).any(|window| window == pair),
+        "swap data doesn't name the pair: {}",
+        swap.swaps
+    );
+}